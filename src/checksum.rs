@@ -0,0 +1,109 @@
+/// Incrementally update an Internet checksum (RFC 1071) after the checksummed data `old` is
+/// overwritten with `new`, using RFC 1624 eqn. 3 rather than summing the whole packet again.
+///
+/// `old` and `new` must be the same length and start at an even offset into the checksummed
+/// data; an odd trailing byte is treated as the high byte of a zero-padded word.
+pub fn update(checksum: u16, old: &[u8], new: &[u8]) -> u16 {
+    debug_assert_eq!(old.len(), new.len());
+    let mut sum = u32::from(!checksum);
+    for (old, new) in old.chunks(2).zip(new.chunks(2)) {
+        sum += u32::from(!word(old)) + u32::from(word(new));
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn word(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes.get(1).copied().unwrap_or(0)])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    /// The checksum of `data` summed in full.
+    fn full(data: &[u8]) -> u16 {
+        let mut sum: u32 = data.chunks(2).map(|w| u32::from(word(w))).sum();
+        while sum > 0xffff {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        !(sum as u16)
+    }
+
+    /// Overwrite `data` at `offset` with `new`, updating `checksum` incrementally, and check the
+    /// result against the checksum of the whole of `data`.
+    fn overwrite(data: &mut [u8], checksum: u16, offset: usize, new: &[u8]) -> u16 {
+        let range = offset..offset + new.len();
+        let updated = update(checksum, &data[range.clone()], new);
+        data[range].copy_from_slice(new);
+        assert_eq!(updated, full(data), "after writing {new:02x?} at {offset}");
+        updated
+    }
+
+    /// An IPv4 header with its checksum field zeroed, as it's excluded from the sum.
+    fn ipv4_header() -> Vec<u8> {
+        let mut header = vec![
+            0x45, 0x00, 0x00, 0x54, 0x00, 0x00, 0x40, 0x00, 0x65, 0x01, 0x00, 0x00,
+        ];
+        header.extend_from_slice(&[192, 0, 2, 100]);
+        header.extend_from_slice(&[0, 0, 0, 0]);
+        header
+    }
+
+    #[test]
+    fn matches_full_recompute_across_destinations() {
+        let mut header = ipv4_header();
+        let mut checksum = full(&header);
+        let mut edges = vec![
+            Ipv4Addr::new(0, 0, 0, 0),
+            Ipv4Addr::new(255, 255, 255, 255),
+            Ipv4Addr::new(0, 0, 255, 255),
+            Ipv4Addr::new(255, 255, 0, 0),
+            Ipv4Addr::new(0, 0, 0, 0),
+        ];
+        // a pseudo-random walk over the rest of the address space
+        let mut state = 0x2545_f491_u32;
+        edges.extend((0..10_000).map(|_| {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            Ipv4Addr::from(state)
+        }));
+        for addr in edges {
+            checksum = overwrite(&mut header, checksum, 16, &addr.octets());
+        }
+    }
+
+    #[test]
+    fn matches_full_recompute_across_sequence_numbers() {
+        // an echo request header and nonce, with its checksum field zeroed
+        let mut message = vec![8, 0, 0, 0, 0x12, 0x34, 0, 0, 0xde, 0xad, 0xbe, 0xef];
+        let mut checksum = full(&message);
+        let mut seen_zero = false;
+        for seq in (0..=u16::MAX).chain([0xffff, 0x0000, 0xffff]) {
+            checksum = overwrite(&mut message, checksum, 6, &seq.to_be_bytes());
+            seen_zero |= checksum == 0x0000;
+        }
+        // somewhere along the way the sum came to 0xffff, the one's complement of which is zero
+        assert!(seen_zero);
+    }
+
+    #[test]
+    fn updates_from_checksum_of_all_ones() {
+        // data summing to zero has a checksum of 0xffff
+        let mut data = vec![0u8; 4];
+        assert_eq!(full(&data), 0xffff);
+        let checksum = overwrite(&mut data, 0xffff, 2, &[0xff, 0xff]);
+        overwrite(&mut data, checksum, 0, &[0x12, 0x34]);
+    }
+
+    #[test]
+    fn odd_trailing_byte_is_padded() {
+        let mut data = vec![0x45, 0x00, 0x12];
+        let checksum = full(&data);
+        let checksum = overwrite(&mut data, checksum, 2, &[0xff]);
+        overwrite(&mut data, checksum, 0, &[0x46, 0x01, 0x00]);
+    }
+}
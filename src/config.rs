@@ -0,0 +1,98 @@
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::error::{Error, Result};
+use crate::output::OutputFormat;
+use crate::summary::FailScope;
+use crate::target::{Target, MAX_DSCP, MIN_PAYLOAD_SIZE};
+
+/// A run described in a TOML file: defaults for command line options, which take precedence over
+/// them, and targets probed in addition to any given on the command line.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub defaults: Defaults,
+
+    #[serde(default)]
+    pub targets: Vec<Target>,
+}
+
+/// Defaults for the command line options of the same names.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Defaults {
+    pub icmp_timeout: Option<u64>,
+    pub default_count: Option<u64>,
+    #[serde(default, deserialize_with = "crate::duration::deserialize_option")]
+    pub default_interval: Option<Duration>,
+    pub interface: Option<String>,
+    pub output: Option<OutputFormat>,
+    pub max_pps: Option<u32>,
+    pub dscp: Option<u8>,
+    pub payload_size: Option<u16>,
+    pub dont_fragment: Option<bool>,
+    pub warmup: Option<u16>,
+    pub concurrent_probes: Option<usize>,
+    pub fail_on_loss: Option<f64>,
+    #[serde(default, deserialize_with = "crate::duration::deserialize_option")]
+    pub rtt_warn: Option<Duration>,
+    pub fail_on_rtt_warn: Option<f64>,
+    pub fail_scope: Option<FailScope>,
+    pub source_ip: Option<Ipv4Addr>,
+}
+
+impl Config {
+    /// Read and validate the config file at `path`. Targets are validated along with those given
+    /// on the command line.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        let config: Self = toml::from_str(&contents).map_err(|e| {
+            Error::GenericStringError(format!("invalid config file {}: {e}", path.display()))
+        })?;
+        config.defaults.validate().map_err(|e| {
+            Error::GenericStringError(format!("invalid config file {}: {e}", path.display()))
+        })?;
+        Ok(config)
+    }
+}
+
+impl Defaults {
+    /// Apply the bounds enforced on the corresponding command line options.
+    fn validate(&self) -> std::result::Result<(), String> {
+        if let Some(dscp) = self.dscp {
+            if dscp > MAX_DSCP {
+                return Err(format!("dscp {dscp} exceeds the maximum of {MAX_DSCP}"));
+            }
+        }
+        if let Some(payload_size) = self.payload_size {
+            if payload_size < MIN_PAYLOAD_SIZE {
+                return Err(format!(
+                    "payload_size {payload_size} is below the minimum of {MIN_PAYLOAD_SIZE}"
+                ));
+            }
+        }
+        if self.max_pps == Some(0) {
+            return Err("max_pps must be at least 1".to_string());
+        }
+        if self.concurrent_probes == Some(0) {
+            return Err("concurrent_probes must be at least 1".to_string());
+        }
+        if let Some(percent) = self.fail_on_loss {
+            if !(0.0..=100.0).contains(&percent) {
+                return Err(format!("fail_on_loss {percent} is not between 0 and 100"));
+            }
+        }
+        if let Some(percent) = self.fail_on_rtt_warn {
+            if !(0.0..=100.0).contains(&percent) {
+                return Err(format!(
+                    "fail_on_rtt_warn {percent} is not between 0 and 100"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
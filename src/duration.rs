@@ -0,0 +1,40 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer};
+
+/// Parse a duration such as `250us`, `10ms` or `1.5s`. Values without a unit suffix are
+/// interpreted as milliseconds for backwards compatibility.
+pub fn parse_duration(s: &str) -> std::result::Result<Duration, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value: f64 = value
+        .parse()
+        .map_err(|e| format!("invalid duration {s:?}: {e}"))?;
+    let secs = match unit.trim() {
+        "us" | "µs" => value / 1_000_000.0,
+        "" | "ms" => value / 1_000.0,
+        "s" => value,
+        unit => {
+            return Err(format!(
+                "invalid duration {s:?}: unknown unit {unit:?} (expected us, ms or s)"
+            ))
+        }
+    };
+    Duration::try_from_secs_f64(secs).map_err(|e| format!("invalid duration {s:?}: {e}"))
+}
+
+/// Deserialize an optional `Duration` using the same syntax as `parse_duration`. An empty value,
+/// eg an empty column, yields `None`.
+pub fn deserialize_option<'de, D: Deserializer<'de>>(
+    d: D,
+) -> std::result::Result<Option<Duration>, D::Error> {
+    match Option::<String>::deserialize(d)?.as_deref().map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(s) => parse_duration(s)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+    }
+}
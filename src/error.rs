@@ -1,7 +1,7 @@
-
 pub(crate) type Result<T> = std::result::Result<T, Error>;
 
 #[derive(thiserror::Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     #[error("{0}")]
     GenericStringError(String),
@@ -12,6 +12,9 @@ pub enum Error {
     #[error("{0:?}")]
     CsvError(#[from] csv::Error),
 
+    #[error("{0:?}")]
+    JsonError(#[from] serde_json::Error),
+
     #[error("{0:?}")]
     RtnetlinkError(#[from] rtnetlink::Error),
 
@@ -20,4 +23,7 @@ pub enum Error {
 
     #[error("failed to send output on output handler channel")]
     OutputHandlerChannelClosed,
+
+    #[error("output sink lock poisoned by a panicked writer")]
+    OutputSinkPoisoned,
 }
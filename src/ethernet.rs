@@ -1,22 +1,73 @@
+use std::fmt;
 use std::net::Ipv4Addr;
+use std::str::FromStr;
+use std::sync::Arc;
 
 use futures::stream::TryStreamExt;
 use netlink_packet_route::rtnl::{address, constants as nlconsts, link, neighbour};
 use netlink_packet_route::LinkMessage;
-use pnet::packet::ethernet::{EtherTypes, Ethernet};
+use pnet::packet::ethernet::{EtherType, EtherTypes, Ethernet, MutableEthernetPacket};
 use pnet::util::MacAddr;
-use rtnetlink::{new_connection, Handle, IpVersion};
+use rtnetlink::{new_connection, Handle, IpVersion as NetlinkIpVersion};
 
 use crate::error::{Error, Result};
+use crate::neighbor::NeighborCache;
+
+const ETHERNET_HEADER_SIZE: usize = MutableEthernetPacket::minimum_packet_size();
 
 /// Information about the interface on which we will emit packets and listen for responses.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct InterfaceInfo {
     name: String,
     pub index: u32,
     //address: [u8; 4],
+    /// The address used as the source of emitted packets, unless one of the interface's other
+    /// addresses is in the target's subnet.
     pub address: Ipv4Addr,
+    /// All IPv4 addresses assigned to the interface.
+    pub addresses: Vec<Ipv4Addr>,
+    /// Broadcast addresses of the subnets attached to the interface.
+    pub broadcasts: Vec<Ipv4Addr>,
+    /// Subnets attached to the interface, as addresses and prefix lengths.
+    networks: Vec<(Ipv4Addr, u8)>,
     mac_addr: MacAddr,
+    pub link_type: LinkType,
+
+    /// Whether the interface is administratively up with a carrier.
+    pub up: bool,
+
+    /// Largest IP packet the interface can send, if known.
+    pub mtu: Option<u32>,
+}
+
+/// How packets are framed on an interface's link.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LinkType {
+    /// Packets are carried in Ethernet frames addressed to the MAC address of the next hop.
+    Ethernet,
+    /// The loopback interface. Packets start at the IP header and are sent through the kernel's
+    /// IP stack, since it discards loopback-addressed packets injected at the link layer.
+    Loopback,
+    /// Packets start at the IP header, as on point-to-point interfaces such as tun devices or
+    /// WireGuard tunnels.
+    RawIp,
+}
+
+impl LinkType {
+    fn from_arphrd(link_layer_type: u16) -> Self {
+        match link_layer_type {
+            nlconsts::ARPHRD_LOOPBACK => LinkType::Loopback,
+            nlconsts::ARPHRD_NONE
+            | nlconsts::ARPHRD_PPP
+            | nlconsts::ARPHRD_TUNNEL
+            | nlconsts::ARPHRD_IPGRE => LinkType::RawIp,
+            nlconsts::ARPHRD_ETHER => LinkType::Ethernet,
+            other => {
+                tracing::debug!("assuming Ethernet framing for link layer type {other}");
+                LinkType::Ethernet
+            }
+        }
+    }
 }
 
 impl TryFrom<LinkMessage> for InterfaceInfo {
@@ -24,41 +75,77 @@ impl TryFrom<LinkMessage> for InterfaceInfo {
 
     fn try_from(lm: LinkMessage) -> Result<InterfaceInfo> {
         let index = lm.header.index;
-
-        let name = lm
-            .nlas
-            .iter()
-            .find_map(|nla| match nla {
-                link::nlas::Nla::IfName(name) => Some(name.clone()),
-                _ => None,
-            })
-            .ok_or(Error::GenericStringError(format!(
-                "couldn't find interface name for {index}"
-            )))?;
-
-        let mac_addr = lm
-            .nlas
-            .iter()
-            .find_map(|nla| match nla {
-                link::nlas::Nla::PermAddress(v) if v.len() == 6 => {
-                    Some(MacAddr(v[0], v[1], v[2], v[3], v[4], v[5]))
-                }
-                _ => None,
-            })
-            .ok_or(Error::GenericStringError(format!(
-                "couldn't find MAC address for interface {name} (idx: {index})"
-            )))?;
+        let link_type = LinkType::from_arphrd(lm.header.link_layer_type);
+        let running = (libc::IFF_UP | libc::IFF_RUNNING) as u32;
+        let up = lm.header.flags & running == running;
+
+        let name = link_name(&lm).ok_or(Error::GenericStringError(format!(
+            "couldn't find interface name for {index}"
+        )))?;
+
+        let mtu = lm.nlas.iter().find_map(|nla| match nla {
+            link::nlas::Nla::Mtu(mtu) => Some(*mtu),
+            _ => None,
+        });
+
+        let mac_addr = match link_type {
+            // frames are never built for these, so there's no need for a MAC address
+            LinkType::Loopback | LinkType::RawIp => MacAddr::zero(),
+            // virtual interfaces, eg veth, have no permanent address, only the current one
+            LinkType::Ethernet => link_perm_address(&lm).or_else(|| link_address(&lm)).ok_or(
+                Error::GenericStringError(format!(
+                    "couldn't find MAC address for interface {name} (idx: {index})"
+                )),
+            )?,
+        };
 
         Ok(InterfaceInfo {
             name,
             index,
             address: Ipv4Addr::new(0u8, 0, 0, 0),
+            addresses: Vec::new(),
+            broadcasts: Vec::new(),
+            networks: Vec::new(),
             mac_addr,
+            link_type,
+            up,
+            mtu,
         })
     }
 }
 
+fn link_name(lm: &LinkMessage) -> Option<String> {
+    lm.nlas.iter().find_map(|nla| match nla {
+        link::nlas::Nla::IfName(name) => Some(name.clone()),
+        _ => None,
+    })
+}
+
+/// The link's permanent MAC address, if it has one.
+fn link_perm_address(lm: &LinkMessage) -> Option<MacAddr> {
+    lm.nlas.iter().find_map(|nla| match nla {
+        link::nlas::Nla::PermAddress(v) if v.len() == 6 => {
+            Some(MacAddr(v[0], v[1], v[2], v[3], v[4], v[5]))
+        }
+        _ => None,
+    })
+}
+
+/// The link's current MAC address.
+fn link_address(lm: &LinkMessage) -> Option<MacAddr> {
+    lm.nlas.iter().find_map(|nla| match nla {
+        link::nlas::Nla::Address(v) if v.len() == 6 => {
+            Some(MacAddr(v[0], v[1], v[2], v[3], v[4], v[5]))
+        }
+        _ => None,
+    })
+}
+
 impl InterfaceInfo {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     async fn retrieve_address(&mut self, handle: Handle) -> Result<()> {
         let mut addresses = handle
             .address()
@@ -66,42 +153,339 @@ impl InterfaceInfo {
             .set_link_index_filter(self.index)
             .execute();
 
-        let notfounderr = Error::GenericStringError(format!(
-            "unable to retrieve address for interface {} (idx {})",
-            self.name, self.index
-        ));
-
         while let Some(msg) = addresses.try_next().await? {
             if msg.header.family as u16 != nlconsts::AF_INET {
                 continue;
             }
 
-            self.address = msg
-                .nlas
-                .iter()
-                .find_map(|nla| match nla {
-                    address::nlas::Nla::Address(v) if v.len() == 4 => {
-                        Some(Ipv4Addr::new(v[0], v[1], v[2], v[3]))
-                    }
-                    _ => None,
-                })
-                .ok_or(notfounderr)?;
-            return Ok(());
+            if let Some(address) = msg.nlas.iter().find_map(|nla| match nla {
+                address::nlas::Nla::Address(v) if v.len() == 4 => {
+                    Some(Ipv4Addr::new(v[0], v[1], v[2], v[3]))
+                }
+                _ => None,
+            }) {
+                self.addresses.push(address);
+                self.networks.push((address, msg.header.prefix_len));
+            }
+            if let Some(broadcast) = msg.nlas.iter().find_map(|nla| match nla {
+                address::nlas::Nla::Broadcast(v) if v.len() == 4 => {
+                    Some(Ipv4Addr::new(v[0], v[1], v[2], v[3]))
+                }
+                _ => None,
+            }) {
+                self.broadcasts.push(broadcast);
+            }
+        }
+
+        self.address = *self
+            .addresses
+            .first()
+            .ok_or(Error::GenericStringError(format!(
+                "unable to retrieve address for interface {} (idx {})",
+                self.name, self.index
+            )))?;
+        Ok(())
+    }
+}
+
+/// How an interface is selected: by name, by index, or by one of its IPv4 addresses, so that
+/// scripts needn't know interface names, which vary across hosts.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum InterfaceSpec {
+    Name(String),
+    Index(u32),
+    Address(Ipv4Addr),
+}
+
+impl FromStr for InterfaceSpec {
+    type Err = Error;
+
+    /// Parse an IPv4 address or a number as an address or index; anything else is a name.
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(Error::GenericStringError(
+                "interface must not be empty".to_string(),
+            ));
+        }
+        if let Ok(address) = s.parse::<Ipv4Addr>() {
+            Ok(InterfaceSpec::Address(address))
+        } else if let Ok(index) = s.parse::<u32>() {
+            Ok(InterfaceSpec::Index(index))
+        } else {
+            Ok(InterfaceSpec::Name(s.to_string()))
         }
+    }
+}
+
+impl fmt::Display for InterfaceSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterfaceSpec::Name(name) => write!(f, "{name}"),
+            InterfaceSpec::Index(index) => write!(f, "index {index}"),
+            InterfaceSpec::Address(address) => write!(f, "address {address}"),
+        }
+    }
+}
 
-        Err(notfounderr)
+impl InterfaceSpec {
+    /// Look up the name of the selected interface. Fails if there's no such interface, or if the
+    /// address is assigned to more than one interface.
+    pub async fn name(&self) -> Result<String> {
+        let index = match self {
+            InterfaceSpec::Name(name) => return Ok(name.clone()),
+            InterfaceSpec::Index(index) => *index,
+            InterfaceSpec::Address(address) => {
+                let (connection, handle, _) = new_connection()?;
+                tokio::spawn(connection);
+                get_interface_index_by_address(handle, *address).await?
+            }
+        };
+        let (connection, handle, _) = new_connection()?;
+        tokio::spawn(connection);
+        let mut links = handle.link().get().match_index(index).execute();
+        match links.try_next().await {
+            Ok(Some(link)) => link_name(&link).ok_or(Error::GenericStringError(format!(
+                "couldn't find interface name for {index}"
+            ))),
+            // the kernel answers a lookup of a missing index with ENODEV
+            Ok(None) | Err(_) => Err(Error::GenericStringError(format!(
+                "couldn't find interface with index {index}"
+            ))),
+        }
     }
 }
 
+/// The version of IP carried in emitted Ethernet frames.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IpVersion {
+    V4,
+    #[allow(dead_code)]
+    V6,
+}
+
 /// Contains information retrieved from the local network stack necessary to construct Ethernet
 /// packets.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct EthernetConf {
     pub ethernet_info: Ethernet,
     pub interface: InterfaceInfo,
+
+    /// Ethertype to use in place of the one matching the IP version of the target, for
+    /// experimentation.
+    ethertype: Option<EtherType>,
+
+    /// MAC addresses of on-link targets, for interfaces with Ethernet framing.
+    neighbors: Option<Arc<NeighborCache>>,
+
+    /// Whether send sockets are bound to the interface with SO_BINDTODEVICE.
+    bind_device: bool,
+
+    /// Whether requests are sent on raw IPv4 sockets, which take packets starting at the IP
+    /// header whatever the link's framing.
+    inet_raw: bool,
+
+    /// Whether requests are sent on ICMP datagram ("ping") sockets, which take only the ICMP
+    /// message of each packet.
+    ping: bool,
+
+    /// Whether the source address was chosen with `set_source_address`, in which case it's used
+    /// for every target.
+    source_pinned: bool,
+
+    /// Source MAC address to use in place of the interface's, for testing.
+    source_mac: Option<MacAddr>,
 }
 
 impl EthernetConf {
+    /// Length of the link-layer header at the start of each frame we build for this interface.
+    pub fn link_header_len(&self) -> usize {
+        if self.inet_raw || self.ping {
+            return 0;
+        }
+        match self.interface.link_type {
+            LinkType::Ethernet => ETHERNET_HEADER_SIZE,
+            LinkType::Loopback | LinkType::RawIp => 0,
+        }
+    }
+
+    /// The ethertype of frames carrying packets of the given IP version, unless overridden with
+    /// `set_ethertype`.
+    pub fn ethertype(&self, version: IpVersion) -> EtherType {
+        self.ethertype.unwrap_or(match version {
+            IpVersion::V4 => EtherTypes::Ipv4,
+            IpVersion::V6 => EtherTypes::Ipv6,
+        })
+    }
+
+    /// Override the ethertype of emitted frames. Values below 0x0600 denote an 802.3 payload
+    /// length rather than an ethertype, and VLAN tags would change the frame layout, so neither
+    /// is accepted.
+    pub fn set_ethertype(&mut self, ethertype: EtherType) -> Result<()> {
+        if ethertype.0 < 0x0600 {
+            return Err(Error::GenericStringError(format!(
+                "ethertype {:#06x} is below the minimum of 0x0600",
+                ethertype.0
+            )));
+        }
+        if [EtherTypes::Vlan, EtherTypes::PBridge, EtherTypes::QinQ].contains(&ethertype) {
+            return Err(Error::GenericStringError(format!(
+                "ethertype {:#06x} is a VLAN tag, which isn't supported",
+                ethertype.0
+            )));
+        }
+        if ![EtherTypes::Ipv4, EtherTypes::Ipv6].contains(&ethertype) {
+            tracing::warn!(
+                "ethertype {:#06x} isn't IPv4 or IPv6; targets are unlikely to reply",
+                ethertype.0
+            );
+        }
+        self.ethertype = Some(ethertype);
+        Ok(())
+    }
+
+    /// Bind send sockets to the interface with SO_BINDTODEVICE, so that probes can't leave by
+    /// another interface whatever the routing table says. Enabled by default.
+    pub fn set_bind_device(&mut self, bind_device: bool) {
+        self.bind_device = bind_device;
+    }
+
+    pub fn binds_device(&self) -> bool {
+        self.bind_device
+    }
+
+    /// Override the source MAC address of emitted frames, eg to test MAC learning on a switch.
+    /// Frames are only built for interfaces with Ethernet framing, and not at all with raw IPv4
+    /// sockets, so it has no effect otherwise.
+    pub fn set_source_mac(&mut self, mac: MacAddr) {
+        if self.link_header_len() == 0 {
+            tracing::warn!(
+                "source MAC has no effect on {}, which is sent packets without an Ethernet header",
+                self.interface.name
+            );
+        }
+        if mac.0 & 1 != 0 {
+            tracing::warn!("source MAC {mac} is a multicast address; switches may drop the frames");
+        }
+        self.ethernet_info.source = mac;
+        self.source_mac = Some(mac);
+    }
+
+    /// Send requests on raw IPv4 sockets rather than packet sockets, leaving the link-layer header
+    /// to the kernel.
+    pub fn set_inet_raw(&mut self, inet_raw: bool) {
+        self.inet_raw = inet_raw;
+    }
+
+    pub fn is_inet_raw(&self) -> bool {
+        self.inet_raw
+    }
+
+    /// Send requests on ICMP datagram ("ping") sockets, leaving the IPv4 and link-layer headers
+    /// to the kernel, and receive replies on the same sockets.
+    pub fn set_ping(&mut self, ping: bool) {
+        self.ping = ping;
+    }
+
+    pub fn is_ping(&self) -> bool {
+        self.ping
+    }
+
+    /// Use `address` as the source of emitted packets rather than the interface's primary
+    /// address. The address must be assigned to the interface.
+    pub fn set_source_address(&mut self, address: Ipv4Addr) -> Result<()> {
+        if !self.interface.addresses.contains(&address) {
+            return Err(Error::GenericStringError(format!(
+                "source address {address} is not assigned to interface {} (addresses: {:?})",
+                self.interface.name, self.interface.addresses
+            )));
+        }
+        self.interface.address = address;
+        self.source_pinned = true;
+        Ok(())
+    }
+
+    /// The source address of packets sent to `addr`: the interface address in the most specific
+    /// of the interface's subnets containing `addr`, so that on-link targets in a secondary subnet
+    /// are answered directly, and otherwise the primary address. An address chosen with
+    /// `set_source_address` is always used.
+    pub fn source_address(&self, addr: &Ipv4Addr) -> Ipv4Addr {
+        if self.source_pinned {
+            return self.interface.address;
+        }
+        select_source(&self.interface.networks, addr).unwrap_or(self.interface.address)
+    }
+
+    /// Whether `addr` is assigned to the interface, and so may be the source of our requests.
+    pub fn is_local_address(&self, addr: &Ipv4Addr) -> bool {
+        self.interface.addresses.contains(addr)
+    }
+
+    /// Whether `addr` is the limited broadcast address or the broadcast address of a subnet
+    /// attached to the interface.
+    pub fn is_broadcast(&self, addr: &Ipv4Addr) -> bool {
+        addr.is_broadcast() || self.interface.broadcasts.contains(addr)
+    }
+
+    /// The destination MAC address for packets sent to `addr`: the broadcast MAC for broadcast
+    /// addresses, the mapped multicast MAC for multicast groups and otherwise the next hop.
+    pub fn destination_mac(&self, addr: &Ipv4Addr) -> MacAddr {
+        if self.is_broadcast(addr) {
+            MacAddr::broadcast()
+        } else if addr.is_multicast() {
+            // RFC 1112: the low-order 23 bits of the group address are placed in 01:00:5e:00:00:00
+            let o = addr.octets();
+            MacAddr(0x01, 0x00, 0x5e, o[1] & 0x7f, o[2], o[3])
+        } else {
+            self.ethernet_info.destination
+        }
+    }
+
+    /// Whether `addr` is in a subnet attached to the interface, and so reachable without going
+    /// through the default gateway.
+    pub fn is_on_link(&self, addr: &Ipv4Addr) -> bool {
+        self.interface
+            .networks
+            .iter()
+            .any(|(network, prefix_len)| in_subnet(network, *prefix_len, addr))
+    }
+
+    /// Like `destination_mac`, but on-link unicast addresses are sent directly to the target's
+    /// own MAC address, resolved through the shared neighbor cache. Falls back to the next hop if
+    /// the target can't be resolved.
+    pub async fn resolve_destination_mac(&self, addr: &Ipv4Addr) -> MacAddr {
+        if let Some(neighbors) = &self.neighbors {
+            if !self.is_broadcast(addr) && !addr.is_multicast() && self.is_on_link(addr) {
+                if let Some(mac) = neighbors.resolve(*addr).await {
+                    return mac;
+                }
+                tracing::debug!("couldn't resolve on-link target {addr}, sending via next hop");
+            }
+        }
+        self.destination_mac(addr)
+    }
+
+    fn with_interface(ethernet_info: Ethernet, interface: InterfaceInfo, handle: Handle) -> Self {
+        let neighbors = (interface.link_type == LinkType::Ethernet).then(|| {
+            Arc::new(NeighborCache::new(
+                handle,
+                interface.index,
+                interface.name.clone(),
+            ))
+        });
+        Self {
+            ethernet_info,
+            interface,
+            ethertype: None,
+            neighbors,
+            bind_device: true,
+            inet_raw: false,
+            ping: false,
+            source_pinned: false,
+            source_mac: None,
+        }
+    }
+
     /// Prepare a EthernetConf for the specified interface name.
     pub async fn new(interface_name: String) -> Result<Self> {
         // due to some kind of bug in rtnetlink, we have to use a separate netlink connection for
@@ -111,7 +495,7 @@ impl EthernetConf {
         tokio::spawn(connection);
 
         let interface = get_interface_by_name(handle.clone(), interface_name).await?;
-        let destination = get_neighbor_by_interface(handle.clone(), &interface).await?;
+        let destination = get_destination_mac(handle.clone(), &interface).await?;
 
         let ethernet_info = Ethernet {
             destination,
@@ -119,10 +503,50 @@ impl EthernetConf {
             ethertype: EtherTypes::Ipv4,
             payload: Vec::new(),
         };
-        Ok(Self {
-            ethernet_info,
-            interface,
-        })
+        Ok(Self::with_interface(ethernet_info, interface, handle))
+    }
+
+    /// Look the interface up afresh, eg after it went down and may have been recreated, keeping
+    /// the overrides of this configuration. Fails unless the interface is up with the same
+    /// source address. Bringing an interface down flushes its neighbors, so the next hop is kept
+    /// if it can't be found again on the same interface.
+    pub async fn reload(&self) -> Result<Self> {
+        let (connection, handle, _) = new_connection()?;
+        tokio::spawn(connection);
+
+        let interface = get_interface_by_name(handle.clone(), self.interface.name.clone()).await?;
+        if !interface.up {
+            return Err(Error::GenericStringError(format!(
+                "interface {} is down",
+                interface.name
+            )));
+        }
+        let destination = match get_destination_mac(handle.clone(), &interface).await {
+            Ok(destination) => destination,
+            Err(e) if interface.index == self.interface.index => {
+                tracing::debug!("keeping next hop of {}: {e}", interface.name);
+                self.ethernet_info.destination
+            }
+            Err(e) => return Err(e),
+        };
+
+        let ethernet_info = Ethernet {
+            destination,
+            source: interface.mac_addr,
+            ethertype: EtherTypes::Ipv4,
+            payload: Vec::new(),
+        };
+        let mut reloaded = Self::with_interface(ethernet_info, interface, handle);
+        reloaded.ethertype = self.ethertype;
+        reloaded.bind_device = self.bind_device;
+        reloaded.inet_raw = self.inet_raw;
+        reloaded.ping = self.ping;
+        reloaded.set_source_address(self.interface.address)?;
+        reloaded.source_pinned = self.source_pinned;
+        if let Some(mac) = self.source_mac {
+            reloaded.set_source_mac(mac);
+        }
+        Ok(reloaded)
     }
 
     /// Prepare a EthernetConf for the interface attached to the default route.
@@ -135,7 +559,7 @@ impl EthernetConf {
 
         let index = get_default_route_interface_index(handle.clone()).await?;
         let interface = get_interface_by_index(handle.clone(), index).await?;
-        let destination = get_neighbor_by_interface(handle.clone(), &interface).await?;
+        let destination = get_destination_mac(handle.clone(), &interface).await?;
 
         let ethernet_info = Ethernet {
             destination,
@@ -143,25 +567,85 @@ impl EthernetConf {
             ethertype: EtherTypes::Ipv4,
             payload: Vec::new(),
         };
-        Ok(Self {
-            ethernet_info,
+        Ok(Self::with_interface(ethernet_info, interface, handle))
+    }
+}
+
+#[cfg(test)]
+impl EthernetConf {
+    /// A configuration for an interface that doesn't exist, with the given addresses and the
+    /// prefix lengths of their subnets, the first address being its primary one.
+    pub(crate) fn fabricated(link_type: LinkType, networks: &[(Ipv4Addr, u8)]) -> Self {
+        let mac_addr = match link_type {
+            LinkType::Ethernet => MacAddr(0x02, 0, 0, 0, 0, 1),
+            LinkType::Loopback | LinkType::RawIp => MacAddr::zero(),
+        };
+        let interface = InterfaceInfo {
+            name: "test0".to_string(),
+            index: 1,
+            address: networks[0].0,
+            addresses: networks.iter().map(|(address, _)| *address).collect(),
+            broadcasts: Vec::new(),
+            networks: networks.to_vec(),
+            mac_addr,
+            link_type,
+            up: true,
+            mtu: Some(1500),
+        };
+        Self {
+            ethernet_info: Ethernet {
+                destination: MacAddr(0x02, 0, 0, 0, 0, 2),
+                source: mac_addr,
+                ethertype: EtherTypes::Ipv4,
+                payload: Vec::new(),
+            },
             interface,
-        })
+            ethertype: None,
+            neighbors: None,
+            bind_device: false,
+            inet_raw: false,
+            ping: false,
+            source_pinned: false,
+            source_mac: None,
+        }
     }
 }
 
+/// Whether `addr` is in the subnet of `network` with the given prefix length.
+fn in_subnet(network: &Ipv4Addr, prefix_len: u8, addr: &Ipv4Addr) -> bool {
+    let mask = u32::MAX
+        .checked_shl(32 - u32::from(prefix_len))
+        .unwrap_or(0);
+    u32::from(*network) & mask == u32::from(*addr) & mask
+}
+
+/// The address among `networks`, given as interface addresses and the prefix lengths of their
+/// subnets, whose subnet contains `addr`. The longest prefix wins, and the earliest address among
+/// equally long ones, so that the primary address is preferred.
+fn select_source(networks: &[(Ipv4Addr, u8)], addr: &Ipv4Addr) -> Option<Ipv4Addr> {
+    let mut selected: Option<(Ipv4Addr, u8)> = None;
+    for (address, prefix_len) in networks {
+        if in_subnet(address, *prefix_len, addr)
+            && selected.is_none_or(|(_, longest)| *prefix_len > longest)
+        {
+            selected = Some((*address, *prefix_len));
+        }
+    }
+    selected.map(|(address, _)| address)
+}
+
 async fn get_default_route_interface_index(handle: Handle) -> Result<u32> {
-    let mut routes = handle.route().get(IpVersion::V4).execute();
+    let mut routes = handle.route().get(NetlinkIpVersion::V4).execute();
 
     while let Some(route) = routes.try_next().await? {
         if let Some(idx) = route.output_interface() {
-            log::debug!("found interface index: {idx}");
+            tracing::debug!("found interface index: {idx}");
             return Ok(idx);
         }
     }
-    Err(Error::GenericStringError(format!(
-        "couldn't find suitable default route"
-    )))
+    Err(Error::GenericStringError(
+        "couldn't find suitable default route".to_string(),
+    ))
 }
 
 async fn get_interface_by_index(handle: Handle, interface_index: u32) -> Result<InterfaceInfo> {
@@ -169,7 +653,7 @@ async fn get_interface_by_index(handle: Handle, interface_index: u32) -> Result<
 
     let mut ii = if let Some(link) = links.try_next().await? {
         let ii: InterfaceInfo = link.try_into()?;
-        log::debug!("found interface {0}: {ii:?}", ii.name);
+        tracing::debug!("found interface {0}: {ii:?}", ii.name);
         ii
     } else {
         return Err(Error::GenericStringError(format!(
@@ -182,6 +666,43 @@ async fn get_interface_by_index(handle: Handle, interface_index: u32) -> Result<
     Ok(ii)
 }
 
+/// The index of the interface `address` is assigned to. Fails if it's assigned to none, or to
+/// several, since the choice between them would be arbitrary.
+async fn get_interface_index_by_address(handle: Handle, address: Ipv4Addr) -> Result<u32> {
+    let mut messages = handle.address().get().execute();
+    let mut indices = Vec::new();
+    while let Some(msg) = messages.try_next().await? {
+        if msg.header.family as u16 != nlconsts::AF_INET {
+            continue;
+        }
+        let assigned = msg.nlas.iter().any(
+            |nla| matches!(nla, address::nlas::Nla::Address(v) if v.as_slice() == address.octets()),
+        );
+        if assigned && !indices.contains(&msg.header.index) {
+            indices.push(msg.header.index);
+        }
+    }
+
+    match indices.as_slice() {
+        [] => Err(Error::GenericStringError(format!(
+            "no interface has address {address}"
+        ))),
+        [index] => Ok(*index),
+        _ => {
+            let mut names = Vec::new();
+            for index in &indices {
+                let mut links = handle.link().get().match_index(*index).execute();
+                let name = links.try_next().await?.as_ref().and_then(link_name);
+                names.push(name.unwrap_or_else(|| index.to_string()));
+            }
+            Err(Error::GenericStringError(format!(
+                "address {address} is assigned to several interfaces ({}); select one by name or index",
+                names.join(", ")
+            )))
+        }
+    }
+}
+
 async fn get_interface_by_name(handle: Handle, interface_name: String) -> Result<InterfaceInfo> {
     let mut links = handle
         .link()
@@ -191,7 +712,7 @@ async fn get_interface_by_name(handle: Handle, interface_name: String) -> Result
 
     let mut ii = if let Some(link) = links.try_next().await? {
         let ii: InterfaceInfo = link.try_into()?;
-        log::debug!("found interface {interface_name}: {ii:?}");
+        tracing::debug!("found interface {interface_name}: {ii:?}");
         ii
     } else {
         return Err(Error::GenericStringError(format!(
@@ -204,16 +725,25 @@ async fn get_interface_by_name(handle: Handle, interface_name: String) -> Result
     Ok(ii)
 }
 
+/// The MAC address of the next hop on `interface`. Interfaces without Ethernet framing have no
+/// next hop MAC address, so it is left zeroed.
+async fn get_destination_mac(handle: Handle, interface: &InterfaceInfo) -> Result<MacAddr> {
+    match interface.link_type {
+        LinkType::Ethernet => get_neighbor_by_interface(handle, interface).await,
+        LinkType::Loopback | LinkType::RawIp => Ok(MacAddr::zero()),
+    }
+}
+
 async fn get_neighbor_by_interface(handle: Handle, interface: &InterfaceInfo) -> Result<MacAddr> {
     let mut neighbors = handle
         .neighbours()
         .get()
-        .set_family(IpVersion::V4)
+        .set_family(NetlinkIpVersion::V4)
         .execute();
 
     while let Some(msg) = neighbors.try_next().await? {
         if msg.header.ifindex != interface.index {
-            log::debug!(
+            tracing::debug!(
                 "neighbor does not match output interface ({}) ({msg:?})",
                 interface.index
             );
@@ -237,3 +767,67 @@ async fn get_neighbor_by_interface(handle: Handle, interface: &InterfaceInfo) ->
         "unable to find neighbor MAC address for interface {interface:?}"
     )))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRIMARY: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 5);
+    const SECONDARY: Ipv4Addr = Ipv4Addr::new(192, 168, 7, 5);
+    const NARROW: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 130);
+
+    /// Addresses of an interface with a /24 primary subnet, a secondary /24, and a /25 carved out
+    /// of the upper half of the primary subnet.
+    fn networks() -> Vec<(Ipv4Addr, u8)> {
+        vec![(PRIMARY, 24), (SECONDARY, 24), (NARROW, 25)]
+    }
+
+    #[test]
+    fn subnet_membership() {
+        let network = Ipv4Addr::new(10, 0, 0, 0);
+        assert!(in_subnet(&network, 24, &Ipv4Addr::new(10, 0, 0, 255)));
+        assert!(!in_subnet(&network, 24, &Ipv4Addr::new(10, 0, 1, 0)));
+        assert!(in_subnet(&network, 0, &Ipv4Addr::new(203, 0, 113, 9)));
+        assert!(in_subnet(&network, 32, &network));
+        assert!(!in_subnet(&network, 32, &Ipv4Addr::new(10, 0, 0, 1)));
+    }
+
+    #[test]
+    fn source_in_targets_subnet_is_selected() {
+        let networks = networks();
+        let select = |addr| select_source(&networks, &addr);
+        assert_eq!(select(Ipv4Addr::new(10, 0, 0, 9)), Some(PRIMARY));
+        assert_eq!(select(Ipv4Addr::new(192, 168, 7, 200)), Some(SECONDARY));
+        // the longest matching prefix wins over the primary address
+        assert_eq!(select(Ipv4Addr::new(10, 0, 0, 200)), Some(NARROW));
+        assert_eq!(select(Ipv4Addr::new(203, 0, 113, 9)), None);
+    }
+
+    #[test]
+    fn earliest_address_wins_among_equal_prefixes() {
+        let other = Ipv4Addr::new(10, 0, 0, 6);
+        let networks = [(PRIMARY, 24), (other, 24)];
+        assert_eq!(
+            select_source(&networks, &Ipv4Addr::new(10, 0, 0, 9)),
+            Some(PRIMARY)
+        );
+    }
+
+    #[test]
+    fn off_link_targets_use_primary_address() {
+        let ec = EthernetConf::fabricated(LinkType::Ethernet, &networks());
+        assert_eq!(ec.source_address(&Ipv4Addr::new(203, 0, 113, 9)), PRIMARY);
+        assert_eq!(ec.source_address(&Ipv4Addr::new(192, 168, 7, 1)), SECONDARY);
+        assert!(ec.is_on_link(&Ipv4Addr::new(192, 168, 7, 1)));
+        assert!(!ec.is_on_link(&Ipv4Addr::new(203, 0, 113, 9)));
+    }
+
+    #[test]
+    fn pinned_source_is_used_for_every_target() {
+        let mut ec = EthernetConf::fabricated(LinkType::Ethernet, &networks());
+        ec.set_source_address(SECONDARY).unwrap();
+        assert_eq!(ec.source_address(&Ipv4Addr::new(10, 0, 0, 9)), SECONDARY);
+        assert_eq!(ec.source_address(&Ipv4Addr::new(203, 0, 113, 9)), SECONDARY);
+        assert!(ec.set_source_address(Ipv4Addr::new(172, 16, 0, 1)).is_err());
+    }
+}
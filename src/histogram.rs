@@ -0,0 +1,141 @@
+use std::time::Duration;
+
+/// Number of linear sub-buckets each power of two is divided into, which bounds the relative error
+/// of a recorded value to 1/16th.
+const SUB_BUCKETS: u64 = 16;
+const SUB_BUCKET_BITS: u32 = SUB_BUCKETS.trailing_zeros();
+
+/// Highest power of two (in microseconds) with buckets of its own, about 2.2 years. Larger values
+/// are counted in the last bucket.
+const MAX_EXPONENT: u32 = 50;
+const BUCKETS: usize = (SUB_BUCKETS * (MAX_EXPONENT - SUB_BUCKET_BITS + 2) as u64) as usize;
+
+/// Bounds of the coarse buckets printed by `Histogram::render`: 0.1ms doubling up to ~13s.
+const RENDER_MIN: Duration = Duration::from_micros(100);
+const RENDER_BUCKETS: u32 = 17;
+
+/// A fixed-resolution, HDR-style histogram of durations.
+///
+/// Values are recorded at microsecond resolution into log-linear buckets, so memory use is
+/// constant no matter how many samples are recorded while percentiles stay within ~6% of the true
+/// value.
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    total: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; BUCKETS],
+            total: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn record(&mut self, value: Duration) {
+        self.counts[bucket_index(value)] += 1;
+        self.total += 1;
+    }
+
+    /// Add the values recorded in `other`, eg to combine the RTTs of several targets.
+    pub fn merge(&mut self, other: &Histogram) {
+        for (count, other_count) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other_count;
+        }
+        self.total += other.total;
+    }
+
+    /// Estimate the value below which `percentile` percent of the recorded values fall, using the
+    /// nearest rank rather than interpolating between recorded values.
+    pub fn percentile(&self, percentile: f64) -> Option<Duration> {
+        if self.total == 0 {
+            return None;
+        }
+        let rank = ((percentile / 100.0) * self.total as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Some(bucket_midpoint(index));
+            }
+        }
+        None
+    }
+
+    /// Render bucketed counts with power-of-two bounds from 0.1ms, one bucket per line.
+    pub fn render(&self) -> String {
+        let mut coarse = [0u64; RENDER_BUCKETS as usize + 2];
+        for (index, count) in self.counts.iter().enumerate() {
+            let value = bucket_lower_bound(index);
+            let slot = if value < RENDER_MIN {
+                0
+            } else {
+                let doublings = (value.as_micros() / RENDER_MIN.as_micros()).ilog2();
+                (doublings + 1).min(RENDER_BUCKETS + 1) as usize
+            };
+            coarse[slot] += count;
+        }
+
+        let width = coarse.iter().max().copied().unwrap_or(0).to_string().len();
+        let mut out = String::new();
+        for (slot, count) in coarse.iter().enumerate() {
+            let label = match slot as u32 {
+                0 => format!("< {}", format_ms(RENDER_MIN)),
+                s if s > RENDER_BUCKETS => format!(">= {}", format_ms(render_bound(s - 1))),
+                s => format!(
+                    "{} - {}",
+                    format_ms(render_bound(s - 1)),
+                    format_ms(render_bound(s))
+                ),
+            };
+            out.push_str(&format!("{label:>22} | {count:>width$}\n"));
+        }
+        out
+    }
+}
+
+fn render_bound(doublings: u32) -> Duration {
+    RENDER_MIN * 2u32.pow(doublings)
+}
+
+fn format_ms(d: Duration) -> String {
+    format!("{:.1}ms", d.as_secs_f64() * 1000.0)
+}
+
+/// Values below `SUB_BUCKETS` microseconds get a bucket each; above that every power of two is
+/// split into `SUB_BUCKETS` equal parts.
+fn bucket_index(value: Duration) -> usize {
+    let us = value.as_micros().min(u64::MAX as u128) as u64;
+    if us < SUB_BUCKETS {
+        return us as usize;
+    }
+    let exponent = us.ilog2().min(MAX_EXPONENT);
+    if exponent == MAX_EXPONENT && us >> MAX_EXPONENT > 1 {
+        return BUCKETS - 1;
+    }
+    let shift = exponent - SUB_BUCKET_BITS;
+    let sub = (us >> shift) - SUB_BUCKETS;
+    (SUB_BUCKETS * (shift as u64 + 1) + sub) as usize
+}
+
+fn bucket_lower_bound(index: usize) -> Duration {
+    let index = index as u64;
+    if index < SUB_BUCKETS {
+        return Duration::from_micros(index);
+    }
+    let shift = index / SUB_BUCKETS - 1;
+    let sub = index % SUB_BUCKETS;
+    Duration::from_micros((SUB_BUCKETS + sub) << shift)
+}
+
+fn bucket_midpoint(index: usize) -> Duration {
+    let lower = bucket_lower_bound(index);
+    if (index as u64) < SUB_BUCKETS {
+        return lower;
+    }
+    let width = Duration::from_micros(1 << (index as u64 / SUB_BUCKETS - 1));
+    lower + width / 2
+}
@@ -0,0 +1,139 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// Token-bucket rate limiter shared by all target tasks to cap the aggregate send rate, in packets
+/// or bits per second or both.
+///
+/// The bucket holds a single token that refills once the previous send has been paid for: `1/n`
+/// seconds for a packet rate, the frame's transmission time for a bit rate, or the longer of the
+/// two if both are set. Sends are therefore spaced evenly rather than allowed to burst, and
+/// whichever limit is the tighter for a given frame applies. Waiters are queued in FIFO order by
+/// the underlying `tokio::sync::Mutex` and each target task only ever has one acquisition pending
+/// at a time, so targets are effectively served round-robin and an aggressive target can't starve
+/// the others.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    inner: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Time it takes the bucket to refill a single token, if packets per second are limited.
+    period: Option<Duration>,
+
+    /// Bits per second, if limited.
+    bits_per_sec: Option<u64>,
+
+    /// Earliest instant at which the next token becomes available.
+    next_slot: Instant,
+
+    /// Cumulative time waiters have spent waiting for tokens.
+    delay: Duration,
+}
+
+impl RateLimiter {
+    /// Create a limiter allowing at most `max_pps` acquisitions per second and at most
+    /// `bits_per_sec` bits of frames per second, either of which may be unlimited.
+    pub fn new(max_pps: Option<u32>, bits_per_sec: Option<u64>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Bucket {
+                period: max_pps.map(|max_pps| Duration::from_secs(1) / max_pps.max(1)),
+                bits_per_sec: bits_per_sec.map(|bps| bps.max(1)),
+                next_slot: Instant::now(),
+                delay: Duration::ZERO,
+            })),
+        }
+    }
+
+    /// Wait until a token is available and take it to send a frame of `frame_len` bytes.
+    pub async fn acquire(&self, frame_len: usize) {
+        let slot = {
+            let mut bucket = self.inner.lock().await;
+            let now = Instant::now();
+            let slot = bucket.next_slot.max(now);
+            let transmission = bucket.bits_per_sec.map_or(Duration::ZERO, |bps| {
+                Duration::from_secs_f64(frame_len as f64 * 8.0 / bps as f64)
+            });
+            bucket.next_slot = slot + bucket.period.unwrap_or_default().max(transmission);
+            bucket.delay += slot - now;
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+
+    /// Cumulative delay introduced by the limiter across all acquisitions so far.
+    pub async fn delay(&self) -> Duration {
+        self.inner.lock().await.delay
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn acquisitions_are_spaced_by_the_packet_rate() {
+        let limiter = RateLimiter::new(Some(100), None);
+        let start = Instant::now();
+        limiter.acquire(64).await;
+        // the first token is available straight away
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        for _ in 0..4 {
+            limiter.acquire(64).await;
+        }
+        assert_eq!(start.elapsed(), Duration::from_millis(40));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_time_does_not_build_up_a_burst() {
+        let limiter = RateLimiter::new(Some(10), None);
+        limiter.acquire(64).await;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let start = Instant::now();
+        limiter.acquire(64).await;
+        limiter.acquire(64).await;
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bit_rate_spaces_sends_by_the_previous_frame_length() {
+        // a byte per millisecond
+        let limiter = RateLimiter::new(None, Some(8000));
+        let start = Instant::now();
+        limiter.acquire(100).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        limiter.acquire(500).await;
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+        limiter.acquire(100).await;
+        assert_eq!(start.elapsed(), Duration::from_millis(600));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tighter_of_both_limits_applies() {
+        let limiter = RateLimiter::new(Some(10), Some(8000));
+        let start = Instant::now();
+        // sending 50 bytes takes less than the 100ms packet period
+        limiter.acquire(50).await;
+        limiter.acquire(500).await;
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+        // while sending 500 bytes takes longer
+        limiter.acquire(50).await;
+        assert_eq!(start.elapsed(), Duration::from_millis(600));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn delay_is_the_time_spent_waiting_for_tokens() {
+        let limiter = RateLimiter::new(Some(10), None);
+        // concurrent acquisitions are granted 0, 100 and 200ms from now
+        futures::future::join_all((0..3).map(|_| limiter.acquire(64))).await;
+        assert_eq!(limiter.delay().await, Duration::from_millis(300));
+
+        // a token that's already available adds nothing
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        limiter.acquire(64).await;
+        assert_eq!(limiter.delay().await, Duration::from_millis(300));
+    }
+}
@@ -0,0 +1,188 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::error::Result;
+use crate::ethernet::EthernetConf;
+use crate::socket::ProbeSocket;
+
+/// Time between attempts to re-initialize a link that went down.
+const RECOVERY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Creates a send socket for an interface's current configuration.
+pub type SenderFactory = Box<dyn Fn(&EthernetConf) -> Result<Arc<dyn ProbeSocket>> + Send + Sync>;
+
+/// A period during which probes couldn't be sent because their interface was down.
+#[derive(Clone, Debug, Serialize)]
+pub struct Outage {
+    pub interface: String,
+
+    /// When the first send failed, relative to the start of the run.
+    pub start_ms: u128,
+
+    /// How long it took for the link to be re-initialized, unless it was still down at the end
+    /// of the run.
+    pub duration_ms: Option<u128>,
+
+    /// Probes that failed to send during the outage.
+    pub failed_sends: u64,
+}
+
+impl std::fmt::Display for Outage {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "link on {} went down {:.1}s into the run",
+            self.interface,
+            self.start_ms as f64 / 1000.0
+        )?;
+        match self.duration_ms {
+            Some(duration) => write!(f, " for {:.1}s", duration as f64 / 1000.0)?,
+            None => write!(f, " and didn't come back")?,
+        }
+        write!(f, ", {} probes failed to send", self.failed_sends)
+    }
+}
+
+/// The interface configuration and send socket probes currently use. Each recovery from an
+/// outage bumps the generation, so that `ProbeTask`s know to pick up the new configuration.
+#[derive(Clone)]
+pub struct Link {
+    pub generation: u64,
+    pub ethernet_conf: Arc<EthernetConf>,
+    pub sender: Arc<dyn ProbeSocket>,
+}
+
+struct LinkState {
+    current: Link,
+
+    /// When the ongoing outage started, if the link is down.
+    down_since: Option<Instant>,
+    failed_sends: u64,
+    outages: Vec<Outage>,
+}
+
+/// Watches for sends failing because the interface is down and re-initializes the interface
+/// configuration and send socket once it comes back, eg after a flap or a failover that
+/// recreated the interface.
+pub struct LinkMonitor {
+    state: Mutex<LinkState>,
+    create_sender: SenderFactory,
+    started: Instant,
+}
+
+impl std::fmt::Debug for LinkMonitor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("LinkMonitor")
+            .field("started", &self.started)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LinkMonitor {
+    pub fn new(
+        ethernet_conf: Arc<EthernetConf>,
+        sender: Arc<dyn ProbeSocket>,
+        create_sender: SenderFactory,
+    ) -> Self {
+        Self {
+            state: Mutex::new(LinkState {
+                current: Link {
+                    generation: 0,
+                    ethernet_conf,
+                    sender,
+                },
+                down_since: None,
+                failed_sends: 0,
+                outages: Vec::new(),
+            }),
+            create_sender,
+            started: Instant::now(),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, LinkState> {
+        // the state stays consistent even if a holder panicked
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn current(&self) -> Link {
+        self.state().current.clone()
+    }
+
+    /// Record a send that failed because the link is down, starting recovery unless it's already
+    /// under way.
+    pub fn link_down(self: &Arc<Self>) {
+        let mut state = self.state();
+        state.failed_sends += 1;
+        if state.down_since.is_some() {
+            return;
+        }
+        tracing::warn!(
+            "link on {} is down; retrying every {RECOVERY_INTERVAL:?}",
+            state.current.ethernet_conf.interface.name()
+        );
+        state.down_since = Some(Instant::now());
+        tokio::spawn(self.clone().recover());
+    }
+
+    /// Periodically look the interface up again until it's back with the same address, then
+    /// publish its configuration and a new send socket.
+    async fn recover(self: Arc<Self>) {
+        let ethernet_conf = self.current().ethernet_conf;
+        let link = loop {
+            tokio::time::sleep(RECOVERY_INTERVAL).await;
+            let reloaded = match ethernet_conf.reload().await {
+                Ok(reloaded) => reloaded,
+                Err(e) => {
+                    tracing::debug!(
+                        "link on {} not recovered: {e}",
+                        ethernet_conf.interface.name()
+                    );
+                    continue;
+                }
+            };
+            match (self.create_sender)(&reloaded) {
+                Ok(sender) => break (Arc::new(reloaded), sender),
+                Err(e) => tracing::debug!(
+                    "failed to recreate sender for {}: {e}",
+                    ethernet_conf.interface.name()
+                ),
+            }
+        };
+        let mut state = self.state();
+        let down_since = state
+            .down_since
+            .take()
+            .expect("recovery only runs while the link is down");
+        let outage = self.outage(&state, down_since, Some(down_since.elapsed()));
+        tracing::warn!("{outage}; resuming");
+        state.outages.push(outage);
+        state.failed_sends = 0;
+        state.current = Link {
+            generation: state.current.generation + 1,
+            ethernet_conf: link.0,
+            sender: link.1,
+        };
+    }
+
+    fn outage(&self, state: &LinkState, start: Instant, duration: Option<Duration>) -> Outage {
+        Outage {
+            interface: state.current.ethernet_conf.interface.name().to_string(),
+            start_ms: start.saturating_duration_since(self.started).as_millis(),
+            duration_ms: duration.map(|d| d.as_millis()),
+            failed_sends: state.failed_sends,
+        }
+    }
+
+    /// Outages so far, including one still ongoing.
+    pub fn outages(&self) -> Vec<Outage> {
+        let state = self.state();
+        let mut outages = state.outages.clone();
+        if let Some(down_since) = state.down_since {
+            outages.push(self.outage(&state, down_since, None));
+        }
+        outages
+    }
+}
@@ -0,0 +1,54 @@
+use std::io::{Stdout, Write};
+use std::time::Duration;
+
+use crossterm::cursor::{MoveToColumn, MoveUp};
+use crossterm::terminal::{Clear, ClearType};
+use crossterm::QueueableCommand;
+
+use crate::error::Result;
+use crate::summary::Summary;
+
+/// Time between redraws of the live table.
+pub const REFRESH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Continuously redraws the per-target `Summary` table in place on the terminal.
+#[derive(Debug)]
+pub struct LiveTable {
+    stdout: Stdout,
+
+    /// Number of lines drawn by the previous render, which must be cleared before redrawing.
+    rendered_lines: u16,
+}
+
+impl LiveTable {
+    pub fn new() -> Self {
+        Self {
+            stdout: std::io::stdout(),
+            rendered_lines: 0,
+        }
+    }
+
+    /// Redraw the table; called every `REFRESH_INTERVAL`.
+    pub fn update(&mut self, summary: &Summary) -> Result<()> {
+        self.render(summary)
+    }
+
+    /// Replace the live table with a final plain text rendering that will survive in scrollback.
+    pub fn finish(&mut self, summary: &Summary) -> Result<()> {
+        self.render(summary)
+    }
+
+    fn render(&mut self, summary: &Summary) -> Result<()> {
+        let table = summary.table();
+        if self.rendered_lines > 0 {
+            self.stdout.queue(MoveUp(self.rendered_lines))?;
+        }
+        self.stdout
+            .queue(MoveToColumn(0))?
+            .queue(Clear(ClearType::FromCursorDown))?;
+        self.stdout.write_all(table.as_bytes())?;
+        self.stdout.flush()?;
+        self.rendered_lines = table.lines().count() as u16;
+        Ok(())
+    }
+}
@@ -1,130 +1,2433 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::File;
+use std::io::IsTerminal;
 use std::net::Ipv4Addr;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
-use clap::Parser;
-use csv::{ReaderBuilder, Terminator};
-use serde::Deserialize;
+use clap::parser::ValueSource;
+use clap::{ArgGroup, ArgMatches, CommandFactory, FromArgMatches, Parser};
+use csv::{ReaderBuilder, Terminator, Trim};
+use pnet::packet::ethernet::EtherType;
+use pnet::packet::icmp::{IcmpCode, IcmpType, IcmpTypes};
+use pnet::util::MacAddr;
 
+mod checksum;
+mod config;
+mod duration;
 mod error;
 mod ethernet;
+mod histogram;
+mod limiter;
+mod link;
+mod live;
+mod neighbor;
+mod output;
+mod pcap;
 mod prober;
 mod probes;
+mod resolver;
 mod socket;
+mod summary;
+mod target;
+#[cfg(feature = "uring")]
+mod uring;
 
-use error::{Error, Result};
-use ethernet::EthernetConf;
-use prober::{Prober, TargetParams};
-use probes::icmp::IcmpProbe;
-use tokio::task::JoinSet;
+use async_channel::Sender as ACSender;
+use config::{Config, Defaults};
+use duration::parse_duration;
+use error::Result;
+use ethernet::{EthernetConf, InterfaceSpec};
+use limiter::RateLimiter;
+use live::LiveTable;
+use output::{
+    ColorChoice, CsvSink, DotSink, OutputFile, OutputFormat, OutputSink, SortedSink, TeeSink,
+    TextSink,
+};
+use pcap::PcapWriter;
+use prober::{
+    ProbeRequest, ProbeStatus, Prober, ProberStats, SendOptions, SendRetry, TargetParams,
+};
+use probes::icmp::{self, IcmpOptions, IcmpProbe};
+use resolver::Resolver;
+use socket::{IoBackend, SocketBackend};
+use summary::{FailScope, Summary, TargetKey, Thresholds};
+use target::{Limits, ProbeType, Target, TargetDefaults, MAX_DSCP, MIN_PAYLOAD_SIZE};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc::unbounded_channel;
+use tokio::sync::Semaphore;
+use tokio::task::{AbortHandle, JoinSet};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+
+/// Number of probes sent to targets that don't specify a count, unless limited by --duration.
+const DEFAULT_COUNT: u64 = 10;
+
+/// Interface used for loopback targets that don't specify one.
+const LOOPBACK_INTERFACE: &str = "lo";
+
+/// Formats of the log messages written to stderr.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, clap::ValueEnum)]
+enum LogFormat {
+    /// Human-readable lines.
+    #[default]
+    Text,
+    /// Newline-delimited JSON objects.
+    Json,
+}
+
+/// What a target's loop does when sending a probe took so long that the following probe's tick
+/// has already passed, eg while its destination MAC address was resolved or the rate limiter held
+/// it up.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, clap::ValueEnum)]
+enum MissedTicks {
+    /// Send the probes of every missed tick back to back until the loop has caught up.
+    Burst,
+    /// Send the next probe at once and the rest an interval apart from it, shifting the schedule
+    /// back by the overrun.
+    Delay,
+    /// Send the next probe at the first tick that hasn't passed yet, keeping to the schedule.
+    #[default]
+    Skip,
+}
 
 #[derive(Parser, Debug)]
 #[command(author, version)]
+#[command(group(
+    ArgGroup::new("flood_confirmation")
+        .args(["i_know_what_im_doing", "max_count"])
+        .multiple(true)
+))]
 struct Cli {
-    targets: String,
+    /// Targets as
+    /// `addr[,count[,interval[,interface[,probes[,label[,dscp[,group[,rtt_warn[,payload_size[,dont_fragment]]]]]]]]]]`
+    /// rows separated by semicolons or newlines. Blank lines and lines beginning with `#` are ignored.
+    /// Labels containing commas or semicolons must be double-quoted. An empty count or interval
+    /// falls back to --default-count or --default-interval.
+    #[arg(required_unless_present_any = ["config", "targets_file"])]
+    targets: Option<String>,
+
+    /// File of targets in the same format as the targets argument, probed in addition to any
+    /// given there or in the config file. On SIGHUP the file is read again: loops for new rows
+    /// are started and those for removed rows stopped, while unchanged rows keep running.
+    #[arg(long)]
+    targets_file: Option<PathBuf>,
+
+    /// TOML file with defaults for command line options and a list of targets, probed in
+    /// addition to any given on the command line. Options given on the command line take
+    /// precedence.
+    #[arg(long)]
+    config: Option<PathBuf>,
 
     #[arg(default_value_t = 5000, long)]
     icmp_timeout: u64,
 
+    /// Interface to send probes from, unless a target specifies its own, by name, index or one of
+    /// its IPv4 addresses. Defaults to the interface attached to the default route.
     #[arg(short, long)]
     interface: Option<String>,
 
+    /// Source IPv4 address for probes sent from the default interface. Must be assigned to that
+    /// interface. By default probes to a target in one of the interface's subnets are sent from
+    /// the interface's address in that subnet, and others from its primary address.
+    #[arg(long)]
+    source_ip: Option<Ipv4Addr>,
+
+    /// Don't bind send sockets to their interface with SO_BINDTODEVICE. By default probes can
+    /// only leave by the interface selected for their target, even where the routing table would
+    /// choose another.
+    #[arg(long)]
+    no_bind_device: bool,
+
+    /// Set the IPv4 Record Route option on requests and report the addresses of up to nine hops
+    /// recorded in each reply. Routers may strip the option, leaving the route empty.
+    #[arg(long)]
+    record_route: bool,
+
+    /// Send ICMP Timestamp requests rather than echo requests and report the estimated offset of
+    /// each target's clock from ours. Hosts that don't answer timestamp requests time out.
+    #[arg(long)]
+    timestamp: bool,
+
+    /// ICMP type (0-255) of echo requests, for protocol testing. Replies are still matched by
+    /// identifier and nonce, so a target only counts as replying if it answers with an echo
+    /// reply. Timestamp requests keep their own type.
+    #[arg(default_value_t = 8, long, conflicts_with = "timestamp")]
+    icmp_type: u8,
+
+    /// ICMP code (0-255) of requests, for protocol testing.
+    #[arg(default_value_t = 0, long)]
+    icmp_code: u8,
+
+    /// DSCP value (0-63) to mark probes with, unless a target specifies its own. Replies whose
+    /// DSCP differs are reported, so that re-marking along the path is visible.
+    #[arg(default_value_t = 0, long, value_parser = clap::value_parser!(u8).range(0..=i64::from(MAX_DSCP)))]
+    dscp: u8,
+
+    /// Bytes of data carried by echo requests, unless a target specifies its own size. The first
+    /// four hold a nonce and the rest a fixed pattern. Replies that don't carry back the whole
+    /// payload intact are reported as corrupted or truncated, and sizes that don't fit the MTU of
+    /// a target's interface are rejected.
+    #[arg(default_value_t = MIN_PAYLOAD_SIZE, long, value_parser = clap::value_parser!(u16).range(i64::from(MIN_PAYLOAD_SIZE)..))]
+    payload_size: u16,
+
+    /// Set the Don't Fragment flag on requests, unless a target specifies otherwise, so that
+    /// routers report requests too big for the next hop rather than fragmenting them.
+    #[arg(long)]
+    dont_fragment: bool,
+
+    /// Receive buffer size in bytes of the socket on which replies are received. Larger buffers
+    /// let the kernel queue more replies during bursts rather than dropping them.
+    #[arg(long)]
+    rcvbuf: Option<usize>,
+
+    /// Number of workers receiving replies on each interface. With more than one, replies are
+    /// spread among the workers' sockets by the kernel (PACKET_FANOUT), so that a single receive
+    /// loop doesn't become a bottleneck at high reply rates.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    rx_workers: u16,
+
+    /// How sockets are driven. io_uring needs pingers built with the `uring` feature and Linux
+    /// 6.0 or later; epoll is used instead, with a warning, when it's unavailable.
+    #[arg(default_value = "epoll", long, value_enum)]
+    io_backend: IoBackend,
+
+    /// Kind of socket requests are sent on. `packet` sends the Ethernet frames pingers builds on
+    /// AF_PACKET sockets. `inet-raw` sends its IPv4 packets on raw AF_INET sockets (IP_HDRINCL)
+    /// instead, for hosts where packet sockets are blocked: the kernel adds the link-layer header
+    /// and fills in the IP identification field, destination MAC addresses come from the kernel's
+    /// neighbor table rather than pingers', --ethertype has no effect and only one receive worker
+    /// is used. `ping` sends only the ICMP messages on unprivileged ICMP datagram sockets,
+    /// leaving IPv4 headers to the kernel too; it needs no privileges where the user's group is
+    /// in net.ipv4.ping_group_range, but only sends echo requests and never sees ICMP errors, so
+    /// their probes time out. `auto` uses packet sockets unless opening one fails with EPERM or
+    /// EAFNOSUPPORT, then raw sockets unless they're not permitted either, then ping sockets;
+    /// `ping` falls back the same way when ping sockets aren't permitted.
+    #[arg(default_value = "auto", long, value_enum)]
+    socket_backend: SocketBackend,
+
+    /// Override the ethertype of emitted frames, eg 0x86dd. Intended for testing; defaults to the
+    /// ethertype matching the IP version of each target.
+    #[arg(long, value_parser = parse_ethertype)]
+    ethertype: Option<u16>,
+
+    /// Override the source MAC address of emitted frames, eg 02:00:00:00:00:01. Intended for
+    /// testing switches' MAC learning and port security in a lab. Replies are addressed to this
+    /// MAC, so they're only received if something delivers them to the interface, eg promiscuous
+    /// mode. Has no effect on interfaces without Ethernet framing or with inet-raw sockets.
+    #[arg(long, value_parser = parse_mac)]
+    source_mac: Option<MacAddr>,
+
+    /// Number of probes that may be in flight at once on each interface.
     #[arg(default_value_t = 1000, short, long)]
     concurrent_probes: usize,
+
+    /// Number of probes to a single target that may await replies at once. Once reached, the
+    /// target's next probe waits for one of them to complete, however short its interval.
+    #[arg(default_value_t = 1000, long, value_parser = clap::value_parser!(u32).range(1..))]
+    max_in_flight: u32,
+
+    /// Increase logging verbosity: -v for info, -vv for debug, -vvv for trace. RUST_LOG takes
+    /// precedence when set.
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Format of log messages on stderr. With `json`, each message is a JSON object carrying the
+    /// fields of the target and probe spans it was logged in, eg the address, sequence number and
+    /// ICMP identifier.
+    #[arg(default_value = "text", long, value_enum)]
+    log_format: LogFormat,
+
+    /// Don't print per-probe text lines to stdout, only the final summary. Structured (JSON/CSV)
+    /// records are still written.
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Send this many extra probes to each target before the counted ones. Their results are
+    /// marked as warm-up in the output and excluded from the summary.
+    #[arg(default_value_t = 0, long)]
+    warmup: u16,
+
+    /// Start each target's sequence numbers at a random offset rather than at zero, making
+    /// replies harder to spoof and less likely to be attributed to the wrong probe.
+    #[arg(long)]
+    random_seq: bool,
+
+    /// Format of the per-probe records written to stdout.
+    #[arg(default_value = "text", long, value_enum)]
+    output: OutputFormat,
+
+    /// Hold text records back until the run completes, then print them grouped by target in the
+    /// order the targets were listed, each target's probes in sequence order. JSON and CSV
+    /// records, and those written to files, are still written as probes complete. Every target
+    /// needs a finite count, since all of its records are kept in memory.
+    #[arg(long, conflicts_with_all = ["live", "flood"])]
+    sort: bool,
+
+    /// Color text records on stdout, showing failed probes in red and replies slower than their
+    /// target's --rtt-warn threshold (100ms without one) in yellow, and the summary on stderr by
+    /// loss. `auto` colors only terminals, and nothing if NO_COLOR is set. JSON and CSV records
+    /// and files are never colored.
+    #[arg(default_value = "auto", long, value_enum)]
+    color: ColorChoice,
+
+    /// Write per-probe records to stdout in large batches rather than flushing each as its probe
+    /// completes. This saves a write per probe, which helps sustain very high probe rates, at the
+    /// cost of records reaching a pipe in bursts.
+    #[arg(long, conflicts_with_all = ["live", "sort"])]
+    batch_output: bool,
+
+    /// Print a histogram of RTTs per target with the final summary.
+    #[arg(long)]
+    histogram: bool,
+
+    /// Also write per-probe records, in the format selected by --output, to this file. The file
+    /// is written under a temporary name alongside it and moved into place once the run
+    /// completes, including when interrupted with Ctrl-C.
+    #[arg(long)]
+    output_file: Option<PathBuf>,
+
+    /// Append to --output-file rather than replacing it, writing in place. CSV records appended
+    /// to a non-empty file aren't preceded by another header row.
+    #[arg(long, requires = "output_file")]
+    append: bool,
+
+    /// Show a continuously updated table of per-target statistics, including loss over recent
+    /// probes and a sparkline of recent RTTs, instead of printing per-probe records to stdout.
+    /// Falls back to plain output when stdout isn't a terminal.
+    #[arg(long, visible_alias = "watch")]
+    live: bool,
+
+    /// Write every frame sent and every reply received to this file in pcap format, eg for
+    /// inspection with Wireshark.
+    #[arg(long)]
+    pcap: Option<PathBuf>,
+
+    /// Look up the names of responders other than the target, eg routers reporting errors, by
+    /// reverse DNS. Lookups happen in the background, so records for a responder only carry its
+    /// name once resolved.
+    #[arg(long)]
+    resolve: bool,
+
+    /// How long to wait for a single reverse DNS lookup.
+    #[arg(default_value = "2s", long, value_parser = parse_duration)]
+    resolve_timeout: Duration,
+
+    /// Additionally write per-probe records as CSV, with a header row, to this file.
+    #[arg(long)]
+    csv_file: Option<PathBuf>,
+
+    /// Write the summary of the run to this file as a single JSON document, along with when the
+    /// run started and ended, the interfaces probes were sent from and the totals across
+    /// targets. The file is written under a temporary name alongside it and moved into place
+    /// once the run completes, including when interrupted with Ctrl-C.
+    #[arg(long)]
+    summary_json: Option<PathBuf>,
+
+    /// Treat every target as a broadcast address, collecting replies from all responding hosts
+    /// for the full --icmp-timeout. Broadcast and multicast targets are detected automatically.
+    #[arg(long)]
+    broadcast: bool,
+
+    /// Maximum number of probes per second sent across all targets combined.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    max_pps: Option<u32>,
+
+    /// Maximum bandwidth used by probes across all targets combined, eg `500kbps` or `1Mbps`,
+    /// counting each request's whole frame: link-layer, IPv4 and ICMP headers and the payload.
+    /// Bare numbers are bits per second. With --max-pps too, whichever is hit first applies.
+    #[arg(long, value_parser = parse_bandwidth)]
+    bandwidth: Option<u64>,
+
+    /// Double a target's interval once per interval in which its probes time out, resetting it to
+    /// the configured interval on the next reply. The effective interval (us) is appended to each
+    /// output line.
+    #[arg(long)]
+    adaptive: bool,
+
+    /// Upper bound on a target's interval in --adaptive mode.
+    #[arg(default_value = "10s", long, value_parser = parse_duration)]
+    adaptive_max_interval: Duration,
+
+    /// Start every target's probes at once. By default targets started together have their first
+    /// probe spread over their interval, so that targets with equal intervals don't send in
+    /// lockstep bursts; the spacing of each target's probes is the same either way.
+    #[arg(long)]
+    no_stagger: bool,
+
+    /// Start each target's probes at a random point within its interval rather than spreading
+    /// targets evenly in the order they're listed. Offsets are logged with -v.
+    #[arg(long, conflicts_with = "no_stagger")]
+    spread: bool,
+
+    /// Seed for the --spread offsets, so that a run's start offsets can be reproduced. A random
+    /// seed is used otherwise, and logged at info level.
+    #[arg(long, requires = "spread")]
+    seed: Option<u64>,
+
+    /// What a target's probes do when sending one takes longer than the target's interval, so
+    /// that the next probe's tick passes before it can be sent. Each such overrun is counted, and
+    /// the summary warns about targets probed at a lower rate than their interval asks for.
+    #[arg(default_value = "skip", long, value_enum)]
+    missed_ticks: MissedTicks,
+
+    /// Check reachability: send a single probe to each target, wait up to --icmp-timeout for the
+    /// replies, and print `<addr> up` or `<addr> down` per target instead of per-probe records
+    /// and the summary. The exit code is as usual, so it's 0 only if every target replied.
+    #[arg(long, conflicts_with_all = ["adaptive", "flood", "serial", "duration", "live", "warmup"])]
+    oneshot: bool,
+
+    /// Find the path MTU to each target instead of probing it at its interval: echo requests are
+    /// sent with Don't Fragment set, one at a time and no more often than the target's interval,
+    /// binary searching for the largest payload that's answered. Requests a router reports as
+    /// too big, or that time out, count as too big. Prints the path MTU and the number of probes
+    /// it took to stderr.
+    #[arg(
+        long,
+        conflicts_with_all = ["oneshot", "adaptive", "flood", "serial", "duration", "broadcast", "timestamp"]
+    )]
+    mtu_sweep: bool,
+
+    /// Send each probe to a target only once the previous one has been answered or timed out,
+    /// like classic ping, rather than at the target's interval regardless. Probes never overlap,
+    /// which keeps RTTs free of queuing behind earlier probes, at the cost of a lower probe rate
+    /// when replies are slow or lost.
+    #[arg(long, conflicts_with_all = ["adaptive", "flood"])]
+    serial: bool,
+
+    /// Ignore target intervals and send each probe as soon as the previous one to the same
+    /// target is answered or times out, printing a dot for each lost probe rather than a line per
+    /// probe. This can overwhelm targets, so it requires either --i-know-what-im-doing or
+    /// --max-count.
+    #[arg(
+        short,
+        long,
+        conflicts_with = "adaptive",
+        requires = "flood_confirmation"
+    )]
+    flood: bool,
+
+    /// Minimum gap between consecutive probes to a target in --flood mode.
+    #[arg(default_value = "1ms", long, value_parser = parse_duration)]
+    flood_min_gap: Duration,
+
+    /// Confirm that --flood may run without a --max-count bound.
+    #[arg(long)]
+    i_know_what_im_doing: bool,
+
+    /// Exit with a failure code if any target's packet loss exceeds this percentage. By default
+    /// only targets with 100% loss are considered failures.
+    #[arg(long, value_parser = parse_percent)]
+    fail_on_loss: Option<f64>,
+
+    /// Mark probes whose RTT exceeds this, eg `50ms`, unless a target specifies its own
+    /// threshold. Timed-out probes always exceed it.
+    #[arg(long, value_parser = parse_duration)]
+    rtt_warn: Option<Duration>,
+
+    /// Exit with a failure code if more than this percentage of any target's probes exceeded its
+    /// RTT warning threshold.
+    #[arg(long, value_parser = parse_percent)]
+    fail_on_rtt_warn: Option<f64>,
+
+    /// Whether --fail-on-loss and --fail-on-rtt-warn apply to each target or to the combined
+    /// probes of each group. Targets outside any group are evaluated on their own either way.
+    #[arg(default_value = "target", long, value_enum)]
+    fail_scope: FailScope,
+
+    /// Number of attempts to send each probe when sending fails with a transient error, such as
+    /// the kernel running out of buffer space. Other errors fail the probe immediately.
+    #[arg(default_value_t = 3, long, value_parser = clap::value_parser!(u32).range(1..))]
+    send_attempts: u32,
+
+    /// Keep running when an interface goes down, re-initializing it and its send socket once it
+    /// comes back, rather than failing every remaining probe. Probes that can't be sent in the
+    /// meantime count as lost, and each outage is reported in the summary.
+    #[arg(long)]
+    survive_link_down: bool,
+
+    /// Delay before retrying a failed send, doubling with each further attempt.
+    #[arg(default_value = "1ms", long, value_parser = parse_duration)]
+    send_retry_delay: Duration,
+
+    /// Skip targets that can't be parsed or fall outside the limits, with a warning, rather than
+    /// refusing to run. The run still fails if every target is invalid.
+    #[arg(long)]
+    skip_invalid: bool,
+
+    /// Minimum interval allowed for any target.
+    #[arg(default_value = "1us", long, value_parser = parse_duration)]
+    min_interval: Duration,
+
+    /// Maximum interval allowed for any target. Unbounded by default.
+    #[arg(long, value_parser = parse_duration)]
+    max_interval: Option<Duration>,
+
+    /// Maximum probe count allowed for any target. Unbounded by default.
+    #[arg(long)]
+    max_count: Option<u64>,
+
+    /// Stop scheduling probes once this long has passed, eg `60s`, then wait for those in flight
+    /// and print the summary. Targets with a count stop early if they reach it first.
+    #[arg(long, value_parser = parse_duration)]
+    duration: Option<Duration>,
+
+    /// Number of probes sent to targets that don't specify a count. Defaults to 10, or with
+    /// --duration to as many as fit in the run (up to --max-count, if given).
+    #[arg(long)]
+    default_count: Option<u64>,
+
+    /// Interval between probes to targets that don't specify one.
+    #[arg(default_value = "1s", long, value_parser = parse_duration)]
+    default_interval: Duration,
+
+    /// Probes per second sent to targets that don't specify an interval, eg `20` or `0.5`. A
+    /// shorthand for a --default-interval of 1000/rate milliseconds, so targets with intervals of
+    /// their own keep them. Can't be combined with a `default_interval` in the config file.
+    #[arg(
+        long,
+        visible_alias = "count-per-second",
+        conflicts_with = "default_interval",
+        value_parser = parse_rate
+    )]
+    rate_per_target: Option<Duration>,
 }
 
-#[derive(Debug, Deserialize)]
-struct Target {
-    addr: Ipv4Addr,
-    count: u16,
-    interval: u64,
+/// Fill in options not given on the command line from the config file's `defaults`.
+fn apply_defaults(cli: &mut Cli, matches: &ArgMatches, defaults: Defaults) -> Result<()> {
+    let given = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    if let (Some(icmp_timeout), false) = (defaults.icmp_timeout, given("icmp_timeout")) {
+        cli.icmp_timeout = icmp_timeout;
+    }
+    if let (Some(output), false) = (defaults.output, given("output")) {
+        cli.output = output;
+    }
+    if let (Some(dscp), false) = (defaults.dscp, given("dscp")) {
+        cli.dscp = dscp;
+    }
+    if let (Some(payload_size), false) = (defaults.payload_size, given("payload_size")) {
+        cli.payload_size = payload_size;
+    }
+    if let (Some(dont_fragment), false) = (defaults.dont_fragment, given("dont_fragment")) {
+        cli.dont_fragment = dont_fragment;
+    }
+    if let (Some(warmup), false) = (defaults.warmup, given("warmup")) {
+        cli.warmup = warmup;
+    }
+    if let (Some(concurrent_probes), false) =
+        (defaults.concurrent_probes, given("concurrent_probes"))
+    {
+        cli.concurrent_probes = concurrent_probes;
+    }
+    if let (Some(fail_scope), false) = (defaults.fail_scope, given("fail_scope")) {
+        cli.fail_scope = fail_scope;
+    }
+    cli.default_count = cli.default_count.or(defaults.default_count);
+    if let (Some(default_interval), false) = (defaults.default_interval, given("default_interval"))
+    {
+        // like --default-interval on the command line, the config file's conflicts with the
+        // interval --rate-per-target sets
+        if cli.rate_per_target.is_some() {
+            return Err(error::Error::GenericStringError(
+                "--rate-per-target can't be combined with default_interval in the config file"
+                    .to_string(),
+            ));
+        }
+        cli.default_interval = default_interval;
+    }
+    cli.interface = cli.interface.take().or(defaults.interface);
+    cli.source_ip = cli.source_ip.or(defaults.source_ip);
+    cli.max_pps = cli.max_pps.or(defaults.max_pps);
+    cli.fail_on_loss = cli.fail_on_loss.or(defaults.fail_on_loss);
+    cli.rtt_warn = cli.rtt_warn.or(defaults.rtt_warn);
+    cli.fail_on_rtt_warn = cli.fail_on_rtt_warn.or(defaults.fail_on_rtt_warn);
+    if let Some(interval) = cli.rate_per_target {
+        cli.default_interval = interval;
+    }
+    Ok(())
+}
+
+fn parse_ethertype(s: &str) -> std::result::Result<u16, String> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|e| format!("{e}"))
+}
+
+fn parse_mac(s: &str) -> std::result::Result<MacAddr, String> {
+    s.parse::<MacAddr>()
+        .map_err(|e| format!("{e}, expected six colon-separated hex octets"))
+}
+
+/// Parse a rate in probes per second into the interval between probes.
+fn parse_rate(s: &str) -> std::result::Result<Duration, String> {
+    let rate: f64 = s
+        .trim()
+        .parse()
+        .map_err(|e| format!("invalid rate {s:?}: {e}"))?;
+    if rate.is_nan() || rate <= 0.0 {
+        return Err(format!("invalid rate {s:?}: must be above 0"));
+    }
+    Duration::try_from_secs_f64(1.0 / rate).map_err(|e| format!("invalid rate {s:?}: {e}"))
+}
+
+/// Parse a bandwidth such as `64000`, `500kbps`, `1.5Mbps` or `1Gbps` into bits per second.
+/// Prefixes are decimal, as is usual for link speeds.
+fn parse_bandwidth(s: &str) -> std::result::Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value: f64 = value
+        .parse()
+        .map_err(|e| format!("invalid bandwidth {s:?}: {e}"))?;
+    let multiplier = match unit.trim() {
+        "" | "bps" => 1.0,
+        "kbps" | "Kbps" => 1e3,
+        "Mbps" => 1e6,
+        "Gbps" => 1e9,
+        unit => {
+            return Err(format!(
+                "invalid bandwidth {s:?}: unknown unit {unit:?} (expected bps, kbps, Mbps or Gbps)"
+            ))
+        }
+    };
+    let bps = (value * multiplier).round();
+    if bps < 1.0 {
+        return Err(format!("invalid bandwidth {s:?}: must be at least 1bps"));
+    }
+    Ok(bps as u64)
+}
+
+fn parse_percent(s: &str) -> std::result::Result<f64, String> {
+    let percent: f64 = s.parse().map_err(|e| format!("{e}"))?;
+    if !(0.0..=100.0).contains(&percent) {
+        return Err(format!("{percent} is not between 0 and 100"));
+    }
+    Ok(percent)
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    env_logger::init();
-    let cli = Cli::parse();
+async fn main() -> Result<ExitCode> {
+    // keep the matches around to tell which options were given on the command line, since those
+    // take precedence over the config file
+    let parsed = Cli::command()
+        .try_get_matches()
+        .and_then(|matches| Ok((Cli::from_arg_matches(&matches)?, matches)));
+    let (mut cli, matches) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            e.print()?;
+            // clap exits with code 2 on usage errors by default, which would be indistinguishable
+            // from a partial failure
+            return Ok(ExitCode::from(if e.use_stderr() { 1 } else { 0 }));
+        }
+    };
+
+    // RUST_LOG directives take precedence over the level selected by -v flags
+    let level = match cli.verbose {
+        0 => LevelFilter::WARN,
+        1 => LevelFilter::INFO,
+        2 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    };
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_env_filter(
+            EnvFilter::builder()
+                .with_default_directive(level.into())
+                .from_env_lossy(),
+        );
+    match cli.log_format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+
+    let config = match &cli.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    apply_defaults(&mut cli, &matches, config.defaults)?;
+
+    let limits = Limits {
+        min_interval: cli.min_interval,
+        max_interval: cli.max_interval,
+        max_count: cli.max_count,
+    };
+    let target_defaults = TargetDefaults {
+        count: match (cli.default_count, cli.duration) {
+            (Some(count), _) => Some(count),
+            (None, Some(_)) => None,
+            (None, None) => Some(DEFAULT_COUNT),
+        },
+        interval: cli.default_interval,
+    };
+    let (mut fixed_targets, mut skipped) = parse_targets(
+        cli.targets.as_deref().unwrap_or_default(),
+        &target_defaults,
+        &limits,
+        cli.skip_invalid,
+    )?;
+    for mut t in config.targets {
+        match t.resolve(&target_defaults, &limits) {
+            Ok(()) => fixed_targets.push(t),
+            Err(e) if cli.skip_invalid => {
+                tracing::warn!("skipping invalid target in config file: {e}");
+                skipped += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    let mut targets = fixed_targets.clone();
+    if let Some(path) = &cli.targets_file {
+        let (file_targets, file_skipped) =
+            read_targets_file(path, &target_defaults, &limits, cli.skip_invalid)?;
+        targets.extend(file_targets);
+        skipped += file_skipped;
+    }
+    if targets.is_empty() && skipped > 0 {
+        return Err(error::Error::GenericStringError(format!(
+            "all {skipped} targets are invalid"
+        )));
+    }
+    if cli.sort {
+        if let Some(target) = targets.iter().find(|t| t.count == Some(u64::MAX)) {
+            return Err(error::Error::GenericStringError(format!(
+                "--sort needs a finite count, but target {} probes until --duration ends; \
+                 set --default-count or --max-count",
+                target.addr
+            )));
+        }
+    }
+
+    // run one Prober per interface and probe type, each with its own sockets and probes, all
+    // reporting to a single output channel. every Prober's listener sees every ICMP reply;
+    // validate_response dispatches on the ICMP type and replies for targets of another Prober
+    // match none of its waiters
+    let (mut prober, _, mut output_receiver) = Prober::<IcmpProbe>::new()?;
+    if let Some(rcvbuf) = cli.rcvbuf {
+        prober.set_recv_buffer_size(rcvbuf);
+    }
+    prober.set_rx_workers(usize::from(cli.rx_workers));
+    prober.set_io_backend(cli.io_backend);
+    cli.socket_backend = cli.socket_backend.resolve();
+    if cli.socket_backend == SocketBackend::Ping {
+        check_ping_options(&cli)?;
+    }
+    prober.set_survive_link_down(cli.survive_link_down);
+    prober.set_probe_slots(cli.concurrent_probes);
+    tracing::debug!("sending requests with {}", prober.tag());
+    prober.set_send_retry(SendRetry {
+        max_attempts: cli.send_attempts,
+        base_delay: cli.send_retry_delay,
+    });
+    let capture = match &cli.pcap {
+        Some(path) => Some(Arc::new(PcapWriter::create(path)?)),
+        None => None,
+    };
+    if let Some(capture) = &capture {
+        prober.set_capture(capture.clone());
+    }
+
+    let limiter = (cli.max_pps.is_some() || cli.bandwidth.is_some())
+        .then(|| RateLimiter::new(cli.max_pps, cli.bandwidth));
+    let pacing = if cli.flood {
+        eprintln!(
+            "WARNING: flood mode sends probes as fast as replies arrive and can overwhelm targets"
+        );
+        Pacing::Flood {
+            min_gap: cli.flood_min_gap,
+        }
+    } else if cli.adaptive {
+        Pacing::Adaptive {
+            max_interval: cli.adaptive_max_interval,
+            missed_ticks: cli.missed_ticks,
+        }
+    } else if cli.serial {
+        Pacing::Serial
+    } else {
+        Pacing::Fixed {
+            missed_ticks: cli.missed_ticks,
+        }
+    };
+    let spread = if cli.spread {
+        let seed = match cli.seed {
+            Some(seed) => seed,
+            None => u64::from_ne_bytes(prober::random_bytes()?),
+        };
+        tracing::info!("spreading target start offsets with seed {seed}");
+        Some(SplitMix64(seed))
+    } else {
+        None
+    };
+    let mut supervisor = Supervisor {
+        cli: &cli,
+        target_defaults,
+        limits,
+        fixed_targets,
+        icmp_timeout: Duration::from_millis(cli.icmp_timeout),
+        prober,
+        ethernet_confs: HashMap::new(),
+        interface_names: HashMap::new(),
+        target_senders: HashMap::new(),
+        probe_tasks: JoinSet::new(),
+        limiter: limiter.clone(),
+        pacing,
+        spread,
+        loops: HashMap::new(),
+        set: JoinSet::new(),
+    };
+    let mut annotations = supervisor.prepare(&mut targets).await?;
+
+    let mut summary = Summary::default();
+    summary.colored = cli.color.enabled(&std::io::stderr());
+    annotations.apply(&mut summary);
+    let live = cli.live && std::io::stdout().is_terminal();
+    if cli.live && !live {
+        eprintln!("stdout is not a terminal, falling back to plain output");
+    }
+    let mut sinks: Vec<Box<dyn OutputSink<IcmpProbe>>> = Vec::new();
+    let colored = cli.color.enabled(&std::io::stdout());
+    let quiet = (cli.quiet && cli.output == OutputFormat::Text) || cli.oneshot;
+    if cli.flood && cli.output == OutputFormat::Text && !live && !quiet {
+        sinks.push(Box::new(DotSink::new(std::io::stdout())));
+    } else if cli.sort && cli.output == OutputFormat::Text && !live && !quiet {
+        let order = targets.iter().map(|t| t.addr);
+        sinks.push(Box::new(SortedSink::new(std::io::stdout(), order, colored)));
+    } else if colored && cli.output == OutputFormat::Text && !live && !quiet {
+        sinks.push(Box::new(TextSink::colored(std::io::stdout())));
+    } else if !live && !quiet {
+        sinks.push(output::stdout_sink(cli.output, cli.batch_output));
+    }
+    let output_file = match &cli.output_file {
+        Some(path) => {
+            let (output_file, sink) = OutputFile::create(path, cli.output, cli.append)?;
+            sinks.push(sink);
+            Some(output_file)
+        }
+        None => None,
+    };
+    if let Some(path) = &cli.csv_file {
+        sinks.push(Box::new(CsvSink::with_flush_interval(
+            File::create(path)?,
+            Duration::from_secs(1),
+        )));
+    }
+    let sink = TeeSink::new(sinks);
+    // annotations for reloaded targets are sent before their probes are scheduled
+    let (annotation_sender, mut annotation_receiver) = unbounded_channel::<Annotations>();
+    let resolver = cli.resolve.then(|| Resolver::new(cli.resolve_timeout));
+    // SIGUSR1 prints the statistics so far to stderr, like SIGQUIT does for ping, without
+    // stopping the run
+    let mut user1 = signal(SignalKind::user_defined1())?;
+    let output_format = cli.output;
+    let output_handling_fut = tokio::spawn(async move {
+        let mut live_table = live.then(LiveTable::new);
+        let mut refresh = tokio::time::interval(live::REFRESH_INTERVAL);
+        loop {
+            // the live table is redrawn at a fixed rate rather than for every report
+            let mut report = tokio::select! {
+                biased;
+                Some(update) = annotation_receiver.recv() => {
+                    update.apply(&mut summary);
+                    annotations = update;
+                    continue;
+                }
+                _ = refresh.tick(), if live_table.is_some() => {
+                    if let Some(live_table) = &mut live_table {
+                        live_table.update(&summary)?;
+                    }
+                    continue;
+                }
+                Some(()) = user1.recv() => {
+                    if output_format == OutputFormat::Json {
+                        eprintln!("{}", serde_json::to_string(&summary.stats())?);
+                    } else {
+                        eprint!("{summary}");
+                    }
+                    continue;
+                }
+                report = output_receiver.recv() => match report {
+                    Some(report) => report,
+                    None => break,
+                },
+            };
+            if let (Some(resolver), Some(responder)) = (&resolver, report.responder()) {
+                report.responder_name = resolver.lookup(responder);
+            }
+            let key = (report.tparams.addr, report.tparams.probe, report.instance);
+            report.label = annotations.labels.get(&key).cloned();
+            report.rtt_warn = annotations.rtt_warns.get(&key).copied();
+            if let (Some(delay), Some(threshold)) = (
+                report.send_delay,
+                annotations.late_thresholds.get(&key).copied(),
+            ) {
+                if delay > threshold {
+                    annotations.late_thresholds.remove(&key);
+                    tracing::warn!(
+                        "probe to {} was sent {:.3}ms late; results may be skewed by local \
+                         scheduling or a shortage of probe tasks (see --concurrent-probes)",
+                        report.tparams.addr,
+                        delay.as_secs_f64() * 1000.0
+                    );
+                }
+            }
+            summary.record(&report);
+            sink.record(&report)?;
+        }
+        sink.flush()?;
+        if let Some(live_table) = &mut live_table {
+            live_table.finish(&summary)?;
+        }
+        Ok::<_, error::Error>(summary)
+    });
+
+    let started = SystemTime::now();
+    supervisor.reconcile(&targets)?;
+
+    // the first Ctrl-C, or the end of --duration, stops scheduling probes but lets those in
+    // flight complete, so that the summary and output files are complete; a second Ctrl-C exits
+    // immediately
+    let stop = CancellationToken::new();
+    let stopped = stop.clone();
+    let interrupt = stop.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            eprintln!("interrupted; waiting for probes in flight, Ctrl-C again to exit now");
+            interrupt.cancel();
+        }
+        if tokio::signal::ctrl_c().await.is_ok() {
+            std::process::exit(130);
+        }
+    });
+    if let Some(duration) = cli.duration {
+        tokio::spawn(async move {
+            tokio::time::sleep(duration).await;
+            tracing::debug!("--duration elapsed, waiting for probes in flight");
+            stop.cancel();
+        });
+    }
+    // SIGHUP reloads the targets file, but otherwise keeps its default of ending the process
+    let mut hangup = match &cli.targets_file {
+        Some(_) => Some(signal(SignalKind::hangup())?),
+        None => None,
+    };
+    let mut output_handling_fut = output_handling_fut;
+    loop {
+        tokio::select! {
+            next = supervisor.set.join_next() => {
+                if next.is_none() {
+                    break;
+                }
+            }
+            _ = stopped.cancelled(), if !supervisor.set.is_empty() => {
+                supervisor.set.abort_all();
+            }
+            Some(()) = async { hangup.as_mut()?.recv().await }, if !stopped.is_cancelled() => {
+                if let Some(path) = &cli.targets_file {
+                    // a bad file leaves the running targets as they are
+                    match supervisor.reload(path).await {
+                        Ok((targets, annotations)) => {
+                            // the output task only goes away on error, which the branch below
+                            // reports
+                            let _ = annotation_sender.send(annotations);
+                            let (started, stopped) = supervisor.reconcile(&targets)?;
+                            eprintln!(
+                                "reloaded {}: {started} target loops started, {stopped} stopped",
+                                path.display()
+                            );
+                        }
+                        Err(e) => tracing::error!(
+                            "failed to reload {}, keeping the current targets: {e}",
+                            path.display()
+                        ),
+                    }
+                }
+            }
+            // records that can't be written, eg because the disk is full, end the run rather
+            // than being silently lost
+            result = &mut output_handling_fut => {
+                let e = match result {
+                    Ok(Ok(_)) => error::Error::GenericStringError(
+                        "output handling stopped unexpectedly".to_string(),
+                    ),
+                    Ok(Err(e)) => e,
+                    Err(e) => e.into(),
+                };
+                return Err(error::Error::GenericStringError(format!(
+                    "failed to write output: {e}"
+                )));
+            }
+        }
+    }
+
+    // drop the original Prober so that the output channel closes once every sibling finishes
+    let Supervisor {
+        prober,
+        target_senders,
+        mut probe_tasks,
+        ethernet_confs,
+        ..
+    } = supervisor;
+    drop(prober);
+
+    tracing::debug!("closing target senders");
+    for target_sender in target_senders.values() {
+        target_sender.close();
+    }
+
+    tracing::debug!("awaiting probe tasks finish");
+    let mut dropped_replies = 0;
+    let mut kernel_drops = 0;
+    let mut redirects: HashMap<TargetKey, u64> = HashMap::new();
+    let mut unexpected = Vec::new();
+    let mut short_replies: HashMap<TargetKey, u64> = HashMap::new();
+    let mut worker_packets = vec![0; usize::from(cli.rx_workers)];
+    let mut outages = Vec::new();
+    while let Some(result) = probe_tasks.join_next().await {
+        let stats = result??;
+        dropped_replies += stats.dropped_replies;
+        kernel_drops += stats.kernel_drops;
+        for (key, count) in stats.redirects {
+            *redirects.entry(key).or_default() += count;
+        }
+        for (key, counts) in stats.unexpected {
+            unexpected.extend(counts.into_iter().map(|(kind, count)| (key, kind, count)));
+        }
+        for (key, count) in stats.short_replies {
+            *short_replies.entry(key).or_default() += count;
+        }
+        for (total, packets) in worker_packets.iter_mut().zip(stats.worker_packets) {
+            *total += packets;
+        }
+        outages.extend(stats.outages);
+    }
+
+    if let Some(capture) = capture {
+        capture.flush()?;
+    }
+
+    tracing::debug!("awaiting output handling task finish");
+    let mut summary = output_handling_fut.await??;
+    if let Some(output_file) = output_file {
+        output_file.finish()?;
+    }
+
+    summary.dropped_replies = dropped_replies;
+    summary.kernel_drops = kernel_drops;
+    for (key, count) in redirects {
+        summary.add_redirects(key, count);
+    }
+    for (key, kind, count) in unexpected {
+        summary.add_unexpected(key, kind, count);
+    }
+    for (key, count) in short_replies {
+        summary.add_short_replies(key, count);
+    }
+    summary.worker_packets = worker_packets;
+    summary.outages = outages;
+    if let Some(limiter) = limiter {
+        summary.limiter_delay = Some(limiter.delay().await);
+    }
+    if let Some(path) = &cli.summary_json {
+        let interfaces: BTreeSet<String> = ethernet_confs
+            .values()
+            .map(|ec| ec.interface.name().to_string())
+            .collect();
+        let stats = summary.run_stats(started, interfaces.into_iter().collect());
+        output::write_atomically(path, &serde_json::to_vec_pretty(&stats)?)?;
+    }
+    if cli.oneshot {
+        print!("{}", summary.reachability());
+    } else if cli.output == OutputFormat::Json {
+        eprintln!("{}", serde_json::to_string(&summary.stats())?);
+    } else {
+        eprint!("{summary}");
+    }
+    if cli.histogram {
+        eprint!("{}", summary.histograms());
+    }
+    let thresholds = Thresholds {
+        loss: cli.fail_on_loss,
+        rtt_warn: cli.fail_on_rtt_warn,
+        scope: cli.fail_scope,
+    };
+    if !cli.oneshot {
+        let (breached, _) = summary.breached(&thresholds);
+        for (name, breach) in breached {
+            eprintln!("{name} breached the {breach}");
+        }
+    }
+
+    Ok(summary.exit_code(&thresholds))
+}
+
+/// Parse a target list in the format of the targets argument, completing and validating each
+/// target. With `skip_invalid`, rows that fail are logged and left out rather than failing the
+/// whole list; the number left out is returned along with the targets.
+fn parse_targets(
+    list: &str,
+    defaults: &TargetDefaults,
+    limits: &Limits,
+    skip_invalid: bool,
+) -> Result<(Vec<Target>, usize)> {
+    let target_list = target::strip_comments(list);
     let mut rdr = ReaderBuilder::new()
         .has_headers(false)
+        // the trailing interface, probe, label and dscp columns are optional
+        .flexible(true)
+        .trim(Trim::All)
         .delimiter(b',')
         .terminator(Terminator::Any(b';'))
-        .from_reader(cli.targets.as_bytes());
-    let mut targets: Vec<Target> = Vec::new();
-    for result in rdr.deserialize() {
-        let t: Target = result?;
-        let addr = t.addr;
-        if t.interval < 1 {
-            return Err(Error::GenericStringError(format!(
-                "error in target {addr}: interval must be between 1 and 1000 (ms)",
-            )));
+        .from_reader(target_list.as_bytes());
+    let mut targets = Vec::new();
+    let mut skipped = 0;
+    for result in rdr.deserialize::<Target>() {
+        let resolved = result
+            .map_err(error::Error::from)
+            .and_then(|mut t: Target| {
+                t.resolve(defaults, limits)?;
+                Ok(t)
+            });
+        match resolved {
+            Ok(t) => targets.push(t),
+            Err(e) if skip_invalid => {
+                tracing::warn!("skipping invalid target: {e}");
+                skipped += 1;
+            }
+            Err(e) => return Err(e),
         }
-        if t.interval > 1000 {
-            return Err(Error::GenericStringError(format!(
-                "error in target {addr}: interval must be between 1 and 1000 (ms)",
-            )));
+    }
+    Ok((targets, skipped))
+}
+
+/// Read the targets file at `path`, completing and validating each target, and skipping invalid
+/// ones if `skip_invalid` is set.
+fn read_targets_file(
+    path: &Path,
+    defaults: &TargetDefaults,
+    limits: &Limits,
+    skip_invalid: bool,
+) -> Result<(Vec<Target>, usize)> {
+    let contents = std::fs::read_to_string(path)?;
+    parse_targets(&contents, defaults, limits, skip_invalid).map_err(|e| {
+        error::Error::GenericStringError(format!("invalid targets file {}: {e}", path.display()))
+    })
+}
+
+/// Ping sockets only send echo requests, in IPv4 packets and frames of the kernel's making, and
+/// never see ICMP error messages, so options that need more than that are rejected.
+fn check_ping_options(cli: &Cli) -> Result<()> {
+    let options = [
+        ("--timestamp", cli.timestamp),
+        ("--record-route", cli.record_route),
+        ("--mtu-sweep", cli.mtu_sweep),
+        ("--dont-fragment", cli.dont_fragment),
+        ("--survive-link-down", cli.survive_link_down),
+        ("--icmp-type", cli.icmp_type != IcmpTypes::EchoRequest.0),
+        ("--icmp-code", cli.icmp_code != 0),
+        ("--ethertype", cli.ethertype.is_some()),
+        ("--source-mac", cli.source_mac.is_some()),
+    ];
+    let unsupported: Vec<&str> = options
+        .iter()
+        .filter(|(_, given)| *given)
+        .map(|(option, _)| *option)
+        .collect();
+    if !unsupported.is_empty() {
+        return Err(error::Error::GenericStringError(format!(
+            "ping sockets don't support {}",
+            unsupported.join(", ")
+        )));
+    }
+    Ok(())
+}
+
+/// Details about target addresses that the output task attaches to their reports and summaries.
+#[derive(Debug, Default)]
+struct Annotations {
+    keys: Vec<TargetKey>,
+    broadcast: HashSet<Ipv4Addr>,
+    labels: HashMap<TargetKey, String>,
+    groups: HashMap<TargetKey, String>,
+    rtt_warns: HashMap<TargetKey, Duration>,
+
+    /// Probes sent more than half an interval late are warned about, once per row; flooding
+    /// ignores intervals, so there's nothing to compare against.
+    late_thresholds: HashMap<TargetKey, Duration>,
+
+    /// Probes per second asked for by each target's row, when sent at fixed intervals.
+    requested_rates: HashMap<TargetKey, f64>,
+}
+
+impl Annotations {
+    /// Add any targets not yet in `summary` to it, along with their details.
+    fn apply(&self, summary: &mut Summary) {
+        for key in &self.keys {
+            let (addr, _, _) = key;
+            summary.add(*key);
+            if self.broadcast.contains(addr) {
+                summary.set_broadcast(*key);
+            }
+            if let Some(label) = self.labels.get(key) {
+                summary.set_label(*key, label.clone());
+            }
+            if let Some(group) = self.groups.get(key) {
+                summary.set_group(*key, group.clone());
+            }
+            if let Some(rate) = self.requested_rates.get(key) {
+                summary.set_requested_rate(*key, *rate);
+            }
         }
-        if t.count < 1 {
-            return Err(Error::GenericStringError(format!(
-                "error in target {addr}: count must be between 1 and 10",
-            )));
+    }
+}
+
+/// Runs a loop scheduling probes for each target and probe type, along with the `Prober`s they
+/// send through, and reconciles the running loops with a reloaded target list.
+struct Supervisor<'a> {
+    cli: &'a Cli,
+    target_defaults: TargetDefaults,
+    limits: Limits,
+
+    /// Targets from the command line and config file, which reloads leave in place.
+    fixed_targets: Vec<Target>,
+
+    icmp_timeout: Duration,
+
+    /// Template for a `Prober` per interface and probe type. Its output channel stays open until
+    /// it's dropped.
+    prober: Prober<IcmpProbe>,
+
+    /// Interfaces by their name, or by none for the interface attached to the default route.
+    ethernet_confs: HashMap<Option<String>, EthernetConf>,
+
+    /// Names of the interfaces selected by each `--interface` value or interface column seen so
+    /// far, which may also be an index or address.
+    interface_names: HashMap<String, String>,
+
+    target_senders: HashMap<(Option<String>, ProbeType), ACSender<ProbeRequest>>,
+    probe_tasks: JoinSet<Result<ProberStats>>,
+    limiter: Option<RateLimiter>,
+    pacing: Pacing,
+
+    /// Draws the start offsets of targets with --spread.
+    spread: Option<SplitMix64>,
+
+    /// Loops by the completed target row and probe type they schedule, along with their lanes
+    /// and strides. Rows listed several times get a loop each.
+    loops: HashMap<(Target, ProbeType), Vec<(Lane, AbortHandle)>>,
+    set: JoinSet<()>,
+}
+
+impl Supervisor<'_> {
+    /// Complete `targets` with defaults for their interface, probe types and DSCP, starting a
+    /// `Prober` for each interface and probe type not yet in use, and collect their annotations.
+    async fn prepare(&mut self, targets: &mut [Target]) -> Result<Annotations> {
+        let cli = self.cli;
+        let default_interface = match &cli.interface {
+            Some(interface) => Some(self.interface_name(interface).await?),
+            None => None,
+        };
+        // build an EthernetConf for each interface in use; targets without an interface use the
+        // loopback interface for loopback addresses and otherwise the --interface default
+        for target in targets.iter_mut() {
+            if cli.oneshot {
+                target.count = Some(1);
+            }
+            let interface = target
+                .interface
+                .take()
+                .or_else(|| {
+                    target
+                        .addr
+                        .is_loopback()
+                        .then(|| LOOPBACK_INTERFACE.to_string())
+                })
+                .or_else(|| cli.interface.clone());
+            let interface = match interface {
+                Some(interface) => Some(self.interface_name(&interface).await?),
+                None => None,
+            };
+            if !self.ethernet_confs.contains_key(&interface) {
+                let mut ethernet_conf = if let Some(interface_name) = interface.clone() {
+                    EthernetConf::new(interface_name).await?
+                } else {
+                    EthernetConf::any().await?
+                };
+                if let Some(source_ip) = cli.source_ip {
+                    if interface == default_interface {
+                        ethernet_conf.set_source_address(source_ip)?;
+                    }
+                }
+                if let Some(ethertype) = cli.ethertype {
+                    ethernet_conf.set_ethertype(EtherType(ethertype))?;
+                }
+                ethernet_conf.set_bind_device(!cli.no_bind_device);
+                ethernet_conf.set_inet_raw(cli.socket_backend == SocketBackend::InetRaw);
+                ethernet_conf.set_ping(cli.socket_backend == SocketBackend::Ping);
+                if let Some(source_mac) = cli.source_mac {
+                    ethernet_conf.set_source_mac(source_mac);
+                }
+                tracing::debug!("ethernet config: {:?}", ethernet_conf);
+                self.ethernet_confs.insert(interface.clone(), ethernet_conf);
+            }
+            target.interface = interface;
+        }
+
+        let default_probe_type = if cli.timestamp {
+            ProbeType::Timestamp
+        } else {
+            ProbeType::Echo
+        };
+        for target in targets.iter_mut() {
+            if target.probes.is_empty() {
+                target.probes.push(default_probe_type);
+            }
+            target.dscp = target.dscp.or(Some(cli.dscp));
+            target.payload_size = target.payload_size.or(Some(cli.payload_size));
+            target.dont_fragment = target.dont_fragment.or(Some(cli.dont_fragment));
+            if cli.socket_backend == SocketBackend::Ping
+                && target.probes.contains(&ProbeType::Timestamp)
+            {
+                return Err(error::Error::GenericStringError(format!(
+                    "error in target {}: ping sockets only send echo requests",
+                    target.addr
+                )));
+            }
+            if cli.socket_backend == SocketBackend::Ping && target.dont_fragment == Some(true) {
+                return Err(error::Error::GenericStringError(format!(
+                    "error in target {}: ping sockets can't set Don't Fragment",
+                    target.addr
+                )));
+            }
+            self.check_mtu(target)?;
+        }
+        self.check_rates(targets);
+
+        let annotations = annotate(self.cli, &self.pacing, targets, |target| {
+            self.is_broadcast(target)
+        });
+
+        let prober_keys: HashSet<(Option<String>, ProbeType)> = targets
+            .iter()
+            .flat_map(|t| t.probes.iter().map(|probe| (t.interface.clone(), *probe)))
+            .collect();
+        for (interface, probe_type) in prober_keys {
+            if self
+                .target_senders
+                .contains_key(&(interface.clone(), probe_type))
+            {
+                continue;
+            }
+            let ethernet_conf = self.ethernet_confs[&interface].clone();
+            let icmp_options = IcmpOptions {
+                record_route: cli.record_route,
+                timestamp: probe_type == ProbeType::Timestamp,
+                echo_type: IcmpType(cli.icmp_type),
+                code: IcmpCode(cli.icmp_code),
+                tag: self.prober.tag(),
+            };
+            let probes = IcmpProbe::many(cli.concurrent_probes, &ethernet_conf, &icmp_options)?;
+            let (prober, target_sender) = self.prober.sibling();
+            let icmp_timeout = self.icmp_timeout;
+            self.probe_tasks
+                .spawn(async move { prober.run_probes(probes, ethernet_conf, icmp_timeout).await });
+            self.target_senders
+                .insert((interface, probe_type), target_sender);
+        }
+        Ok(annotations)
+    }
+
+    /// Look up the name of the interface selected by `interface`, a name, index or address.
+    async fn interface_name(&mut self, interface: &str) -> Result<String> {
+        if let Some(name) = self.interface_names.get(interface) {
+            return Ok(name.clone());
+        }
+        let spec: InterfaceSpec = interface.parse()?;
+        let name = spec.name().await?;
+        if name != interface {
+            tracing::debug!("selected interface {name} by {spec}");
         }
-        if t.count > 10 {
-            return Err(Error::GenericStringError(format!(
-                "error in target {addr}: count must be between 1 and 10",
+        self.interface_names
+            .insert(interface.to_string(), name.clone());
+        Ok(name)
+    }
+
+    /// Largest packet that can be sent to a prepared `target` through its interface.
+    fn interface_mtu(&self, target: &Target) -> usize {
+        let interface = &self.ethernet_confs[&target.interface].interface;
+        // the IPv4 total length field caps packets regardless of the MTU
+        interface.mtu.map_or(usize::from(u16::MAX), |mtu| {
+            (mtu as usize).min(usize::from(u16::MAX))
+        })
+    }
+
+    /// Check that the echo requests of a prepared `target` fit the MTU of its interface.
+    fn check_mtu(&self, target: &Target) -> Result<()> {
+        if !target.probes.contains(&ProbeType::Echo) {
+            return Ok(());
+        }
+        let interface = &self.ethernet_confs[&target.interface].interface;
+        let payload_size = target.payload_size.unwrap_or_default();
+        let len = icmp::echo_packet_len(self.cli.record_route, usize::from(payload_size));
+        let mtu = self.interface_mtu(target);
+        if len > mtu {
+            return Err(error::Error::GenericStringError(format!(
+                "error in target {}: payload size {payload_size} makes {len}-byte packets, \
+                 exceeding the {mtu}-byte MTU of {}",
+                target.addr,
+                interface.name()
             )));
         }
-        targets.push(t);
+        Ok(())
     }
 
-    let ethernet_conf = if let Some(interface_name) = cli.interface {
-        EthernetConf::new(interface_name).await?
-    } else {
-        EthernetConf::any().await?
-    };
+    /// Warn about prepared `targets` that ask for more probes per second than will be sent.
+    fn check_rates(&self, targets: &[Target]) {
+        if !matches!(self.pacing, Pacing::Fixed { .. }) || self.cli.mtu_sweep {
+            return;
+        }
+        let mut loads = Vec::new();
+        for target in targets {
+            let interval = target.interval.unwrap_or_default();
+            if interval.is_zero() {
+                continue;
+            }
+            let link_header_len = self.ethernet_confs[&target.interface].link_header_len();
+            let payload_size = usize::from(target.payload_size.unwrap_or_default());
+            for probe in &target.probes {
+                loads.push(Load {
+                    target,
+                    probe: *probe,
+                    rate: 1.0 / interval.as_secs_f64(),
+                    frame_len: link_header_len
+                        + icmp::request_packet_len(*probe, self.cli.record_route, payload_size),
+                });
+            }
+        }
+        for warning in rate_warnings(self.cli, self.icmp_timeout, &loads) {
+            tracing::warn!("{warning}");
+        }
+    }
 
-    log::debug!("ethernet config: {:?}", ethernet_conf);
+    fn is_broadcast(&self, target: &Target) -> bool {
+        self.cli.broadcast
+            || target.addr.is_multicast()
+            || self.ethernet_confs[&target.interface].is_broadcast(&target.addr)
+    }
 
-    let icmp_timeout = Duration::from_millis(cli.icmp_timeout);
+    /// Re-read the targets file at `path` and prepare its targets along with the fixed ones.
+    async fn reload(&mut self, path: &Path) -> Result<(Vec<Target>, Annotations)> {
+        let mut targets = self.fixed_targets.clone();
+        let (file_targets, _) = read_targets_file(
+            path,
+            &self.target_defaults,
+            &self.limits,
+            self.cli.skip_invalid,
+        )?;
+        targets.extend(file_targets);
+        let annotations = self.prepare(&mut targets).await?;
+        Ok((targets, annotations))
+    }
 
-    let probes = IcmpProbe::many(cli.concurrent_probes, &ethernet_conf)?;
-    let (prober, target_sender, mut output_receiver) = Prober::new()?;
+    /// Start loops for prepared targets that aren't running yet and stop those for targets no
+    /// longer listed, leaving the rest, and the sequence numbers they've reached, untouched. Loops
+    /// whose lane changed, eg because a row probing the same address was added, restart.
+    /// Unless --no-stagger was given, the first probes of the loops started are spread over their
+    /// intervals in the order their targets are listed. Returns the number of loops started and
+    /// stopped.
+    fn reconcile(&mut self, targets: &[Target]) -> Result<(usize, usize)> {
+        // loops probing the same address with the same kind of probe, eg at different intervals,
+        // each take every `stride`-th sequence number from their own lane so that they can't
+        // take each other's replies
+        let lanes = lanes(targets);
+        let wanted: HashSet<(&Target, ProbeType, u16, u16)> = lanes.iter().copied().collect();
 
-    let probe_tasks_fut =
-        tokio::spawn(async move { prober.run_probes(probes, ethernet_conf, icmp_timeout).await });
-    let output_handling_fut = tokio::spawn(async move {
-        while let Some(output) = output_receiver.recv().await {
-            println!("{output}");
+        let mut stopped = 0;
+        self.loops.retain(|(target, probe), handles| {
+            handles.retain(|((lane, stride), handle)| {
+                let keep = wanted.contains(&(target, *probe, *lane, *stride));
+                if !keep {
+                    handle.abort();
+                    stopped += 1;
+                }
+                keep
+            });
+            !handles.is_empty()
+        });
+
+        let starting: Vec<_> = lanes
+            .into_iter()
+            .filter(|(target, probe, lane, stride)| {
+                !self
+                    .loops
+                    .get(&((*target).clone(), *probe))
+                    .is_some_and(|handles| {
+                        handles
+                            .iter()
+                            .any(|(running, _)| *running == (*lane, *stride))
+                    })
+            })
+            .collect();
+        let started = starting.len();
+        for (i, (target, probe, lane, stride)) in starting.into_iter().enumerate() {
+            let interval = target.interval.unwrap_or_default();
+            let offset = if self.cli.no_stagger || self.cli.oneshot {
+                Duration::ZERO
+            } else if let Some(spread) = &mut self.spread {
+                interval.mul_f64(spread.next_f64())
+            } else {
+                stagger_offset(interval, i, started)
+            };
+            let handle = self.spawn(target, probe, offset, lane, stride)?;
+            self.loops
+                .entry((target.clone(), probe))
+                .or_default()
+                .push(((lane, stride), handle));
         }
-    });
+        Ok((started, stopped))
+    }
+
+    /// Start a loop probing `target`, sending its first probe `offset` after now.
+    fn spawn(
+        &mut self,
+        target: &Target,
+        probe: ProbeType,
+        offset: Duration,
+        lane: u16,
+        stride: u16,
+    ) -> Result<AbortHandle> {
+        let sender = self.target_senders[&(target.interface.clone(), probe)].clone();
+        if self.cli.mtu_sweep {
+            let sweep = Sweep {
+                limiter: self.limiter.clone(),
+                link_header_len: self.ethernet_confs[&target.interface].link_header_len(),
+                record_route: self.cli.record_route,
+                max_packet_len: self.interface_mtu(target),
+            };
+            return Ok(self
+                .set
+                .spawn(mtu_sweep(target.clone(), sender, sweep, offset)));
+        }
+        let ethernet_conf = &self.ethernet_confs[&target.interface];
+        tracing::debug!(
+            "sending {probe} probes to {} from {}",
+            target.addr,
+            ethernet_conf.source_address(&target.addr)
+        );
+        let payload_size = usize::from(target.payload_size.unwrap_or_default());
+        let throttle = Throttle {
+            limiter: self.limiter.clone(),
+            frame_len: ethernet_conf.link_header_len()
+                + icmp::request_packet_len(probe, self.cli.record_route, payload_size),
+            in_flight: Arc::new(Semaphore::new(self.cli.max_in_flight as usize)),
+        };
+        let sequencing = Sequencing {
+            initial: if self.cli.random_seq {
+                random_u16()?
+            } else {
+                0
+            },
+            warmup: if self.cli.oneshot { 0 } else { self.cli.warmup },
+            lane,
+            stride,
+        };
+        if stride > 1 {
+            tracing::debug!(
+                "{probe} probes to {} take sequence numbers {lane} mod {stride}",
+                target.addr
+            );
+        }
+        Ok(self.set.spawn(run_target(
+            target.clone(),
+            probe,
+            sender,
+            throttle,
+            self.pacing,
+            self.is_broadcast(target),
+            sequencing,
+            offset,
+        )))
+    }
+}
+
+/// Bounds on how quickly a target's probes are sent.
+#[derive(Clone, Debug)]
+struct Throttle {
+    /// Rate limiter shared by all targets, if any.
+    limiter: Option<RateLimiter>,
+
+    /// Length of the frames carrying the target's requests, which the limiter charges for.
+    frame_len: usize,
+
+    /// Slots for the target's probes awaiting replies.
+    in_flight: Arc<Semaphore>,
+}
+
+/// What an MTU sweep needs to know about how a target's requests are sent.
+#[derive(Clone, Debug)]
+struct Sweep {
+    /// Rate limiter shared by all targets, if any.
+    limiter: Option<RateLimiter>,
+
+    /// Length of the link-layer header preceding each request.
+    link_header_len: usize,
+    record_route: bool,
+
+    /// Largest packet the target's interface can send.
+    max_packet_len: usize,
+}
 
-    let mut set = JoinSet::new();
-
-    for target in targets.into_iter() {
-        let sender = target_sender.clone();
-        set.spawn(async move {
-            let mut interval = tokio::time::interval(Duration::from_millis(target.interval));
-            for i in 0..target.count {
-                interval.tick().await;
-                let tparams = TargetParams {
-                    addr: target.addr,
-                    seq: i,
+/// Determines how wire sequence numbers are assigned to a target's probes.
+#[derive(Clone, Copy, Debug)]
+struct Sequencing {
+    /// Sequence number of the first counted probe.
+    initial: u16,
+
+    /// Number of warm-up probes sent before the counted probes.
+    warmup: u16,
+
+    /// The loop's lane among those probing the same address with the same kind of probe, and
+    /// their number; its sequence numbers are `lane` modulo `stride`, which is a power of two so
+    /// that they keep to the lane as they wrap around.
+    lane: u16,
+    stride: u16,
+}
+
+/// A loop's lane and stride, as set out in its `Sequencing`.
+type Lane = (u16, u16);
+
+/// The lane of each row among the rows probing the same address with the same kind of probe,
+/// numbered in the order they're listed, along with their stride: the number of lanes rounded up
+/// to a power of two. The sequence numbers of an address probed by a single row aren't divided.
+fn lanes(targets: &[Target]) -> Vec<(&Target, ProbeType, u16, u16)> {
+    let mut counts: HashMap<(Ipv4Addr, ProbeType), u16> = HashMap::new();
+    let mut lanes = Vec::new();
+    for target in targets {
+        for probe in &target.probes {
+            let count = counts.entry((target.addr, *probe)).or_default();
+            lanes.push((target, *probe, *count));
+            *count += 1;
+        }
+    }
+    lanes
+        .into_iter()
+        .map(|(target, probe, lane)| {
+            let stride = counts[&(target.addr, probe)].next_power_of_two();
+            (target, probe, lane, stride)
+        })
+        .collect()
+}
+
+/// The details the output task attaches to the reports and summaries of `targets`, each row's
+/// under its own key.
+fn annotate(
+    cli: &Cli,
+    pacing: &Pacing,
+    targets: &[Target],
+    is_broadcast: impl Fn(&Target) -> bool,
+) -> Annotations {
+    let mut annotations = Annotations::default();
+    for (target, probe, lane, stride) in lanes(targets) {
+        // each row's loops report under their own instance, so each row keeps its own details
+        let key = (target.addr, probe, (stride > 1).then_some(lane));
+        annotations.keys.push(key);
+        let interval = target.interval.unwrap_or_default();
+        if matches!(pacing, Pacing::Fixed { .. }) && !cli.mtu_sweep && !interval.is_zero() {
+            annotations
+                .requested_rates
+                .insert(key, 1.0 / interval.as_secs_f64());
+        }
+        if is_broadcast(target) {
+            annotations.broadcast.insert(target.addr);
+        }
+        if let Some(label) = &target.label {
+            annotations.labels.insert(key, label.clone());
+        }
+        if let Some(group) = &target.group {
+            annotations.groups.insert(key, group.clone());
+        }
+        if let Some(rtt_warn) = target.rtt_warn.or(cli.rtt_warn) {
+            annotations.rtt_warns.insert(key, rtt_warn);
+        }
+        if !cli.flood {
+            annotations.late_thresholds.insert(key, interval / 2);
+        }
+    }
+    annotations
+}
+
+/// The probes per second one of a target's loops asks for, and the length of the frames it sends
+/// them in.
+struct Load<'a> {
+    target: &'a Target,
+    probe: ProbeType,
+    rate: f64,
+    frame_len: usize,
+}
+
+/// Warnings about `loads` that ask for more than will be sent, with replies lost for `timeout`:
+/// targets whose probes could outnumber --max-in-flight before the first of them times out,
+/// interfaces whose targets' probes could outnumber their --concurrent-probes, and targets asking
+/// for more probes or bandwidth in all than --max-pps or --bandwidth allows.
+fn rate_warnings(cli: &Cli, timeout: Duration, loads: &[Load]) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let mut pools: Vec<(&Option<String>, ProbeType, f64)> = Vec::new();
+    let (mut total, mut bits) = (0.0, 0.0);
+    for load in loads {
+        let awaiting = (load.rate * timeout.as_secs_f64()).ceil();
+        if awaiting > f64::from(cli.max_in_flight) {
+            warnings.push(format!(
+                "target {} asks for {:.1} {} pps, but with replies lost it would have up to \
+                 {awaiting} probes awaiting them within --icmp-timeout, more than \
+                 --max-in-flight {} allows; it will be probed more slowly then",
+                load.target.addr, load.rate, load.probe, cli.max_in_flight
+            ));
+        }
+        let interface = &load.target.interface;
+        match pools
+            .iter_mut()
+            .find(|(i, probe, _)| *i == interface && *probe == load.probe)
+        {
+            Some((_, _, rate)) => *rate += load.rate,
+            None => pools.push((interface, load.probe, load.rate)),
+        }
+        total += load.rate;
+        bits += load.rate * load.frame_len as f64 * 8.0;
+    }
+    // each interface and kind of probe has a pool of its own
+    for (interface, probe, rate) in pools {
+        let in_flight = (rate * timeout.as_secs_f64()).ceil();
+        if in_flight > cli.concurrent_probes as f64 {
+            warnings.push(format!(
+                "targets on {} ask for {rate:.1} {probe} pps in all, but with replies lost up to \
+                 {in_flight} probes would be in flight within --icmp-timeout, more than \
+                 --concurrent-probes {} allows; they will be probed more slowly then",
+                interface.as_deref().unwrap_or("the default interface"),
+                cli.concurrent_probes
+            ));
+        }
+    }
+    if let Some(max_pps) = cli.max_pps {
+        if total > f64::from(max_pps) {
+            warnings.push(format!(
+                "targets ask for {total:.1} pps in all, more than --max-pps {max_pps} allows; \
+                 they will be probed more slowly"
+            ));
+        }
+    }
+    if let Some(bandwidth) = cli.bandwidth {
+        if bits > bandwidth as f64 {
+            warnings.push(format!(
+                "targets ask for {bits:.0}bps in all, more than --bandwidth {bandwidth}bps \
+                 allows; they will be probed more slowly"
+            ));
+        }
+    }
+    warnings
+}
+
+/// Offset of the first probe of the `i`th of `started` loops started together, spreading their
+/// first probes evenly over `interval`.
+fn stagger_offset(interval: Duration, i: usize, started: usize) -> Duration {
+    interval.mul_f64(i as f64 / started as f64)
+}
+
+/// A random number from the kernel's entropy pool.
+fn random_u16() -> std::io::Result<u16> {
+    Ok(u16::from_ne_bytes(prober::random_bytes()?))
+}
+
+/// The SplitMix64 generator. Start offsets needn't be unpredictable, only reproducible from a
+/// seed, which the kernel's entropy pool can't offer.
+#[derive(Debug)]
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in [0, 1), from the top 53 bits so that every value is exactly representable.
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Determines how a target's probes are spaced out over time.
+#[derive(Clone, Copy, Debug)]
+enum Pacing {
+    /// Send probes at the target's configured interval, catching up on ticks missed by slow
+    /// sends according to `missed_ticks`.
+    Fixed { missed_ticks: MissedTicks },
+
+    /// Double the interval (up to `max_interval`) while probes keep timing out and reset it to the
+    /// target's configured interval on the next reply.
+    Adaptive {
+        max_interval: Duration,
+        missed_ticks: MissedTicks,
+    },
+
+    /// Send probes at the target's configured interval, but never before the previous one has
+    /// completed.
+    Serial,
+
+    /// Ignore the configured interval and send the next probe as soon as the previous one
+    /// completes, but no sooner than `min_gap` after the previous send.
+    Flood { min_gap: Duration },
+}
+
+/// The interval of a target in `Pacing::Adaptive`, which doubles once per interval in which
+/// further probes were lost, however many, and resets on the next reply.
+#[derive(Debug)]
+struct Backoff {
+    base: Duration,
+    ceiling: Duration,
+    interval: Duration,
+
+    /// Probes lost since the last reply.
+    losses: u32,
+
+    /// Whether probes have been lost since the interval was last computed.
+    lost: bool,
+}
+
+impl Backoff {
+    fn new(base: Duration, max_interval: Duration) -> Self {
+        Self {
+            base,
+            ceiling: max_interval.max(base),
+            interval: base,
+            losses: 0,
+            lost: false,
+        }
+    }
+
+    /// Record how a probe completed, returning whether it ended a backoff.
+    fn record(&mut self, status: ProbeStatus) -> bool {
+        match status {
+            ProbeStatus::Replied => {
+                let backed_off = self.interval > self.base;
+                self.interval = self.base;
+                self.losses = 0;
+                self.lost = false;
+                backed_off
+            }
+            ProbeStatus::Redirected => false,
+            ProbeStatus::TimedOut
+            | ProbeStatus::SendFailed
+            | ProbeStatus::TtlExpired
+            | ProbeStatus::Corrupted
+            | ProbeStatus::Truncated
+            | ProbeStatus::TooBig => {
+                self.losses += 1;
+                self.lost = true;
+                false
+            }
+        }
+    }
+
+    /// The interval to wait after the probe about to be sent.
+    fn next_interval(&mut self) -> Duration {
+        if std::mem::take(&mut self.lost) {
+            self.interval = (self.interval * 2).min(self.ceiling);
+            tracing::debug!(
+                "{} consecutive probes lost, backing off to {:?}",
+                self.losses,
+                self.interval
+            );
+        }
+        self.interval
+    }
+}
+
+/// Find the path MTU to `target` by binary searching, one echo request at a time, for the largest
+/// payload that's answered, starting `offset` after the loop starts. Requests are spaced at least
+/// the target's interval apart. Replies smaller than the minimum request are assumed to get
+/// through; if even those don't, no MTU is reported.
+#[tracing::instrument(name = "target", skip_all, fields(addr = %target.addr, probe = "mtu-sweep"))]
+async fn mtu_sweep(target: Target, sender: ACSender<ProbeRequest>, sweep: Sweep, offset: Duration) {
+    let header_len = icmp::echo_packet_len(sweep.record_route, 0);
+    let (mut fits, mut too_big) = (
+        usize::from(MIN_PAYLOAD_SIZE),
+        sweep.max_packet_len.saturating_sub(header_len) + 1,
+    );
+    let interval = target.interval.unwrap_or_default();
+    let mut next = Instant::now() + offset;
+    let mut index = 0;
+    // the first probe checks that the smallest request is answered at all
+    let mut payload_size = fits;
+    loop {
+        tokio::time::sleep_until(next).await;
+        next += interval;
+        if let Some(limiter) = &sweep.limiter {
+            limiter
+                .acquire(sweep.link_header_len + header_len + payload_size)
+                .await;
+        }
+        let (status_sender, mut status_receiver) = unbounded_channel();
+        let request = ProbeRequest {
+            tparams: TargetParams {
+                addr: target.addr,
+                seq: index as u16,
+                probe: ProbeType::Echo,
+            },
+            index,
+            interval: None,
+            notify: Some(status_sender),
+            broadcast: false,
+            warmup: false,
+            instance: None,
+            send_options: SendOptions {
+                dscp: target.dscp.unwrap_or_default(),
+                payload_size,
+                dont_fragment: true,
+            },
+            scheduled: Some(Instant::now().into_std()),
+            overran: false,
+            slot: None,
+            span: tracing::Span::current(),
+        };
+        if let Err(e) = sender.send(request).await {
+            tracing::error!("error sending target to ProbeTasks: {e}");
+            return;
+        }
+        index += 1;
+        let status = status_receiver.recv().await;
+        tracing::debug!("{}-byte packet: {status:?}", header_len + payload_size);
+        match status {
+            Some(ProbeStatus::Replied) => fits = payload_size,
+            Some(ProbeStatus::SendFailed) => {
+                eprintln!("{} path MTU sweep stopped: sending failed", target.addr);
+                return;
+            }
+            // a host that silently drops oversized requests looks the same as one reporting them
+            _ if payload_size > fits => too_big = payload_size,
+            _ => {
+                eprintln!(
+                    "{} path MTU unknown: even {}-byte packets went unanswered",
+                    target.addr,
+                    header_len + payload_size
+                );
+                return;
+            }
+        }
+        if too_big - fits <= 1 {
+            break;
+        }
+        payload_size = fits + (too_big - fits) / 2;
+    }
+    eprintln!(
+        "{} path MTU {} bytes, found with {index} probes",
+        target.addr,
+        header_len + fits
+    );
+}
+
+/// Schedule `probe` probes for a single target, sending each `ProbeRequest` to the `Prober`
+/// according to the given `Pacing`. Probes to broadcast targets collect replies from every responding host.
+///
+/// `sequencing.warmup` additional probes are sent first, at the same pacing, with wire sequence
+/// numbers counting up to (but excluding) `sequencing.initial` so that they can't be confused
+/// with counted probes.
+///
+/// The first probe is sent `offset` after the loop starts, and the rest follow at the pacing from
+/// there.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(name = "target", skip_all, fields(addr = %target.addr, %probe))]
+async fn run_target(
+    target: Target,
+    probe: ProbeType,
+    sender: ACSender<ProbeRequest>,
+    throttle: Throttle,
+    pacing: Pacing,
+    broadcast: bool,
+    sequencing: Sequencing,
+    offset: Duration,
+) {
+    let send_options = SendOptions {
+        dscp: target.dscp.unwrap_or_default(),
+        payload_size: usize::from(target.payload_size.unwrap_or_default()),
+        dont_fragment: target.dont_fragment.unwrap_or_default(),
+    };
+    let base_interval = target.interval.unwrap_or_default();
+    let mut interval = base_interval;
+    let mut backoff = match pacing {
+        Pacing::Adaptive { max_interval, .. } => Some(Backoff::new(base_interval, max_interval)),
+        _ => None,
+    };
+    let (status_sender, mut status_receiver) = unbounded_channel();
+    if !offset.is_zero() {
+        tracing::debug!(
+            "{} {probe} probes start {offset:?} into their interval",
+            target.addr
+        );
+    }
+    let mut next = Instant::now() + offset;
+    let mut last_sent = None;
+    let mut overran = false;
+
+    let warmups = (0..u64::from(sequencing.warmup)).map(|i| (i, true));
+    for (i, is_warmup) in warmups.chain((0..target.count.unwrap_or_default()).map(|i| (i, false))) {
+        match &mut backoff {
+            // take statuses as they arrive, so that a reply ends a backoff without first waiting
+            // out the backed off interval
+            Some(backoff) => loop {
+                tokio::select! {
+                    _ = tokio::time::sleep_until(next) => break,
+                    Some(status) = status_receiver.recv() => {
+                        if let (true, Some(last_sent)) = (backoff.record(status), last_sent) {
+                            next = next.min(last_sent + base_interval);
+                        }
+                    }
+                }
+            },
+            None => tokio::time::sleep_until(next).await,
+        }
+        if let Some(backoff) = &mut backoff {
+            interval = backoff.next_interval();
+        }
+
+        if throttle.in_flight.available_permits() == 0 {
+            tracing::debug!(
+                "{} has the maximum number of probes in flight, waiting for one to complete",
+                target.addr
+            );
+        }
+        let slot = match throttle.in_flight.clone().acquire_owned().await {
+            Ok(slot) => slot,
+            // the semaphore is never closed
+            Err(_) => return,
+        };
+        if let Some(limiter) = &throttle.limiter {
+            limiter.acquire(throttle.frame_len).await;
+        }
+        // ICMP sequence numbers are only 16 bits wide, so wrap around
+        let seq = if is_warmup {
+            (i as u16).wrapping_sub(sequencing.warmup)
+        } else {
+            i as u16
+        }
+        .wrapping_add(sequencing.initial)
+        .wrapping_mul(sequencing.stride)
+        .wrapping_add(sequencing.lane);
+        let instance = (sequencing.stride > 1).then_some(sequencing.lane);
+        let tparams = TargetParams {
+            addr: target.addr,
+            seq,
+            probe,
+        };
+        let sent_at = Instant::now();
+        last_sent = Some(sent_at);
+
+        match pacing {
+            Pacing::Fixed { missed_ticks } | Pacing::Adaptive { missed_ticks, .. } => {
+                let adaptive = matches!(pacing, Pacing::Adaptive { .. });
+                let request = ProbeRequest {
+                    tparams,
+                    index: i,
+                    interval: adaptive.then_some(interval),
+                    notify: adaptive.then(|| status_sender.clone()),
+                    broadcast,
+                    warmup: is_warmup,
+                    instance,
+                    send_options,
+                    scheduled: Some(next.into_std()),
+                    overran,
+                    slot: Some(slot),
+                    span: tracing::Span::current(),
                 };
-                if let Err(e) = sender.send(tparams).await {
-                    log::error!("error sending target to ProbeTasks: {e}");
+                if let Err(e) = sender.send(request).await {
+                    tracing::error!("error sending target to ProbeTasks: {e}");
+                }
+                next += interval;
+                let now = Instant::now();
+                overran = !interval.is_zero() && now > next;
+                if overran {
+                    tracing::trace!(
+                        "{} {probe} probe overran its interval by {:?}",
+                        target.addr,
+                        now - next
+                    );
+                    next = match missed_ticks {
+                        MissedTicks::Burst => next,
+                        MissedTicks::Delay => now,
+                        // the first tick yet to come
+                        MissedTicks::Skip => {
+                            let behind = (now - next).as_nanos() % interval.as_nanos();
+                            now + interval - Duration::from_nanos(behind as u64)
+                        }
+                    };
                 }
             }
-        });
+            Pacing::Serial | Pacing::Flood { .. } => {
+                // use a fresh channel per probe so that a probe that fails to send (and therefore
+                // never reports a status) doesn't stall the loop
+                let (status_sender, mut status_receiver) = unbounded_channel();
+                let request = ProbeRequest {
+                    tparams,
+                    index: i,
+                    interval: None,
+                    notify: Some(status_sender),
+                    broadcast,
+                    warmup: is_warmup,
+                    instance,
+                    send_options,
+                    scheduled: Some(next.into_std()),
+                    overran: false,
+                    slot: Some(slot),
+                    span: tracing::Span::current(),
+                };
+                if let Err(e) = sender.send(request).await {
+                    tracing::error!("error sending target to ProbeTasks: {e}");
+                }
+                status_receiver.recv().await;
+                next = match pacing {
+                    Pacing::Flood { min_gap } => sent_at + min_gap,
+                    // a probe that outlasted the interval delays the rest rather than letting
+                    // them catch up in a burst
+                    _ => (next + interval).max(Instant::now()),
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(list: &str) -> Result<Vec<Target>> {
+        let defaults = TargetDefaults {
+            count: Some(1),
+            interval: Duration::from_secs(1),
+        };
+        let limits = Limits {
+            min_interval: Duration::ZERO,
+            max_interval: None,
+            max_count: None,
+        };
+        parse_targets(list, &defaults, &limits, false).map(|(targets, _)| targets)
     }
 
-    while set.join_next().await.is_some() {}
+    fn labels(list: &str) -> Vec<Option<String>> {
+        parse(list)
+            .unwrap()
+            .into_iter()
+            .map(|target| target.label)
+            .collect()
+    }
 
-    log::debug!("closing target sender");
-    target_sender.close();
+    #[test]
+    fn labels_are_optional_and_trimmed() {
+        assert_eq!(
+            labels("10.0.0.1;10.0.0.2,1,100,,,site=fra1;10.0.0.3,1,100,,, site=ams1 ,46"),
+            vec![None, Some("site=fra1".into()), Some("site=ams1".into())]
+        );
+    }
 
-    log::debug!("awaiting probe tasks finish");
-    probe_tasks_fut.await??;
+    #[test]
+    fn quoted_labels_may_contain_delimiters() {
+        assert_eq!(
+            labels(r#"10.0.0.1,1,100,,,"site=fra1,rack=b2";10.0.0.2,1,100,,,"a;b",46"#),
+            vec![Some("site=fra1,rack=b2".into()), Some("a;b".into())]
+        );
+        // the quoted semicolon doesn't end the row, so the DSCP column still follows the label
+        assert_eq!(
+            parse(r#"10.0.0.2,1,100,,,"a;b",46"#).unwrap()[0].dscp,
+            Some(46)
+        );
+    }
 
-    log::debug!("awaiting output handling task finish");
-    output_handling_fut.await?;
+    #[test]
+    fn doubled_quotes_within_quoted_labels_are_escaped_quotes() {
+        assert_eq!(
+            labels(r#"10.0.0.1,10,100,,,"site=fra1,rack=""b2""""#),
+            vec![Some(r#"site=fra1,rack="b2""#.into())]
+        );
+    }
 
-    Ok(())
+    #[test]
+    fn unquoted_comma_in_label_spills_into_next_column() {
+        // `rack=b2` lands in the DSCP column
+        assert!(parse("10.0.0.1,1,100,,,site=fra1,rack=b2").is_err());
+    }
+
+    #[test]
+    fn quoted_labels_survive_comments_and_newlines() {
+        let list = "# inventory\n10.0.0.1,1,100,,,\"site=fra1, rack=b2\"\n\n10.0.0.2\n";
+        assert_eq!(labels(list), vec![Some("site=fra1, rack=b2".into()), None]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn staggered_targets_send_at_distinct_times() {
+        let interval = Duration::from_millis(100);
+        let targets = parse("10.0.0.1,3,100;10.0.0.2,3,100;10.0.0.3,3,100;10.0.0.4,3,100").unwrap();
+        let (sender, receiver) = async_channel::unbounded();
+        let start = Instant::now();
+        let count = targets.len();
+        for (i, target) in targets.into_iter().enumerate() {
+            let throttle = Throttle {
+                limiter: None,
+                frame_len: 0,
+                in_flight: Arc::new(Semaphore::new(16)),
+            };
+            let sequencing = Sequencing {
+                initial: 0,
+                warmup: 0,
+                lane: 0,
+                stride: 1,
+            };
+            tokio::spawn(run_target(
+                target,
+                ProbeType::Echo,
+                sender.clone(),
+                throttle,
+                Pacing::Fixed {
+                    missed_ticks: MissedTicks::Skip,
+                },
+                false,
+                sequencing,
+                stagger_offset(interval, i, count),
+            ));
+        }
+        drop(sender);
+
+        let mut sends: HashMap<Ipv4Addr, Vec<Duration>> = HashMap::new();
+        while let Ok(request) = receiver.recv().await {
+            sends
+                .entry(request.tparams.addr)
+                .or_default()
+                .push(start.elapsed());
+        }
+        let mut firsts: Vec<Duration> = sends.values().map(|times| times[0]).collect();
+        firsts.sort();
+        // the first probes are a quarter of the interval apart rather than all at once
+        assert_eq!(
+            firsts,
+            (0..4u32).map(|i| interval * i / 4).collect::<Vec<_>>()
+        );
+        // while each target's probes are still an interval apart
+        for times in sends.values() {
+            assert_eq!(times.len(), 3);
+            assert_eq!(times[1] - times[0], interval);
+            assert_eq!(times[2] - times[1], interval);
+        }
+    }
+
+    #[test]
+    fn stagger_offsets_spread_over_interval() {
+        let interval = Duration::from_secs(1);
+        assert_eq!(stagger_offset(interval, 0, 1), Duration::ZERO);
+        let offsets: Vec<_> = (0..5).map(|i| stagger_offset(interval, i, 5)).collect();
+        assert_eq!(
+            offsets,
+            [0, 200, 400, 600, 800].map(Duration::from_millis).to_vec()
+        );
+    }
+
+    #[test]
+    fn rows_probing_the_same_address_take_their_own_lanes() {
+        let mut targets = parse("10.0.0.1,1,100;10.0.0.2;10.0.0.1,1,1000;10.0.0.1,1,100").unwrap();
+        for target in &mut targets {
+            target.probes = vec![ProbeType::Echo];
+        }
+        targets[0].probes.push(ProbeType::Timestamp);
+        let lanes: Vec<_> = lanes(&targets)
+            .into_iter()
+            .map(|(target, probe, lane, stride)| (target.addr, probe, lane, stride))
+            .collect();
+        let a = Ipv4Addr::new(10, 0, 0, 1);
+        let b = Ipv4Addr::new(10, 0, 0, 2);
+        assert_eq!(
+            lanes,
+            vec![
+                (a, ProbeType::Echo, 0, 4),
+                (a, ProbeType::Timestamp, 0, 1),
+                (b, ProbeType::Echo, 0, 1),
+                (a, ProbeType::Echo, 1, 4),
+                // identical rows still get a lane each
+                (a, ProbeType::Echo, 2, 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn rows_probing_the_same_address_keep_their_own_details() {
+        let cli = Cli::try_parse_from(["pingers", "10.0.0.1"]).unwrap();
+        let mut targets = parse(
+            "10.0.0.1,10,100,,,site=fra1,,edge,20ms;10.0.0.1,10,1000,,,site=ams1,,core,200ms",
+        )
+        .unwrap();
+        for target in &mut targets {
+            target.probes = vec![ProbeType::Echo];
+        }
+        let pacing = Pacing::Fixed {
+            missed_ticks: MissedTicks::Skip,
+        };
+        let annotations = annotate(&cli, &pacing, &targets, |_| false);
+
+        let addr = Ipv4Addr::new(10, 0, 0, 1);
+        let (fast, slow) = (
+            (addr, ProbeType::Echo, Some(0)),
+            (addr, ProbeType::Echo, Some(1)),
+        );
+        assert_eq!(annotations.keys, vec![fast, slow]);
+        assert_eq!(annotations.labels[&fast], "site=fra1");
+        assert_eq!(annotations.labels[&slow], "site=ams1");
+        assert_eq!(annotations.groups[&fast], "edge");
+        assert_eq!(annotations.groups[&slow], "core");
+        assert_eq!(annotations.rtt_warns[&fast], Duration::from_millis(20));
+        assert_eq!(annotations.rtt_warns[&slow], Duration::from_millis(200));
+        assert_eq!(
+            annotations.late_thresholds[&fast],
+            Duration::from_millis(50)
+        );
+        assert_eq!(
+            annotations.late_thresholds[&slow],
+            Duration::from_millis(500)
+        );
+    }
+
+    /// The loads of `targets`, each sending `frame_len` byte echo requests at its interval.
+    fn loads(targets: &[Target], frame_len: usize) -> Vec<Load<'_>> {
+        targets
+            .iter()
+            .map(|target| Load {
+                target,
+                probe: ProbeType::Echo,
+                rate: 1.0 / target.interval.unwrap().as_secs_f64(),
+                frame_len,
+            })
+            .collect()
+    }
+
+    fn rate_warnings_with(args: &[&str], targets: &str) -> Vec<String> {
+        let cli = Cli::try_parse_from([&["pingers"], args, &["10.0.0.1"]].concat()).unwrap();
+        let targets = parse(targets).unwrap();
+        rate_warnings(&cli, Duration::from_secs(1), &loads(&targets, 98))
+    }
+
+    #[test]
+    fn feasible_rates_are_not_warned_about() {
+        let args = [
+            "--max-pps",
+            "20",
+            "--bandwidth",
+            "16kbps",
+            "--concurrent-probes",
+            "20",
+        ];
+        let warnings = rate_warnings_with(&args, "10.0.0.1,1,100;10.0.0.2,1,100");
+        assert_eq!(warnings, Vec::<String>::new());
+    }
+
+    #[test]
+    fn rates_beyond_each_cap_are_warned_about() {
+        let targets = "10.0.0.1,1,10;10.0.0.2,1,10";
+        let warnings = rate_warnings_with(&["--max-in-flight", "50"], targets);
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("--max-in-flight 50"));
+
+        // 200 pps over a 1s timeout
+        let warnings = rate_warnings_with(&["--concurrent-probes", "150"], targets);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("the default interface"));
+        assert!(warnings[0].contains("--concurrent-probes 150"));
+
+        let warnings = rate_warnings_with(&["--max-pps", "150"], targets);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("--max-pps 150"));
+
+        // 200 pps of 98 byte frames
+        let warnings = rate_warnings_with(&["--bandwidth", "150kbps"], targets);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("156800bps"));
+    }
+
+    #[test]
+    fn interfaces_have_pools_of_their_own() {
+        let targets = "10.0.0.1,1,10,eth0;10.0.0.2,1,10,eth1";
+        let warnings = rate_warnings_with(&["--concurrent-probes", "150"], targets);
+        assert_eq!(warnings, Vec::<String>::new());
+    }
+
+    #[test]
+    fn rate_per_target_sets_the_default_interval() {
+        let matches = Cli::command()
+            .try_get_matches_from(["pingers", "--rate-per-target", "20", "10.0.0.1"])
+            .unwrap();
+        let mut cli = Cli::from_arg_matches(&matches).unwrap();
+        apply_defaults(&mut cli, &matches, Defaults::default()).unwrap();
+        assert_eq!(cli.default_interval, Duration::from_millis(50));
+
+        // rather than being silently overridden by the config file's
+        let defaults = Defaults {
+            default_interval: Some(Duration::from_secs(2)),
+            ..Defaults::default()
+        };
+        let mut cli = Cli::from_arg_matches(&matches).unwrap();
+        assert!(apply_defaults(&mut cli, &matches, defaults).is_err());
+
+        let rejected = Cli::command().try_get_matches_from([
+            "pingers",
+            "--rate-per-target",
+            "20",
+            "--default-interval",
+            "1s",
+            "10.0.0.1",
+        ]);
+        assert!(rejected.is_err());
+    }
+
+    #[test]
+    fn backoff_doubles_once_per_interval_of_losses() {
+        let base = Duration::from_millis(10);
+        let mut backoff = Backoff::new(base, Duration::from_millis(100));
+        assert_eq!(backoff.next_interval(), base);
+
+        // a burst of timeouts seen in one interval doubles it once
+        for _ in 0..5 {
+            backoff.record(ProbeStatus::TimedOut);
+        }
+        assert_eq!(backoff.next_interval(), base * 2);
+        assert_eq!(backoff.losses, 5);
+
+        // no further losses, no further backoff
+        assert_eq!(backoff.next_interval(), base * 2);
+
+        backoff.record(ProbeStatus::TimedOut);
+        assert_eq!(backoff.next_interval(), base * 4);
+        backoff.record(ProbeStatus::Redirected);
+        assert_eq!(backoff.next_interval(), base * 4);
+
+        // capped at the maximum interval
+        for _ in 0..3 {
+            backoff.record(ProbeStatus::TimedOut);
+            backoff.next_interval();
+        }
+        assert_eq!(backoff.next_interval(), Duration::from_millis(100));
+
+        assert!(backoff.record(ProbeStatus::Replied));
+        assert_eq!(backoff.losses, 0);
+        assert_eq!(backoff.next_interval(), base);
+        assert!(!backoff.record(ProbeStatus::Replied));
+    }
+
+    #[test]
+    fn backoff_ceiling_is_at_least_the_base_interval() {
+        let base = Duration::from_secs(20);
+        let mut backoff = Backoff::new(base, Duration::from_secs(10));
+        backoff.record(ProbeStatus::TimedOut);
+        assert_eq!(backoff.next_interval(), base);
+    }
 }
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::stream::TryStreamExt;
+use netlink_packet_route::rtnl::{constants as nlconsts, neighbour};
+use pnet::util::MacAddr;
+use rtnetlink::{Handle, IpVersion as NetlinkIpVersion};
+use socket2::{Domain, Socket, Type};
+use tokio::sync::{Mutex, OnceCell};
+use tokio::time::Instant;
+
+use crate::error::Result;
+
+/// How long a neighbor's address, or the failure to resolve it, is remembered.
+const NEIGHBOR_TTL: Duration = Duration::from_secs(60);
+
+/// How long to wait for the kernel to resolve a neighbor missing from its table.
+const RESOLUTION_TIMEOUT: Duration = Duration::from_secs(1);
+const RESOLUTION_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The UDP discard port, to which a datagram is sent to prompt the kernel to resolve a neighbor.
+const DISCARD_PORT: u16 = 9;
+
+/// Neighbor table states in which an entry's link-layer address can be used.
+const NUD_VALID: u16 = nlconsts::NUD_REACHABLE
+    | nlconsts::NUD_STALE
+    | nlconsts::NUD_DELAY
+    | nlconsts::NUD_PROBE
+    | nlconsts::NUD_PERMANENT
+    | nlconsts::NUD_NOARP;
+
+#[derive(Debug)]
+struct Entry {
+    /// Initialized by the first lookup; later lookups wait for it rather than resolving again.
+    mac: Arc<OnceCell<Option<MacAddr>>>,
+    created: Instant,
+}
+
+impl Entry {
+    fn new() -> Self {
+        Self {
+            mac: Arc::new(OnceCell::new()),
+            created: Instant::now(),
+        }
+    }
+}
+
+/// MAC addresses of the neighbors on one interface, shared by every probe sent from it.
+/// Addresses come from the kernel's neighbor table, and concurrent lookups of the same neighbor
+/// share a single resolution.
+#[derive(Debug)]
+pub struct NeighborCache {
+    handle: Handle,
+    interface_index: u32,
+    interface_name: String,
+    entries: Mutex<HashMap<Ipv4Addr, Entry>>,
+}
+
+impl NeighborCache {
+    pub fn new(handle: Handle, interface_index: u32, interface_name: String) -> Self {
+        Self {
+            handle,
+            interface_index,
+            interface_name,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The MAC address of the neighbor `addr`, or `None` if it couldn't be resolved.
+    pub async fn resolve(&self, addr: Ipv4Addr) -> Option<MacAddr> {
+        let mac = {
+            let mut entries = self.entries.lock().await;
+            let entry = entries.entry(addr).or_insert_with(Entry::new);
+            if entry.created.elapsed() > NEIGHBOR_TTL {
+                *entry = Entry::new();
+            }
+            entry.mac.clone()
+        };
+        *mac.get_or_init(|| self.lookup(addr)).await
+    }
+
+    async fn lookup(&self, addr: Ipv4Addr) -> Option<MacAddr> {
+        let deadline = Instant::now() + RESOLUTION_TIMEOUT;
+        let mut prompted = false;
+        loop {
+            match self.find(addr).await {
+                Ok(Some(mac)) => {
+                    tracing::debug!("resolved neighbor {addr} to {mac}");
+                    return Some(mac);
+                }
+                Ok(None) => (),
+                Err(e) => {
+                    tracing::debug!("failed to look up neighbor {addr}: {e}");
+                    return None;
+                }
+            }
+            if !prompted {
+                if let Err(e) = self.prompt_resolution(addr) {
+                    tracing::debug!("failed to prompt resolution of neighbor {addr}: {e}");
+                    return None;
+                }
+                prompted = true;
+            }
+            if Instant::now() >= deadline {
+                tracing::debug!("timed out resolving neighbor {addr}");
+                return None;
+            }
+            tokio::time::sleep(RESOLUTION_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Look up `addr` in the kernel's neighbor table.
+    async fn find(&self, addr: Ipv4Addr) -> Result<Option<MacAddr>> {
+        let mut neighbors = self
+            .handle
+            .neighbours()
+            .get()
+            .set_family(NetlinkIpVersion::V4)
+            .execute();
+
+        let mut found = None;
+        while let Some(msg) = neighbors.try_next().await? {
+            if msg.header.ifindex != self.interface_index || msg.header.state & NUD_VALID == 0 {
+                continue;
+            }
+            let destination = msg.nlas.iter().find_map(|nla| match nla {
+                neighbour::Nla::Destination(v) if v.len() == 4 => {
+                    Some(Ipv4Addr::new(v[0], v[1], v[2], v[3]))
+                }
+                _ => None,
+            });
+            if destination != Some(addr) {
+                continue;
+            }
+            // keep draining the dump so the netlink connection is left in a clean state
+            found = msg.nlas.iter().find_map(|nla| match nla {
+                neighbour::Nla::LinkLocalAddress(v) if v.len() == 6 => {
+                    Some(MacAddr(v[0], v[1], v[2], v[3], v[4], v[5]))
+                }
+                _ => None,
+            });
+        }
+        Ok(found)
+    }
+
+    /// Prompt the kernel to resolve `addr` by sending it an empty datagram through the IP stack.
+    fn prompt_resolution(&self, addr: Ipv4Addr) -> Result<()> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, None)?;
+        socket.set_nonblocking(true)?;
+        socket.bind_device(Some(self.interface_name.as_bytes()))?;
+        socket.send_to(&[], &SocketAddrV4::new(addr, DISCARD_PORT).into())?;
+        Ok(())
+    }
+}
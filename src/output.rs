@@ -0,0 +1,602 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, IsTerminal, Write};
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+
+use crossterm::style::{Color, Stylize};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::prober::{
+    format_route, Corruption, Probe, ProbeOutcome, ProbeOutput, ProbeReport, ProbeStatus,
+};
+use crate::target::ProbeType;
+
+/// Output formats selectable from the command line.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Comma-separated lines without a header, one per probe.
+    #[default]
+    Text,
+    /// Newline-delimited JSON objects, one per probe.
+    Json,
+    /// CSV with a header row, one record per probe.
+    Csv,
+}
+
+/// RTT beyond which text records of replies are shown in yellow, for targets without an RTT
+/// warning threshold.
+const SLOW_RTT: Duration = Duration::from_millis(100);
+
+/// When text output is colored.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorChoice {
+    /// When writing to a terminal and the NO_COLOR environment variable isn't set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether text written to `stream` should be colored.
+    pub fn enabled(self, stream: &impl IsTerminal) -> bool {
+        match self {
+            Self::Auto => stream.is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+            Self::Always => true,
+            Self::Never => false,
+        }
+    }
+}
+
+/// The color a report's text record is shown in, if any: red for probes that failed, and yellow
+/// for replies slower than the target's RTT warning threshold, or `SLOW_RTT` without one.
+fn color<P: Probe>(report: &ProbeReport<P>) -> Option<Color> {
+    match (report.outcome.status(), report.outcome.rtt()) {
+        (ProbeStatus::Replied, Some(rtt)) if rtt > report.rtt_warn.unwrap_or(SLOW_RTT) => {
+            Some(Color::Yellow)
+        }
+        (ProbeStatus::Replied | ProbeStatus::Redirected, _) => None,
+        _ => Some(Color::Red),
+    }
+}
+
+/// A report's `Display` representation, in its color if `colored`.
+fn text_record<P: Probe>(report: &ProbeReport<P>, colored: bool) -> String {
+    match color(report).filter(|_| colored) {
+        Some(color) => report.to_string().with(color).to_string(),
+        None => report.to_string(),
+    }
+}
+
+/// Receives every `ProbeReport` produced during a run.
+///
+/// Reports may be recorded from any task, so implementations must be thread-safe.
+pub trait OutputSink<P: Probe>: Send + Sync {
+    /// Record the outcome of a single probe.
+    fn record(&self, report: &ProbeReport<P>) -> Result<()>;
+
+    /// Flush any buffered records. Called once all probes have completed.
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Create the built-in `OutputSink` for `format` writing to `writer`.
+pub fn sink<P: Probe, W: Write + Send + 'static>(
+    format: OutputFormat,
+    writer: W,
+) -> Box<dyn OutputSink<P>> {
+    match format {
+        OutputFormat::Text => Box::new(TextSink::new(writer)),
+        OutputFormat::Json => Box::new(JsonSink::new(writer)),
+        OutputFormat::Csv => Box::new(CsvSink::new(writer)),
+    }
+}
+
+/// Size of the buffer that batched records written to stdout are collected in.
+const STDOUT_BATCH_SIZE: usize = 64 * 1024;
+
+/// Create the built-in `OutputSink` for `format` writing to stdout. Each record is flushed as
+/// soon as it's written, so that a consumer reading from a pipe sees probes as they complete,
+/// unless `batched`, in which case records are written out whenever a buffer fills. Batching
+/// saves a write per probe, which only matters at very high probe rates.
+pub fn stdout_sink<P: Probe>(format: OutputFormat, batched: bool) -> Box<dyn OutputSink<P>> {
+    let stdout = std::io::stdout();
+    match (format, batched) {
+        (_, true) => sink(format, BufWriter::with_capacity(STDOUT_BATCH_SIZE, stdout)),
+        // stdout itself flushes every line, but the CSV writer has a buffer of its own
+        (OutputFormat::Csv, false) => {
+            Box::new(CsvSink::with_flush_interval(stdout, Duration::ZERO))
+        }
+        (_, false) => sink(format, stdout),
+    }
+}
+
+/// A file that per-probe records are written to. New files are written under a temporary name
+/// in the same directory and renamed into place by `finish`, so that the file only ever appears
+/// complete; appended files are written in place.
+#[derive(Debug)]
+pub struct OutputFile {
+    path: PathBuf,
+    partial: Option<PathBuf>,
+}
+
+impl OutputFile {
+    /// Open `path`, or with `append` continue it, and return a sink writing `format` records to
+    /// it. CSV records appended to a non-empty file aren't preceded by another header row.
+    pub fn create<P: Probe>(
+        path: &Path,
+        format: OutputFormat,
+        append: bool,
+    ) -> Result<(Self, Box<dyn OutputSink<P>>)> {
+        if append {
+            let file = OpenOptions::new().append(true).create(true).open(path)?;
+            let header = file.metadata()?.len() == 0;
+            let writer = BufWriter::new(file);
+            let sink: Box<dyn OutputSink<P>> = match format {
+                OutputFormat::Csv if !header => Box::new(CsvSink::without_header(writer)),
+                format => sink(format, writer),
+            };
+            let output_file = Self {
+                path: path.to_path_buf(),
+                partial: None,
+            };
+            return Ok((output_file, sink));
+        }
+        let partial = partial_path(path)?;
+        let file = File::create(&partial)?;
+        let output_file = Self {
+            path: path.to_path_buf(),
+            partial: Some(partial),
+        };
+        Ok((output_file, sink(format, BufWriter::new(file))))
+    }
+
+    /// Move a newly written file into place. Its sink must have been flushed.
+    pub fn finish(self) -> Result<()> {
+        if let Some(partial) = &self.partial {
+            std::fs::rename(partial, &self.path)?;
+        }
+        Ok(())
+    }
+}
+
+/// The temporary name alongside `path` under which it's written before being moved into place.
+fn partial_path(path: &Path) -> Result<PathBuf> {
+    let file_name = path.file_name().ok_or_else(|| {
+        Error::GenericStringError(format!("{} is not a file path", path.display()))
+    })?;
+    Ok(path.with_file_name(format!(".{}.partial", file_name.to_string_lossy())))
+}
+
+/// Write `contents` to `path` under a temporary name alongside it and then move it into place,
+/// so that the file only ever appears complete.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    let partial = partial_path(path)?;
+    std::fs::write(&partial, contents)?;
+    std::fs::rename(&partial, path)?;
+    Ok(())
+}
+
+/// A flat, probe-agnostic representation of a `ProbeReport` used by structured sinks.
+#[derive(Debug, Serialize)]
+pub struct Record {
+    pub addr: Ipv4Addr,
+    pub seq: u64,
+    pub probe: ProbeType,
+    pub status: ProbeStatus,
+
+    /// Host other than the target that responded, eg the router at which the TTL expired or a host
+    /// answering a broadcast probe.
+    pub responder: Option<Ipv4Addr>,
+
+    /// Whether the responder replied in place of a unicast target, eg through NAT or anycast.
+    pub responded_by: bool,
+
+    /// Name of the responder found by reverse DNS, if requested and resolved in time.
+    pub hostname: Option<String>,
+    pub rtt_us: Option<u128>,
+    pub interval_us: Option<u128>,
+
+    /// Time from when the probe was scheduled until it was sent.
+    pub send_delay_ms: Option<f64>,
+
+    /// Number of times sending the probe failed with a transient error and was retried.
+    pub send_retries: u32,
+
+    /// When the probe was sent and its reply received, in nanoseconds since the Unix epoch. Both
+    /// are taken from the monotonic clock, so their differences aren't skewed by adjustments to
+    /// the system clock during the run.
+    pub sent_ns: Option<u128>,
+    pub received_ns: Option<u128>,
+
+    /// Space-separated addresses recorded by the Record Route IP option, if requested.
+    pub route: Option<String>,
+
+    /// Estimated offset of the target's clock from ours, for ICMP Timestamp probes.
+    pub clock_offset_ms: Option<i64>,
+
+    /// Gateway toward which a router redirected the probe, for redirects.
+    pub gateway: Option<Ipv4Addr>,
+
+    /// MTU of the next hop of a router that reported the probe too big to forward.
+    pub next_hop_mtu: Option<u16>,
+
+    /// Offset within the payload of the first byte at which a corrupted reply differed from the
+    /// request.
+    pub corrupted_offset: Option<usize>,
+
+    /// Bytes of payload carried back by a truncated reply.
+    pub truncated_len: Option<usize>,
+
+    /// DSCP value the probe was marked with.
+    pub dscp: u8,
+
+    /// ToS byte of the reply, which differs from the probe's if it was re-marked along the path.
+    pub reply_tos: Option<u8>,
+
+    /// TTL of the reply as it arrived, and the number of hops estimated from it.
+    pub reply_ttl: Option<u8>,
+    pub hops: Option<u8>,
+
+    /// Warm-up probes are excluded from summary statistics.
+    pub is_warmup: bool,
+
+    /// Which of several loops probing the same address sent the probe, if there are several.
+    pub instance: Option<u16>,
+
+    /// Free-form label given to the target in the target list.
+    pub label: Option<String>,
+
+    /// Whether the RTT exceeded the target's warning threshold, which timeouts do by definition.
+    pub rtt_exceeded: bool,
+}
+
+impl<P: Probe> From<&ProbeReport<P>> for Record {
+    fn from(report: &ProbeReport<P>) -> Self {
+        Self {
+            addr: report.tparams.addr,
+            seq: report.index,
+            probe: report.tparams.probe,
+            status: report.outcome.status(),
+            responder: report.responder(),
+            responded_by: report.responded_by().is_some(),
+            hostname: report.responder_name.clone(),
+            rtt_us: report.outcome.rtt().map(|rtt| rtt.as_micros()),
+            interval_us: report.interval.map(|interval| interval.as_micros()),
+            send_delay_ms: report.send_delay.map(|delay| delay.as_secs_f64() * 1000.0),
+            send_retries: report.send_retries,
+            sent_ns: report.sent_at.map(unix_nanos),
+            received_ns: match &report.outcome {
+                ProbeOutcome::ReceivedOutput(output, _) => output.received_at().map(unix_nanos),
+                _ => None,
+            },
+            route: match &report.outcome {
+                ProbeOutcome::ReceivedOutput(output, _) => output.route().map(format_route),
+                _ => None,
+            },
+            clock_offset_ms: match &report.outcome {
+                ProbeOutcome::ReceivedOutput(output, _) => output.clock_offset_ms(),
+                _ => None,
+            },
+            gateway: match &report.outcome {
+                ProbeOutcome::Notice(output) => output.gateway(),
+                _ => None,
+            },
+            next_hop_mtu: match &report.outcome {
+                ProbeOutcome::ReceivedOutput(output, _) => output.next_hop_mtu(),
+                _ => None,
+            },
+            corrupted_offset: match &report.outcome {
+                ProbeOutcome::ReceivedOutput(output, _) => match output.corruption() {
+                    Some(Corruption::Altered { offset }) => Some(offset),
+                    _ => None,
+                },
+                _ => None,
+            },
+            truncated_len: match &report.outcome {
+                ProbeOutcome::ReceivedOutput(output, _) => match output.corruption() {
+                    Some(Corruption::Truncated { len, .. }) => Some(len),
+                    _ => None,
+                },
+                _ => None,
+            },
+            dscp: report.dscp,
+            reply_tos: match &report.outcome {
+                ProbeOutcome::ReceivedOutput(output, _) => output.reply_tos(),
+                _ => None,
+            },
+            reply_ttl: match &report.outcome {
+                ProbeOutcome::ReceivedOutput(output, _) => output.reply_ttl(),
+                _ => None,
+            },
+            hops: match &report.outcome {
+                ProbeOutcome::ReceivedOutput(output, _) => output.hops(),
+                _ => None,
+            },
+            is_warmup: report.warmup,
+            instance: report.instance,
+            label: report.label.clone(),
+            rtt_exceeded: report.rtt_exceeded(),
+        }
+    }
+}
+
+/// Nanoseconds since the Unix epoch at `instant`, reckoned from a single reading of the system
+/// clock so that instants keep their monotonic spacing.
+fn unix_nanos(instant: Instant) -> u128 {
+    static ANCHOR: OnceLock<(Instant, u128)> = OnceLock::new();
+    let (anchor, anchor_ns) = *ANCHOR.get_or_init(|| {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        (Instant::now(), now.as_nanos())
+    });
+    match instant.checked_duration_since(anchor) {
+        Some(after) => anchor_ns + after.as_nanos(),
+        None => anchor_ns.saturating_sub(anchor.duration_since(instant).as_nanos()),
+    }
+}
+
+/// Writes each report's `Display` representation on its own line.
+#[derive(Debug)]
+pub struct TextSink<W: Write + Send> {
+    writer: Mutex<W>,
+
+    /// Whether failed probes and slow replies are highlighted with ANSI colors.
+    colored: bool,
+}
+
+impl<W: Write + Send> TextSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+            colored: false,
+        }
+    }
+
+    /// Create a `TextSink` that shows failed probes in red and slow replies in yellow.
+    pub fn colored(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+            colored: true,
+        }
+    }
+}
+
+impl<P: Probe, W: Write + Send> OutputSink<P> for TextSink<W> {
+    fn record(&self, report: &ProbeReport<P>) -> Result<()> {
+        let mut writer = self.writer.lock().map_err(|_| Error::OutputSinkPoisoned)?;
+        writeln!(writer, "{}", text_record(report, self.colored))?;
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        let mut writer = self.writer.lock().map_err(|_| Error::OutputSinkPoisoned)?;
+        Ok(writer.flush()?)
+    }
+}
+
+/// Where a line sorts among those printed by a `SortedSink`: by target position, address,
+/// instance of the row, whether it's for a counted rather than a warm-up probe, and probe index.
+type LineKey = (usize, Ipv4Addr, Option<u16>, bool, u64);
+
+/// Holds each report's `Display` representation back until the run completes, then writes them
+/// grouped by target in the given order, with each target's warm-up probes first and its probes
+/// in sequence order, timeouts included. Targets missing from the order, eg added by a reload,
+/// follow the rest by address.
+#[derive(Debug)]
+pub struct SortedSink<W: Write + Send> {
+    writer: Mutex<W>,
+    order: HashMap<Ipv4Addr, usize>,
+    colored: bool,
+
+    /// Lines by where they sort. Broadcast probes produce several lines per key, kept in the
+    /// order they completed.
+    lines: Mutex<BTreeMap<LineKey, Vec<String>>>,
+}
+
+impl<W: Write + Send> SortedSink<W> {
+    pub fn new(writer: W, targets: impl IntoIterator<Item = Ipv4Addr>, colored: bool) -> Self {
+        let mut order = HashMap::new();
+        for addr in targets {
+            let position = order.len();
+            order.entry(addr).or_insert(position);
+        }
+        Self {
+            writer: Mutex::new(writer),
+            order,
+            colored,
+            lines: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl<P: Probe, W: Write + Send> OutputSink<P> for SortedSink<W> {
+    fn record(&self, report: &ProbeReport<P>) -> Result<()> {
+        let addr = report.tparams.addr;
+        let position = self.order.get(&addr).copied().unwrap_or(usize::MAX);
+        let mut lines = self.lines.lock().map_err(|_| Error::OutputSinkPoisoned)?;
+        lines
+            .entry((
+                position,
+                addr,
+                report.instance,
+                !report.warmup,
+                report.index,
+            ))
+            .or_default()
+            .push(text_record(report, self.colored));
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        let mut lines = self.lines.lock().map_err(|_| Error::OutputSinkPoisoned)?;
+        let mut writer = self.writer.lock().map_err(|_| Error::OutputSinkPoisoned)?;
+        for line in std::mem::take(&mut *lines).into_values().flatten() {
+            writeln!(writer, "{line}")?;
+        }
+        Ok(writer.flush()?)
+    }
+}
+
+/// Writes each report as a JSON object on its own line.
+#[derive(Debug)]
+pub struct JsonSink<W: Write + Send> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Send> JsonSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+impl<P: Probe, W: Write + Send> OutputSink<P> for JsonSink<W> {
+    fn record(&self, report: &ProbeReport<P>) -> Result<()> {
+        let mut writer = self.writer.lock().map_err(|_| Error::OutputSinkPoisoned)?;
+        serde_json::to_writer(&mut *writer, &Record::from(report))?;
+        writeln!(writer)?;
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        let mut writer = self.writer.lock().map_err(|_| Error::OutputSinkPoisoned)?;
+        Ok(writer.flush()?)
+    }
+}
+
+/// Writes each report as a CSV record, preceded by a header row.
+#[derive(Debug)]
+pub struct CsvSink<W: Write + Send> {
+    writer: Mutex<CsvWriter<W>>,
+}
+
+#[derive(Debug)]
+struct CsvWriter<W: Write> {
+    inner: csv::Writer<W>,
+
+    /// Flush buffered records at least this often, if set.
+    flush_interval: Option<Duration>,
+    last_flush: Instant,
+}
+
+impl<W: Write + Send> CsvSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self::with_options(writer, None, true)
+    }
+
+    /// Create a `CsvSink` that omits the header row, eg when appending to a file that already
+    /// has one.
+    pub fn without_header(writer: W) -> Self {
+        Self::with_options(writer, None, false)
+    }
+
+    /// Create a `CsvSink` that flushes buffered records whenever `flush_interval` has elapsed
+    /// since the last flush, so that partial results survive a crash.
+    pub fn with_flush_interval(writer: W, flush_interval: Duration) -> Self {
+        Self::with_options(writer, Some(flush_interval), true)
+    }
+
+    fn with_options(writer: W, flush_interval: Option<Duration>, header: bool) -> Self {
+        Self {
+            writer: Mutex::new(CsvWriter {
+                inner: csv::WriterBuilder::new()
+                    .has_headers(header)
+                    .from_writer(writer),
+                flush_interval,
+                last_flush: Instant::now(),
+            }),
+        }
+    }
+}
+
+impl<P: Probe, W: Write + Send> OutputSink<P> for CsvSink<W> {
+    fn record(&self, report: &ProbeReport<P>) -> Result<()> {
+        let mut writer = self.writer.lock().map_err(|_| Error::OutputSinkPoisoned)?;
+        writer.inner.serialize(Record::from(report))?;
+        if let Some(flush_interval) = writer.flush_interval {
+            if writer.last_flush.elapsed() >= flush_interval {
+                writer.inner.flush()?;
+                writer.last_flush = Instant::now();
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        let mut writer = self.writer.lock().map_err(|_| Error::OutputSinkPoisoned)?;
+        writer.inner.flush()?;
+        writer.last_flush = Instant::now();
+        Ok(())
+    }
+}
+
+/// Prints a `.` for every probe and immediately erases it again with a backspace if the probe was
+/// answered, like `ping -f`. The number of dots left on screen is the number of lost probes.
+#[derive(Debug)]
+pub struct DotSink<W: Write + Send> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Send> DotSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+impl<P: Probe, W: Write + Send> OutputSink<P> for DotSink<W> {
+    fn record(&self, report: &ProbeReport<P>) -> Result<()> {
+        let mut writer = self.writer.lock().map_err(|_| Error::OutputSinkPoisoned)?;
+        match report.outcome.status() {
+            ProbeStatus::Replied => write!(writer, ".\x08")?,
+            // informational, so neither a reply nor a loss
+            ProbeStatus::Redirected => return Ok(()),
+            _ => write!(writer, ".")?,
+        }
+        Ok(writer.flush()?)
+    }
+
+    fn flush(&self) -> Result<()> {
+        let mut writer = self.writer.lock().map_err(|_| Error::OutputSinkPoisoned)?;
+        writeln!(writer)?;
+        Ok(writer.flush()?)
+    }
+}
+
+/// Forwards each report to every one of a collection of sinks.
+pub struct TeeSink<P: Probe> {
+    sinks: Vec<Box<dyn OutputSink<P>>>,
+}
+
+impl<P: Probe> TeeSink<P> {
+    pub fn new(sinks: Vec<Box<dyn OutputSink<P>>>) -> Self {
+        Self { sinks }
+    }
+}
+
+impl<P: Probe> OutputSink<P> for TeeSink<P> {
+    fn record(&self, report: &ProbeReport<P>) -> Result<()> {
+        for sink in &self.sinks {
+            sink.record(report)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        for sink in &self.sinks {
+            sink.flush()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use pnet::packet::ethernet::{EtherTypes, MutableEthernetPacket};
+use pnet::util::MacAddr;
+
+use crate::error::{Error, Result};
+
+const PCAP_MAGIC: u32 = 0xa1b2c3d4;
+const PCAP_VERSION_MAJOR: u16 = 2;
+const PCAP_VERSION_MINOR: u16 = 4;
+const PCAP_SNAPLEN: u32 = 65535;
+const LINKTYPE_ETHERNET: u32 = 1;
+const ETHERNET_HEADER_SIZE: usize = MutableEthernetPacket::minimum_packet_size();
+
+/// Writes Ethernet frames to a file in the classic libpcap format, readable by Wireshark and
+/// tcpdump.
+#[derive(Debug)]
+pub struct PcapWriter {
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl PcapWriter {
+    /// Create the file at `path`, truncating any existing file, and write the pcap file header.
+    pub fn create(path: &Path) -> Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(&PCAP_MAGIC.to_ne_bytes())?;
+        writer.write_all(&PCAP_VERSION_MAJOR.to_ne_bytes())?;
+        writer.write_all(&PCAP_VERSION_MINOR.to_ne_bytes())?;
+        // timezone offset and timestamp accuracy, both always zero in practice
+        writer.write_all(&0i32.to_ne_bytes())?;
+        writer.write_all(&0u32.to_ne_bytes())?;
+        writer.write_all(&PCAP_SNAPLEN.to_ne_bytes())?;
+        writer.write_all(&LINKTYPE_ETHERNET.to_ne_bytes())?;
+        Ok(Self {
+            writer: Mutex::new(writer),
+        })
+    }
+
+    /// Record an Ethernet frame timestamped with the current time.
+    pub fn write_frame(&self, frame: &[u8]) -> Result<()> {
+        self.write_record(&[frame])
+    }
+
+    /// Record an IPv4 packet that was sent or received without a link-layer header, eg on a raw
+    /// IPv4 socket or a point-to-point interface. A plausible Ethernet header with the given
+    /// addresses is synthesized.
+    pub fn write_ipv4_packet(
+        &self,
+        packet: &[u8],
+        source: MacAddr,
+        destination: MacAddr,
+    ) -> Result<()> {
+        let mut header = [0u8; ETHERNET_HEADER_SIZE];
+        {
+            let mut ethernet_packet =
+                MutableEthernetPacket::new(&mut header).expect("buffer is exactly a header long");
+            ethernet_packet.set_source(source);
+            ethernet_packet.set_destination(destination);
+            ethernet_packet.set_ethertype(EtherTypes::Ipv4);
+        }
+        self.write_record(&[&header, packet])
+    }
+
+    fn write_record(&self, parts: &[&[u8]]) -> Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let len: usize = parts.iter().map(|p| p.len()).sum();
+        let captured = len.min(PCAP_SNAPLEN as usize);
+
+        let mut writer = self.writer.lock().map_err(|_| Error::OutputSinkPoisoned)?;
+        writer.write_all(&(timestamp.as_secs() as u32).to_ne_bytes())?;
+        writer.write_all(&timestamp.subsec_micros().to_ne_bytes())?;
+        writer.write_all(&(captured as u32).to_ne_bytes())?;
+        writer.write_all(&(len as u32).to_ne_bytes())?;
+        let mut remaining = captured;
+        for part in parts {
+            let n = part.len().min(remaining);
+            writer.write_all(&part[..n])?;
+            remaining -= n;
+        }
+        Ok(())
+    }
+
+    /// Flush buffered records to the file.
+    pub fn flush(&self) -> Result<()> {
+        let mut writer = self.writer.lock().map_err(|_| Error::OutputSinkPoisoned)?;
+        Ok(writer.flush()?)
+    }
+}
@@ -1,27 +1,47 @@
-use std::collections::HashMap;
-use std::net::Ipv4Addr;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::os::fd::AsRawFd;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
 use async_channel::{Receiver as ACReceiver, Sender as ACSender};
 use async_trait::async_trait;
 use serde::Serialize;
 use socket2::{Domain, Protocol, SockAddr, Socket, Type};
-use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
-use tokio::sync::Mutex;
-use tokio_util::sync::CancellationToken;
+use tokio::sync::mpsc::{channel, Receiver, Sender, UnboundedSender};
+use tokio::sync::{Mutex, OwnedSemaphorePermit};
 use tokio::task::JoinSet;
 use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
 
 use crate::error::{Error, Result};
-use crate::ethernet::EthernetConf;
-use crate::socket::AsyncSocket;
+use crate::ethernet::{EthernetConf, LinkType};
+use crate::link::{LinkMonitor, Outage};
+use crate::pcap::PcapWriter;
+use crate::socket::{self, AsyncSocket, IoBackend, ProbeSocket};
+use crate::target::ProbeType;
+
+/// Number of `ProbeReport`s that may be buffered awaiting the consumer before `ProbeTask`s stop
+/// sending new probes.
+const OUTPUT_CHANNEL_CAPACITY: usize = 4096;
+
+/// Size of the buffer each packet is received into, enough for the largest IPv4 packet.
+const RECV_BUFFER_SIZE: usize = u16::MAX as usize;
 
 /// Parametes describing a single `Probe` target.
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct TargetParams {
     pub addr: Ipv4Addr,
+
+    /// Sequence number as sent on the wire; wraps around every 65536 probes.
     pub seq: u16,
+
+    /// Kind of probe, so that replies to different kinds of probe sent to the same address at
+    /// the same time can't be confused.
+    pub probe: ProbeType,
 }
 
 impl std::fmt::Display for TargetParams {
@@ -30,36 +50,527 @@ impl std::fmt::Display for TargetParams {
     }
 }
 
-pub enum ProbeReport<P: Probe> {
+/// Per-target settings applied to a request as it's sent, which unlike `TargetParams` play no
+/// part in identifying the probe a reply answers.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SendOptions {
+    /// DSCP value marked in the IPv4 header; the ECN bits are left zero.
+    pub dscp: u8,
+
+    /// Bytes of data carried by requests that have a payload, which replies must carry back in
+    /// full.
+    pub payload_size: usize,
+
+    /// Set the Don't Fragment flag, so that routers report requests too big for the next hop
+    /// rather than fragmenting them.
+    pub dont_fragment: bool,
+}
+
+/// What a `Prober` and its siblings mark their requests with, so that their replies can be told
+/// apart from replies to other processes' requests and from forgeries.
+#[derive(Clone, Copy, Debug)]
+pub struct RequestTag {
+    /// Random value that probes embed in their requests and replies must echo, so that replies
+    /// can't be forged by anyone who can't see the requests.
+    pub nonce: u32,
+
+    /// First of this process's identifiers, derived from its ID so that concurrent instances,
+    /// and other programs' pings, are unlikely to use the same ones. Any overlap is still caught
+    /// by the nonce.
+    identifier_base: u16,
+
+    /// Number of identifiers, one per probe slot.
+    identifier_count: u32,
+}
+
+impl RequestTag {
+    /// The identifier of requests sent by the probe in `slot`.
+    pub fn identifier(&self, slot: usize) -> u16 {
+        let offset = slot % self.identifier_count as usize;
+        self.identifier_base.wrapping_add(offset as u16)
+    }
+
+    /// Whether `identifier` is one of this process's.
+    pub fn owns(&self, identifier: u16) -> bool {
+        u32::from(identifier.wrapping_sub(self.identifier_base)) < self.identifier_count
+    }
+}
+
+impl fmt::Display for RequestTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let last = self.identifier(self.identifier_count as usize - 1);
+        write!(f, "identifiers {}..={last}", self.identifier_base)
+    }
+}
+
+/// A request for a `ProbeTask` to probe the target described by `tparams`.
+#[derive(Debug)]
+pub struct ProbeRequest {
+    pub tparams: TargetParams,
+
+    /// Logical index of the probe among all probes sent to the target. Unlike the wire sequence
+    /// number in `tparams` this never wraps around.
+    pub index: u64,
+
+    /// The effective interval of the target when this probe was scheduled; reported alongside
+    /// the probe output when set.
+    pub interval: Option<Duration>,
+
+    /// Notified of the `ProbeStatus` once the probe completes.
+    pub notify: Option<UnboundedSender<ProbeStatus>>,
+
+    /// Keep listening for the full timeout and report every distinct host that responds rather
+    /// than completing on the first reply. Used for broadcast and multicast targets.
+    pub broadcast: bool,
+
+    /// Whether this is a warm-up probe, sent ahead of the counted probes to prime caches along
+    /// the path and excluded from statistics.
+    pub warmup: bool,
+
+    /// Which of several loops probing the same address sent the probe, if there are several.
+    pub instance: Option<u16>,
+
+    pub send_options: SendOptions,
+
+    /// When the probe was meant to be sent, eg the tick of the target's interval. The probe is
+    /// reported with the delay between this and the end of the send.
+    pub scheduled: Option<Instant>,
+
+    /// Whether sending the target's previous probe took so long that this probe's tick had
+    /// already passed by the time it was done.
+    pub overran: bool,
+
+    /// Held until the probe completes, so that a semaphore can bound the number of a target's
+    /// probes awaiting replies at once.
+    pub slot: Option<OwnedSemaphorePermit>,
+
+    /// Span of the target loop that scheduled the probe, under which the probe's span is opened.
+    pub span: tracing::Span,
+}
+
+impl ProbeRequest {
+    /// Build a report of the given outcome of this request, sent at `sent_at`, `send_delay` after
+    /// it was scheduled, once `send_retries` failed sends had been retried.
+    fn report<P: Probe>(
+        &self,
+        outcome: ProbeOutcome<P>,
+        sent_at: Option<Instant>,
+        send_delay: Option<Duration>,
+        send_retries: u32,
+    ) -> ProbeReport<P> {
+        ProbeReport {
+            tparams: self.tparams.clone(),
+            index: self.index,
+            outcome,
+            interval: self.interval,
+            warmup: self.warmup,
+            instance: self.instance,
+            broadcast: self.broadcast,
+            dscp: self.send_options.dscp,
+            send_delay,
+            sent_at,
+            overran: self.overran,
+            send_retries,
+            responder_name: None,
+            label: None,
+            rtt_warn: None,
+        }
+    }
+}
+
+/// Summarizes how a probe completed without carrying its output.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProbeStatus {
+    Replied,
+    TimedOut,
+    SendFailed,
+    /// A router reported that the probe's TTL expired before it reached the target.
+    TtlExpired,
+    /// A router redirected the probe toward another gateway. This is informational and neither
+    /// answers nor fails the probe.
+    Redirected,
+    /// The reply's payload differed from the request's.
+    Corrupted,
+    /// The reply carried back only part of the request's payload.
+    Truncated,
+    /// A router reported that the probe needed fragmenting but had Don't Fragment set.
+    TooBig,
+}
+
+/// How the payload of a reply differed from that of its request.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Corruption {
+    /// The reply's payload first differed from the request's at byte `offset` of the payload.
+    Altered { offset: usize },
+    /// The reply carried back `len` of the request's `expected` bytes of payload.
+    Truncated { len: usize, expected: usize },
+}
+
+/// A packet accepted by `Probe::validate_response`.
+#[derive(Debug)]
+pub enum Response<O> {
+    /// A response about the probe with the given parameters.
+    Matched(TargetParams, O),
+    /// A reply of the kind answering `probe` from `addr` that's only `len` bytes, too short to
+    /// hold a whole reply, eg because a middlebox truncated it, so it can't be matched to a probe.
+    /// It still carries its sequence number if it's long enough to.
+    Short {
+        addr: Ipv4Addr,
+        probe: ProbeType,
+        seq: Option<u16>,
+        len: usize,
+    },
+}
+
+pub enum ProbeOutcome<P: Probe> {
     ReceivedOutput(P::Output, Duration),
-    TimedOut(TargetParams),
+    TimedOut,
+    SendFailed,
+    /// A response about the probe that neither answers nor fails it, eg an ICMP Redirect.
+    Notice(P::Output),
+}
+
+impl<P: Probe> ProbeOutcome<P> {
+    pub fn status(&self) -> ProbeStatus {
+        match self {
+            Self::ReceivedOutput(output, _) | Self::Notice(output) => output.status(),
+            Self::TimedOut => ProbeStatus::TimedOut,
+            Self::SendFailed => ProbeStatus::SendFailed,
+        }
+    }
+
+    pub fn rtt(&self) -> Option<Duration> {
+        match self {
+            Self::ReceivedOutput(_, rtt) => Some(*rtt),
+            Self::TimedOut | Self::SendFailed | Self::Notice(_) => None,
+        }
+    }
+}
+
+/// The outcome of probing the target described by `tparams`.
+pub struct ProbeReport<P: Probe> {
+    pub tparams: TargetParams,
+    pub index: u64,
+    pub outcome: ProbeOutcome<P>,
+    pub interval: Option<Duration>,
+    pub warmup: bool,
+
+    /// Which of several loops probing the same address sent the probe, if there are several.
+    pub instance: Option<u16>,
+
+    /// Whether the probe was sent to a broadcast or multicast target, which any host may answer.
+    pub broadcast: bool,
+
+    /// DSCP value the probe was marked with.
+    pub dscp: u8,
+
+    /// Time from when the probe was scheduled until it was sent, if scheduled. Large delays mean
+    /// local scheduling rather than the network held the probe up.
+    pub send_delay: Option<Duration>,
+
+    /// When the successful attempt to send the probe started, which its RTT is measured from,
+    /// if it was sent.
+    pub sent_at: Option<Instant>,
+
+    /// Whether the probe's tick passed while the target's previous probe was still being sent.
+    pub overran: bool,
+
+    /// Number of times sending the probe failed with a transient error, eg because the send
+    /// buffer was full, and was retried.
+    pub send_retries: u32,
+
+    /// Name of the responder, if it has been resolved.
+    pub responder_name: Option<String>,
+
+    /// The target's label, if it has one.
+    pub label: Option<String>,
+
+    /// The target's RTT warning threshold, if it has one.
+    pub rtt_warn: Option<Duration>,
+}
+
+impl<P: Probe> ProbeReport<P> {
+    /// The host that responded on the target's behalf, if other than the target itself; eg a
+    /// router reporting an error or one of many hosts answering a broadcast probe.
+    pub fn responder(&self) -> Option<Ipv4Addr> {
+        match &self.outcome {
+            ProbeOutcome::ReceivedOutput(output, _) | ProbeOutcome::Notice(output)
+                if output.source() != self.tparams.addr =>
+            {
+                Some(output.source())
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the probe's RTT exceeded the target's warning threshold. Timed-out probes exceed
+    /// it by definition.
+    pub fn rtt_exceeded(&self) -> bool {
+        match (self.rtt_warn, &self.outcome) {
+            (Some(_), ProbeOutcome::TimedOut) => true,
+            (Some(threshold), ProbeOutcome::ReceivedOutput(output, rtt)) => {
+                output.status() == ProbeStatus::Replied && *rtt > threshold
+            }
+            _ => false,
+        }
+    }
+
+    /// The host that replied in place of a unicast target, eg a NAT gateway or another instance
+    /// of an anycast address.
+    pub fn responded_by(&self) -> Option<Ipv4Addr> {
+        match &self.outcome {
+            ProbeOutcome::ReceivedOutput(output, _)
+                if !self.broadcast && output.status() == ProbeStatus::Replied =>
+            {
+                self.responder()
+            }
+            _ => None,
+        }
+    }
 }
 
 impl<P: Probe> std::fmt::Display for ProbeReport<P> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
-            Self::ReceivedOutput(output, duration) => {
-                write!(f, "{output},{}", duration.as_micros())
+        write!(f, "{},{}", self.tparams.addr, self.index)?;
+        match &self.outcome {
+            ProbeOutcome::ReceivedOutput(output, duration) => {
+                match output.corruption() {
+                    _ if output.status() == ProbeStatus::TtlExpired => write!(f, ",TTLEXPIRED")?,
+                    _ if output.status() == ProbeStatus::TooBig => match output.next_hop_mtu() {
+                        Some(mtu) => write!(f, ",TOOBIG for mtu {mtu}")?,
+                        None => write!(f, ",TOOBIG")?,
+                    },
+                    Some(Corruption::Altered { offset }) => {
+                        write!(f, ",CORRUPTED at byte {offset}")?
+                    }
+                    Some(Corruption::Truncated { len, expected }) => {
+                        write!(f, ",TRUNCATED to {len} of {expected} bytes")?
+                    }
+                    None => write!(f, ",{}", duration.as_micros())?,
+                }
+                if let Some(responder) = self.responder() {
+                    if self.responded_by().is_some() {
+                        write!(f, ",RESPONDED-BY {responder}")?;
+                    } else {
+                        write!(f, ",{responder}")?;
+                    }
+                    if let Some(name) = &self.responder_name {
+                        write!(f, " ({name})")?;
+                    }
+                }
+                if let Some(route) = output.route() {
+                    write!(f, ",route={}", format_route(route))?;
+                }
+                if let Some(offset) = output.clock_offset_ms() {
+                    write!(f, ",clock_offset={offset}ms")?;
+                }
+                if let Some(ttl) = output.reply_ttl() {
+                    write!(f, ",ttl={ttl}")?;
+                }
+                if let Some(hops) = output.hops() {
+                    write!(f, ",hops={hops}")?;
+                }
+                // only worth showing when the reply was re-marked along the way
+                if let Some(reply_dscp) = output.reply_tos().map(|tos| tos >> 2) {
+                    if reply_dscp != self.dscp {
+                        write!(f, ",reply_dscp={reply_dscp}")?;
+                    }
+                }
             }
-            Self::TimedOut(targetparams) => write!(f, "{targetparams},TIMEDOUT"),
+            ProbeOutcome::Notice(output) => match output.gateway() {
+                Some(gateway) => write!(f, ",REDIRECTED toward {gateway} by {}", output.source())?,
+                None => write!(f, ",NOTICE,{}", output.source())?,
+            },
+            ProbeOutcome::TimedOut => write!(f, ",TIMEDOUT")?,
+            ProbeOutcome::SendFailed => write!(f, ",SENDFAILED")?,
+        }
+        if let Some(interval) = self.interval {
+            write!(f, ",{}", interval.as_micros())?;
+        }
+        if self.warmup {
+            write!(f, ",WARMUP")?;
+        }
+        if let Some(instance) = self.instance {
+            write!(f, ",instance={instance}")?;
+        }
+        if self.send_retries > 0 {
+            write!(f, ",send_retries={}", self.send_retries)?;
         }
+        if self.dscp != 0 {
+            write!(f, ",dscp={}", self.dscp)?;
+        }
+        // echo is implied, keeping output for plain pings unchanged
+        if self.tparams.probe != ProbeType::Echo {
+            write!(f, ",probe={}", self.tparams.probe)?;
+        }
+        if let Some(label) = &self.label {
+            write!(f, ",label={label}")?;
+        }
+        if self.rtt_exceeded() {
+            write!(f, ",WARN")?;
+        }
+        Ok(())
     }
 }
 
+/// Random bytes from the kernel's entropy pool.
+pub fn random_bytes<const N: usize>() -> std::io::Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    // safe because the kernel writes at most `buf.len()` bytes into `buf`
+    let n = unsafe { libc::getrandom(buf.as_mut_ptr().cast(), buf.len(), 0) };
+    if n != buf.len() as isize {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(buf)
+}
+
+/// Format a recorded route as a space-separated list of addresses.
+pub fn format_route(route: &[Ipv4Addr]) -> String {
+    route
+        .iter()
+        .map(Ipv4Addr::to_string)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// RTT of a probe sent at `start` that `output` answered, measured to when the reply arrived
+/// rather than to when its probe task got around to it.
+fn rtt_since<O: ProbeOutput>(start: Instant, output: &O) -> Duration {
+    output
+        .received_at()
+        .map_or_else(|| start.elapsed(), |at| at.saturating_duration_since(start))
+}
+
+/// The monotonic instant at which a packet the kernel timestamped at `stamp` arrived. Kernel
+/// timestamps are read off the system clock, so they're placed by how long ago they were.
+fn arrival_instant(stamp: SystemTime) -> Instant {
+    let now = Instant::now();
+    let age = SystemTime::now().duration_since(stamp).unwrap_or_default();
+    now.checked_sub(age).unwrap_or(now)
+}
+
+/// Output generated by a `Probe` upon detecting a response.
+pub trait ProbeOutput:
+    Clone + Send + Sync + Serialize + std::fmt::Debug + std::fmt::Display
+{
+    /// The status of the probe that elicited this response. Most responses are replies from the
+    /// target itself.
+    fn status(&self) -> ProbeStatus {
+        ProbeStatus::Replied
+    }
+
+    /// The address of the host that sent the response.
+    fn source(&self) -> Ipv4Addr;
+
+    /// Addresses of the hops traversed by the probe and its response, if recorded.
+    fn route(&self) -> Option<&[Ipv4Addr]> {
+        None
+    }
+
+    /// Estimated offset in milliseconds of the responder's clock from ours, if measured.
+    fn clock_offset_ms(&self) -> Option<i64> {
+        None
+    }
+
+    /// The ToS byte of the reply, if it came from the target.
+    fn reply_tos(&self) -> Option<u8> {
+        None
+    }
+
+    /// The TTL of the reply as it arrived, if it came from the target.
+    fn reply_ttl(&self) -> Option<u8> {
+        None
+    }
+
+    /// Estimated number of hops between the target and us, if it could be told from the reply.
+    fn hops(&self) -> Option<u8> {
+        None
+    }
+
+    /// The gateway a router redirected the probe toward, for redirects.
+    fn gateway(&self) -> Option<Ipv4Addr> {
+        None
+    }
+
+    /// The MTU of the next hop of a router that reported the probe too big to forward, if it
+    /// said.
+    fn next_hop_mtu(&self) -> Option<u16> {
+        None
+    }
+
+    /// Size of the data echoed back by the reply, for replies that echo the request's payload.
+    fn payload_len(&self) -> Option<usize> {
+        None
+    }
+
+    /// Description of a response of an unexpected kind about the probe, eg an ICMP Destination
+    /// Unreachable message, which is counted against the target rather than answering the probe.
+    fn unexpected(&self) -> Option<&str> {
+        None
+    }
+
+    /// Compare the reply's payload with that of a request carrying `expected_len` bytes of it,
+    /// for replies that echo the request's payload.
+    fn check_payload(&mut self, _expected_len: usize) {}
+
+    /// How the reply's payload differed from the request's, if it was checked and did.
+    fn corruption(&self) -> Option<Corruption> {
+        None
+    }
+
+    /// When the response arrived, if recorded.
+    fn received_at(&self) -> Option<Instant> {
+        None
+    }
+
+    /// Record when the response arrived.
+    fn set_received_at(&mut self, _at: Instant) {}
+}
+
 /// A probe managed by a `ProbeTask`. `Probe` implementations are largely responsible for
 /// generating and optionally caching request packets.
+///
+/// The `Prober` only knows which request a response answers from what the implementation tells
+/// it, so implementations must uphold the following:
+/// * a request sent by `send` for some `TargetParams` is answered by responses for which
+///   `validate_response` returns those same `TargetParams`, address, wire sequence number and
+///   probe type alike; anything else leaves the probe to time out.
+/// * requests carry something from the `RequestTag` that responses must carry back, since
+///   `validate_response` sees every packet received on the listener's sockets, including
+///   responses to other `Prober`s and other processes, and must return `None` for those.
+/// * `validate_response` is called on the receive loop, so it mustn't block.
+///
+/// Responses matching no outstanding probe are dropped. `EchoBack` in this module's tests is a
+/// minimal implementation, run by a `Prober` against a scripted socket.
 #[async_trait]
 pub trait Probe {
     // The output generated when the `Prober` successfully detects a response to the `Probe` for a
     // given `TargetParams`.
-    type Output: Send + Serialize + std::fmt::Debug + std::fmt::Display;
+    type Output: ProbeOutput;
+
+    /// Send request using the given `ProbeSocket` with the given `TargetParams` and
+    /// `SendOptions`.
+    async fn send(
+        &mut self,
+        socket: &dyn ProbeSocket,
+        params: &TargetParams,
+        options: &SendOptions,
+    ) -> Result<()>;
 
-    /// Send request using the given `AsyncSocket` with the given `TargetParams`.
-    async fn send(&mut self, socket: AsyncSocket, params: &TargetParams) -> Result<()>;
+    /// Rebuild any state derived from the interface configuration, eg cached request frames,
+    /// after the link was re-initialized.
+    fn reconfigure(&mut self, ec: &EthernetConf) -> Result<()>;
 
     /// Validate whether the given packet buffer matches this Probe type. If so, return the
-    /// detected TargetParams and Self::Output.
-    fn validate_response(buf: &[u8]) -> Option<(TargetParams, Self::Output)>;
+    /// detected TargetParams and Self::Output, or, for replies too short to be matched to a probe,
+    /// what little can be told about them. Replies that don't carry one of the `Prober`'s
+    /// identifiers, or that should echo its nonce but don't, are rejected.
+    fn validate_response(
+        buf: &[u8],
+        ec: &EthernetConf,
+        tag: &RequestTag,
+    ) -> Option<Response<Self::Output>>;
 
     /// Return an AsyncSocket configured for this specific type of probe. Defaults to a RAW IPV4
     /// socket that receives ICMPV4 packets.
@@ -67,6 +578,20 @@ pub trait Probe {
         create_receiver()
     }
 
+    /// Return `count` AsyncSockets among which received packets are divided, each served by its
+    /// own receive worker. Defaults to the socket from `create_receiver` for a single worker and
+    /// otherwise to AF_PACKET sockets joined into a PACKET_FANOUT group. Without packet sockets
+    /// there is only ever one worker, since every raw IPv4 socket sees every reply.
+    fn create_receivers(e: &EthernetConf, count: usize) -> Result<Vec<AsyncSocket>> {
+        if count > 1 && e.is_inet_raw() {
+            tracing::warn!("receive workers need packet sockets, using a single worker");
+        }
+        if count <= 1 || e.is_inet_raw() {
+            return Ok(vec![Self::create_receiver(e)?]);
+        }
+        create_fanout_receivers(e, count)
+    }
+
     /// Return an AsyncSocket configured for this specific type of probe. Defaults to a RAW
     /// AF_PACKET socket bound to the interface specified in the `&EthernetConf`.
     fn create_sender(ec: &EthernetConf) -> Result<AsyncSocket> {
@@ -74,118 +599,526 @@ pub trait Probe {
     }
 }
 
+/// How sends that fail with a transient error, such as the kernel running out of buffer space
+/// during a burst, are retried.
+#[derive(Clone, Copy, Debug)]
+pub struct SendRetry {
+    /// Total number of attempts to send each probe, including the first.
+    pub max_attempts: u32,
+
+    /// Delay before the first retry, doubling with each subsequent one.
+    pub base_delay: Duration,
+}
+
+impl Default for SendRetry {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+        }
+    }
+}
+
+impl SendRetry {
+    /// Whether a send failing with `e` may succeed if retried.
+    fn is_transient(e: &std::io::Error) -> bool {
+        matches!(
+            e.kind(),
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::Interrupted
+        ) || matches!(e.raw_os_error(), Some(libc::ENOBUFS | libc::ENOMEM))
+    }
+
+    /// Whether a send failing with `e` means the interface is down or gone.
+    fn is_link_down(e: &std::io::Error) -> bool {
+        matches!(
+            e.raw_os_error(),
+            Some(libc::ENETDOWN | libc::ENXIO | libc::ENODEV)
+        )
+    }
+}
+
 /// ProbeTask holds general probe configuration and the sockets used to send request packets.
 #[derive(Debug)]
 struct ProbeTask<P: Probe + Send + Sync + 'static + std::fmt::Debug> {
     probe: P,
 
     /// Receives targets from the target-generator task.
-    target_receiver: ACReceiver<TargetParams>,
+    target_receiver: ACReceiver<ProbeRequest>,
 
     /// Sends ProbeReports
-    output_sender: UnboundedSender<ProbeReport<P>>,
+    output_sender: Sender<ProbeReport<P>>,
 
-    sender: AsyncSocket,
+    /// Held by every in-flight probe, so that the `Prober` can wait for them to complete.
+    in_flight: Sender<()>,
+
+    sender: Arc<dyn ProbeSocket>,
     listener: ProbeListener<P>,
 
     timeout: Duration,
+    send_retry: SendRetry,
+
+    /// Number of retries made by the last call to `send`.
+    send_retries: u32,
+
+    /// Re-initializes the link when sends fail because it's down, if enabled.
+    link: Option<Arc<LinkMonitor>>,
+
+    /// Generation of the link configuration `sender` and `probe` were set up for.
+    link_generation: u64,
 }
 
 impl<P: Probe + Send + Sync + 'static + std::fmt::Debug> ProbeTask<P> {
     /// Asynchronously run probe task end-to-end, including wait for reply.
-    async fn probe(&mut self, tparams: &TargetParams) -> Result<()> {
+    #[tracing::instrument(
+        name = "probe",
+        parent = &request.span,
+        skip_all,
+        fields(
+            addr = %request.tparams.addr,
+            seq = request.tparams.seq,
+            identifier = tracing::field::Empty,
+        ),
+    )]
+    async fn probe(&mut self, mut request: ProbeRequest) -> Result<()> {
+        if request.broadcast {
+            return self.probe_broadcast(request).await;
+        }
+        let notify = request.notify.take();
+        // wait for room in the output channel before sending so that a slow consumer holds back
+        // further probes rather than letting reports pile up
+        let permit = self
+            .output_sender
+            .clone()
+            .reserve_owned()
+            .await
+            .map_err(|_| Error::OutputHandlerChannelClosed)?;
+        let tparams = &request.tparams;
+        let index = request.index;
         let probe_waiter_fut = {
             let (sender, receiver) = async_channel::bounded(1);
             // create a probe response waiter task
-            let probe_waiter_fut = tokio::spawn(async move {
-                log::debug!("waiting for response to probe");
-                receiver.recv().await
-            });
-            log::debug!("registering probe waiter with ProbeListener");
+            let probe_waiter_fut = tokio::spawn(
+                async move {
+                    tracing::debug!("waiting for response to probe");
+                    receiver.recv().await
+                }
+                .in_current_span(),
+            );
+            tracing::debug!("registering probe waiter with ProbeListener");
             self.listener
-                .put_probe_sender(tparams.clone(), sender)
+                .put_probe_sender(
+                    tparams.clone(),
+                    index,
+                    request.instance,
+                    sender,
+                    request.send_options.payload_size,
+                )
                 .await;
             probe_waiter_fut
         };
 
-        log::debug!("sending probe for {tparams}");
-        self.probe.send(self.sender.clone(), tparams).await?;
-
-        let start = Instant::now();
+        tracing::debug!("sending probe for {tparams}");
+        let start = match self.send(tparams, &request.send_options).await {
+            Ok(start) => start,
+            Err(e) => {
+                // a probe that never made it onto the wire still counts against the target, so
+                // report it rather than silently dropping it
+                self.listener.expire_probe_sender(tparams, index).await;
+                drop(permit);
+                self.report_send_failure(&request, notify).await;
+                return Err(e);
+            }
+        };
+        let send_delay = request
+            .scheduled
+            .map(|scheduled| Instant::now().saturating_duration_since(scheduled));
+        let send_retries = self.send_retries;
 
         // create a timer
-        let output_sender = self.output_sender.clone();
-        let probe_timeout = self.timeout.clone();
-        let tparams = tparams.clone();
-        let _fut = tokio::spawn(async move {
-            let probe_report = match timeout(probe_timeout, probe_waiter_fut).await {
-                // Elapsed timeout error
-                Err(_elapsed) => {
-                    log::debug!("timed out waiting for {tparams} probe reply");
-                    ProbeReport::TimedOut(tparams.clone())
+        let in_flight = self.in_flight.clone();
+        let probe_timeout = self.timeout;
+        let listener = self.listener.clone();
+        let _fut = tokio::spawn(
+            async move {
+                let _in_flight = in_flight;
+                let tparams = &request.tparams;
+                let outcome = match timeout(probe_timeout, probe_waiter_fut).await {
+                    // Elapsed timeout error
+                    Err(_elapsed) => {
+                        tracing::debug!("timed out waiting for {tparams} probe reply");
+                        // stop treating the target as outstanding; dropping the sender also ends the
+                        // probe waiter task
+                        listener.expire_probe_sender(tparams, index).await;
+                        ProbeOutcome::TimedOut
+                    }
+                    // JoinError for probe waiter task; the probe can no longer be answered, so
+                    // it's reported as timed out rather than left without an outcome
+                    Ok(Err(e)) => {
+                        if e.is_panic() {
+                            tracing::debug!("probe waiter task panicked");
+                        } else if e.is_cancelled() {
+                            tracing::debug!("probe waiter task cancelled");
+                        } else {
+                            tracing::debug!("probe waiter task failed for unknown reason");
+                        }
+                        listener.expire_probe_sender(tparams, index).await;
+                        ProbeOutcome::TimedOut
+                    }
+                    // RecvError returned inside probe waiter task, eg because a later probe
+                    // sharing the wire sequence number replaced its waiter
+                    Ok(Ok(Err(e))) => {
+                        tracing::debug!("probe waiter failed to receive output: {e}");
+                        listener.expire_probe_sender(tparams, index).await;
+                        ProbeOutcome::TimedOut
+                    }
+                    // whew!
+                    Ok(Ok(Ok(o))) => {
+                        let rtt = rtt_since(start, &o);
+                        ProbeOutcome::ReceivedOutput(o, rtt)
+                    }
+                };
+                // the probe is complete, so let the target send another
+                drop(request.slot.take());
+                if let Some(notify) = notify {
+                    // the requester may not care to wait around for the outcome
+                    let _ = notify.send(outcome.status());
                 }
-                // JoinError for probe waiter task
-                Ok(Err(e)) => {
-                    if e.is_panic() {
-                        log::debug!("probe waiter task panicked");
-                    } else if e.is_cancelled() {
-                        log::debug!("probe waiter task cancelled");
-                    } else {
-                        log::debug!("probe waiter task failed for unknown reason");
+                permit.send(request.report(outcome, Some(start), send_delay, send_retries));
+            }
+            .in_current_span(),
+        );
+
+        Ok(())
+    }
+
+    /// Send the probe for `tparams` with `options`, retrying transient failures with exponential backoff.
+    /// Returns the time at which the successful attempt started, which is what RTTs are measured
+    /// from; backends that only return once the kernel reports completion would otherwise
+    /// understate them. Gives up once the probe timeout has passed, eg because a dead interface
+    /// never becomes writable, however many attempts remain. The number of retries is left in
+    /// `send_retries`.
+    async fn send(&mut self, tparams: &TargetParams, options: &SendOptions) -> Result<Instant> {
+        self.send_retries = 0;
+        let probe_timeout = self.timeout;
+        match timeout(probe_timeout, self.send_with_retries(tparams, options)).await {
+            Ok(result) => result,
+            Err(_elapsed) => {
+                tracing::debug!(
+                    "sending probe for {tparams} didn't complete within {probe_timeout:?}"
+                );
+                Err(Error::StdIoError(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "send didn't complete within the probe timeout",
+                )))
+            }
+        }
+    }
+
+    /// The retry loop of `send`, unbounded in time.
+    async fn send_with_retries(
+        &mut self,
+        tparams: &TargetParams,
+        options: &SendOptions,
+    ) -> Result<Instant> {
+        if let Some(link) = &self.link {
+            let current = link.current();
+            if current.generation != self.link_generation {
+                tracing::debug!("picking up link configuration {}", current.generation);
+                self.probe.reconfigure(&current.ethernet_conf)?;
+                self.sender = current.sender;
+                self.link_generation = current.generation;
+            }
+        }
+        let mut attempt = 1;
+        loop {
+            let start = Instant::now();
+            match self
+                .probe
+                .send(self.sender.as_ref(), tparams, options)
+                .await
+            {
+                Err(Error::StdIoError(e))
+                    if attempt < self.send_retry.max_attempts && SendRetry::is_transient(&e) =>
+                {
+                    let delay = self.send_retry.base_delay * 2u32.saturating_pow(attempt - 1);
+                    tracing::debug!(
+                        "sending probe for {tparams} failed ({e}), retrying in {delay:?}"
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                    self.send_retries += 1;
+                }
+                Err(Error::StdIoError(e)) if SendRetry::is_link_down(&e) => {
+                    if let Some(link) = &self.link {
+                        link.link_down();
                     }
-                    // not clear if returning a result here would be helpful
-                    return;
+                    return Err(Error::StdIoError(e));
                 }
-                // RecvError returned inside probe waiter task
-                Ok(Ok(Err(e))) => {
-                    log::debug!("probe waiter failed to receive output: {e}");
-                    return;
+                result => return result.map(|()| start),
+            }
+        }
+    }
+
+    /// Send a probe to a broadcast or multicast target and report each distinct host that
+    /// responds before the timeout elapses. If no host responds the probe is reported as timed
+    /// out.
+    async fn probe_broadcast(&mut self, mut request: ProbeRequest) -> Result<()> {
+        let notify = request.notify.take();
+        let (sender, receiver) = async_channel::unbounded();
+        tracing::debug!("registering broadcast probe waiter with ProbeListener");
+        self.listener
+            .put_broadcast_sender(
+                request.tparams.clone(),
+                request.index,
+                request.instance,
+                sender,
+                request.send_options.payload_size,
+            )
+            .await;
+
+        tracing::debug!("sending broadcast probe for {}", request.tparams);
+        let start = match self.send(&request.tparams, &request.send_options).await {
+            Ok(start) => start,
+            Err(e) => {
+                self.listener
+                    .expire_broadcast_sender(&request.tparams, request.index)
+                    .await;
+                self.report_send_failure(&request, notify).await;
+                return Err(e);
+            }
+        };
+        let send_delay = request
+            .scheduled
+            .map(|scheduled| Instant::now().saturating_duration_since(scheduled));
+        let send_retries = self.send_retries;
+        let output_sender = self.output_sender.clone();
+        let in_flight = self.in_flight.clone();
+        let probe_timeout = self.timeout;
+        let listener = self.listener.clone();
+        let _fut = tokio::spawn(
+            async move {
+                let _in_flight = in_flight;
+                let mut responders = HashSet::new();
+                while let Ok(Ok(output)) = timeout(
+                    probe_timeout.saturating_sub(start.elapsed()),
+                    receiver.recv(),
+                )
+                .await
+                {
+                    if !responders.insert(output.source()) {
+                        continue;
+                    }
+                    let rtt = rtt_since(start, &output);
+                    let outcome = ProbeOutcome::ReceivedOutput(output, rtt);
+                    if let Err(e) = output_sender
+                        .send(request.report(outcome, Some(start), send_delay, send_retries))
+                        .await
+                    {
+                        tracing::debug!("failed to send output for broadcast probe: {e}");
+                        return;
+                    }
                 }
-                // whew!
-                Ok(Ok(Ok(o))) => {
-                    let elapsed = start.elapsed();
-                    ProbeReport::ReceivedOutput(o, elapsed)
+                listener
+                    .expire_broadcast_sender(&request.tparams, request.index)
+                    .await;
+                tracing::debug!(
+                    "{} hosts responded to {} probe",
+                    responders.len(),
+                    request.tparams
+                );
+
+                drop(request.slot.take());
+                let status = if responders.is_empty() {
+                    ProbeStatus::TimedOut
+                } else {
+                    ProbeStatus::Replied
+                };
+                if let Some(notify) = notify {
+                    let _ = notify.send(status);
                 }
-            };
-            match output_sender.send(probe_report) {
-                Ok(_) => (),
-                Err(e) => {
-                    log::debug!("shutting down ProbeTask after failing to send output: {e}");
+                if responders.is_empty() {
+                    if let Err(e) = output_sender
+                        .send(request.report(
+                            ProbeOutcome::TimedOut,
+                            Some(start),
+                            send_delay,
+                            send_retries,
+                        ))
+                        .await
+                    {
+                        tracing::debug!("failed to send output for broadcast probe: {e}");
+                    }
                 }
-            };
-        });
+            }
+            .in_current_span(),
+        );
 
         Ok(())
     }
 
+    /// Report a probe that never made it onto the wire; it still counts against the target.
+    async fn report_send_failure(
+        &self,
+        request: &ProbeRequest,
+        notify: Option<UnboundedSender<ProbeStatus>>,
+    ) {
+        if let Some(notify) = notify {
+            let _ = notify.send(ProbeStatus::SendFailed);
+        }
+        if let Err(e) = self
+            .output_sender
+            .send(request.report(ProbeOutcome::SendFailed, None, None, self.send_retries))
+            .await
+        {
+            tracing::debug!("failed to send output for failed probe: {e}");
+        }
+    }
+
     /// Probe targets as they become avaailable on the channel
     async fn run(&mut self) -> Result<()> {
         loop {
-            let target = match self.target_receiver.recv().await {
-                Ok(t) => t,
+            let request = match self.target_receiver.recv().await {
+                Ok(r) => r,
                 Err(e) => {
-                    log::debug!("shutting down ProbeTask after failing to receive target: {e}");
+                    tracing::debug!("shutting down ProbeTask after failing to receive target: {e}");
                     break;
                 }
             };
-            log::debug!("received target {target}, attempting to send probe");
-            match self.probe(&target).await {
+            let target = request.tparams.clone();
+            tracing::debug!("received target {target}, attempting to send probe");
+            match self.probe(request).await {
                 Ok(probe_report) => probe_report,
                 Err(e) => {
-                    log::debug!("probe of {target} failed: {e}");
+                    tracing::debug!("probe of {target} failed: {e}");
                     continue;
                 }
             }
         }
-        log::debug!("ProbeTask finished running");
+        tracing::debug!("ProbeTask finished running");
         Ok(())
     }
 }
 
+/// A probe awaiting its reply.
+#[derive(Debug)]
+struct Waiter<O> {
+    /// Logical index of the probe; `TargetParams` only carries the wire sequence number, which
+    /// may be shared by more than one probe over the course of a long run.
+    index: u64,
+
+    /// Which of several loops probing the same address sent the probe, so that responses about it
+    /// are counted against that loop's row.
+    instance: Option<u16>,
+    sender: ACSender<O>,
+    registered: Instant,
+
+    /// Payload size the probe was sent with.
+    payload_len: usize,
+
+    /// Span of the probe, so that what becomes of its reply is logged in it.
+    span: tracing::Span,
+}
+
+/// Probes awaiting a reply, indexed by their `TargetParams`. Each probe's timer removes its
+/// waiter once the probe times out, so only probes sent within the last timeout are held however
+/// long the run.
+#[derive(Debug)]
+struct WaitingProbes<O> {
+    senders: HashMap<TargetParams, Waiter<O>>,
+
+    /// Broadcast probes, which may be answered by any host and so are matched on sequence number
+    /// alone. These remain registered until they time out.
+    broadcasts: HashMap<TargetParams, Waiter<O>>,
+}
+
+impl<O> WaitingProbes<O> {
+    fn new() -> Self {
+        Self {
+            senders: HashMap::new(),
+            broadcasts: HashMap::new(),
+        }
+    }
+
+    fn insert(&mut self, tparams: TargetParams, sender: Waiter<O>) -> Option<Waiter<O>> {
+        self.senders.insert(tparams, sender)
+    }
+
+    fn remove(&mut self, tparams: &TargetParams) -> Option<Waiter<O>> {
+        self.senders.remove(tparams)
+    }
+
+    fn is_outstanding(&self, tparams: &TargetParams) -> bool {
+        self.senders.contains_key(tparams)
+    }
+
+    /// The only outstanding probe with the sequence number and kind of probe of `tparams`,
+    /// whatever its address. Sequence numbers are assigned per target, so a match is only
+    /// trusted when it's unambiguous, and never for a reply from an address with probes of its
+    /// own outstanding, which is late or stray rather than answering another target's probe.
+    fn find_by_seq(&self, tparams: &TargetParams) -> Option<TargetParams> {
+        if self.senders.keys().any(|t| t.addr == tparams.addr) {
+            return None;
+        }
+        let mut matches = self
+            .senders
+            .keys()
+            .filter(|t| t.seq == tparams.seq && t.probe == tparams.probe);
+        match (matches.next(), matches.next()) {
+            (Some(only), None) => Some(only.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Redirects received for each target, by target address, kind of probe and the instance of the
+/// loop whose probe was redirected.
+pub type RedirectCounts = HashMap<(Ipv4Addr, ProbeType, Option<u16>), u64>;
+
+/// Responses of unexpected kinds received for each target, by target address, kind of probe and
+/// the instance of the loop whose probe they're about, counted by their description.
+pub type UnexpectedCounts = HashMap<(Ipv4Addr, ProbeType, Option<u16>), BTreeMap<String, u64>>;
+
+/// Replies too short to be matched to a probe received from each target, by target address, kind
+/// of probe and the instance of the loop whose probe they answer, if it could be told.
+pub type ShortReplyCounts = HashMap<(Ipv4Addr, ProbeType, Option<u16>), u64>;
+
+/// Redirects received over the course of a run.
+#[derive(Debug, Default)]
+struct Redirects {
+    counts: RedirectCounts,
+
+    /// Target and gateway pairs that have already been reported, so that each is reported once
+    /// rather than for every probe.
+    reported: HashSet<(Ipv4Addr, Ipv4Addr)>,
+}
+
 #[derive(Debug)]
 struct ProbeListener<P: Probe> {
-    waiting_probes: Arc<Mutex<HashMap<TargetParams, ACSender<P::Output>>>>,
-    socket: AsyncSocket,
+    waiting_probes: Arc<Mutex<WaitingProbes<P::Output>>>,
+    socket: Arc<dyn ProbeSocket>,
+    ethernet_conf: Arc<EthernetConf>,
+
+    /// Records every validated reply, if set.
+    capture: Option<Arc<PcapWriter>>,
+
+    /// Count of validated replies matching a probe that could no longer accept them, eg because
+    /// it timed out at the moment the reply arrived.
+    dropped_replies: Arc<AtomicU64>,
+
+    /// Waiters older than this are considered stale and never matched to a reply.
+    timeout: Duration,
+
+    /// Count of packets received on `socket`.
+    received: Arc<AtomicU64>,
+
+    /// Receives reports of responses that don't complete a probe, such as redirects.
+    output_sender: Sender<ProbeReport<P>>,
+    redirects: Arc<Mutex<Redirects>>,
+    unexpected: Arc<Mutex<UnexpectedCounts>>,
+    short_replies: Arc<Mutex<ShortReplyCounts>>,
+
+    /// What replies must carry back.
+    tag: RequestTag,
 }
 
 impl<P: Probe> Clone for ProbeListener<P> {
@@ -193,69 +1126,379 @@ impl<P: Probe> Clone for ProbeListener<P> {
         Self {
             waiting_probes: self.waiting_probes.clone(),
             socket: self.socket.clone(),
+            ethernet_conf: self.ethernet_conf.clone(),
+            capture: self.capture.clone(),
+            dropped_replies: self.dropped_replies.clone(),
+            timeout: self.timeout,
+            received: self.received.clone(),
+            output_sender: self.output_sender.clone(),
+            redirects: self.redirects.clone(),
+            unexpected: self.unexpected.clone(),
+            short_replies: self.short_replies.clone(),
+            tag: self.tag,
+        }
+    }
+}
+
+impl<P: Probe> ProbeListener<P> {
+    /// Create a listener receiving on `socket` that shares its waiting probes and counters with
+    /// this one, except for the count of received packets.
+    fn worker(&self, socket: Arc<dyn ProbeSocket>) -> Self {
+        Self {
+            socket,
+            received: Arc::new(AtomicU64::new(0)),
+            ..self.clone()
+        }
+    }
+
+    /// Receive and handle packets until receiving fails with an error that retrying won't clear,
+    /// which is returned.
+    async fn listen_forever(mut self) -> Result<()> {
+        loop {
+            let mut buf: Vec<u8> = Vec::with_capacity(RECV_BUFFER_SIZE);
+            let received_at = match self.recv(&mut buf).await {
+                Ok((_, stamp)) => {
+                    self.received.fetch_add(1, Ordering::Relaxed);
+                    stamp.map_or_else(Instant::now, arrival_instant)
+                }
+                // the same errors that sends retry on
+                Err(e) if SendRetry::is_transient(&e) => {
+                    tracing::debug!("ProbeListener receive failed, retrying: {e}");
+                    continue;
+                }
+                Err(e) => {
+                    return Err(Error::GenericStringError(format!(
+                        "failed to receive replies: {e}"
+                    )));
+                }
+            };
+            if let Err(e) = self.handle_packet(&buf, received_at).await {
+                tracing::debug!("ProbeListener failed to handle packet: {e}");
+            }
+        }
+    }
+
+    async fn handle_packet(&mut self, buf: &[u8], received_at: Instant) -> Result<()> {
+        tracing::debug!("received packet, checking for match with waiting probe");
+        let (mut tparams, output) = match P::validate_response(buf, &self.ethernet_conf, &self.tag)
+        {
+            Some(Response::Matched(tparams, output)) => (tparams, output),
+            Some(Response::Short {
+                addr,
+                probe,
+                seq,
+                len,
+            }) => {
+                self.count_short_reply(addr, probe, seq, len).await;
+                return Ok(());
+            }
+            None => return Ok(()),
+        };
+        if let Some(capture) = &self.capture {
+            if let Err(e) = capture.write_ipv4_packet(
+                buf,
+                self.ethernet_conf.ethernet_info.destination,
+                self.ethernet_conf.ethernet_info.source,
+            ) {
+                tracing::warn!("failed to capture received packet: {e}");
+            }
+        }
+        if let Some(kind) = output.unexpected() {
+            self.count_unexpected(&tparams, kind).await;
+            return Ok(());
+        }
+        if output.status() == ProbeStatus::Redirected {
+            self.handle_redirect(tparams, output).await;
+            return Ok(());
+        }
+        let broadcast = self.send_to_broadcast_waiters(&tparams, &output).await;
+        if !self.is_outstanding(&tparams).await {
+            // a reply from an address we didn't probe may still answer one of our probes, eg
+            // through NAT or anycast; the output keeps the source so the report shows both
+            let probed = if broadcast {
+                None
+            } else {
+                self.find_by_seq(&tparams).await
+            };
+            match probed {
+                Some(probed) => {
+                    tracing::debug!("{} responded to probe {probed}", tparams.addr);
+                    tparams = probed;
+                }
+                None => {
+                    tracing::debug!(
+                        "dropping reply for {tparams} which matches no outstanding probe"
+                    );
+                    return Ok(());
+                }
+            }
+        }
+        // a reply that doesn't carry back the whole payload intact, eg because something
+        // along the path truncated or altered it, completes the probe without answering it
+        let mut output = output;
+        output.set_received_at(received_at);
+        if let Some(expected) = self.expected_payload_len(&tparams).await {
+            output.check_payload(expected);
+            if let Some(corruption) = output.corruption() {
+                tracing::debug!("reply for {tparams} has a damaged payload: {corruption:?}");
+            }
+        }
+        let Some(waiter) = self.get_probe_sender(&tparams).await else {
+            return Ok(());
+        };
+        tracing::debug!(parent: &waiter.span, "matched reply for {tparams}");
+        // never block the receive loop on a waiter; a reply that can't be delivered is
+        // counted rather than silently conflated with network loss
+        if let Err(e) = waiter.sender.try_send(output) {
+            self.dropped_replies.fetch_add(1, Ordering::Relaxed);
+            tracing::debug!("failed to pass reply for {tparams:?} to its probe: {e}");
+        }
+
+        Ok(())
+    }
+
+    /// Count a redirect for one of our probes and report it, unless the target has already been
+    /// redirected toward the same gateway.
+    async fn handle_redirect(&self, tparams: TargetParams, output: P::Output) {
+        let gateway = match output.gateway() {
+            Some(gateway) => gateway,
+            None => return,
+        };
+        tracing::debug!("{} redirected {tparams} toward {gateway}", output.source());
+        // the logical index of the probe, and the loop that sent it, are only known while it
+        // awaits its reply
+        let (index, instance) = {
+            let g = self.waiting_probes.lock().await;
+            match g.senders.get(&tparams) {
+                Some(waiter) => (Some(waiter.index), waiter.instance),
+                None => (None, None),
+            }
+        };
+        let mut redirects = self.redirects.lock().await;
+        *redirects
+            .counts
+            .entry((tparams.addr, tparams.probe, instance))
+            .or_default() += 1;
+        let index = match index {
+            Some(index) if !redirects.reported.contains(&(tparams.addr, gateway)) => index,
+            _ => return,
+        };
+        redirects.reported.insert((tparams.addr, gateway));
+        let report = ProbeReport {
+            tparams,
+            index,
+            outcome: ProbeOutcome::Notice(output),
+            interval: None,
+            warmup: false,
+            instance,
+            broadcast: false,
+            dscp: 0,
+            send_delay: None,
+            sent_at: None,
+            overran: false,
+            send_retries: 0,
+            responder_name: None,
+            label: None,
+            rtt_warn: None,
+        };
+        // never block the receive loop on the consumer for the sake of an informational report
+        if let Err(e) = self.output_sender.try_send(report) {
+            tracing::debug!("failed to report redirect: {e}");
+        }
+    }
+
+    /// Count a response of an unexpected kind against the target of the probe it's about. Every
+    /// listener sees every response, so only the one awaiting the probe counts it.
+    async fn count_unexpected(&self, tparams: &TargetParams, kind: &str) {
+        tracing::debug!("received {kind} about {tparams}");
+        let instance = match self.waiting_probes.lock().await.senders.get(tparams) {
+            Some(waiter) => waiter.instance,
+            None => return,
+        };
+        let mut unexpected = self.unexpected.lock().await;
+        *unexpected
+            .entry((tparams.addr, tparams.probe, instance))
+            .or_default()
+            .entry(kind.to_string())
+            .or_default() += 1;
+    }
+
+    /// Count a reply too short to be matched to a probe against the target that sent it. Every
+    /// listener sees every reply, so only those awaiting a probe of its kind for the target count
+    /// it. The reply's sequence number, if it carries one, tells which loop's probe it answers.
+    async fn count_short_reply(
+        &self,
+        addr: Ipv4Addr,
+        probe: ProbeType,
+        seq: Option<u16>,
+        len: usize,
+    ) {
+        tracing::debug!("received {len} byte {probe} reply from {addr}, too short to match");
+        let instance = {
+            let waiting_probes = self.waiting_probes.lock().await;
+            let mut waiters = waiting_probes
+                .senders
+                .iter()
+                .filter(|(tparams, _)| tparams.addr == addr && tparams.probe == probe)
+                .peekable();
+            if waiters.peek().is_none() {
+                return;
+            }
+            waiters
+                .find(|(tparams, _)| Some(tparams.seq) == seq)
+                .and_then(|(_, waiter)| waiter.instance)
+        };
+        *self
+            .short_replies
+            .lock()
+            .await
+            .entry((addr, probe, instance))
+            .or_default() += 1;
+    }
+
+    async fn put_probe_sender(
+        &self,
+        tparams: TargetParams,
+        index: u64,
+        instance: Option<u16>,
+        sender: ACSender<P::Output>,
+        payload_len: usize,
+    ) {
+        let waiter = Waiter {
+            index,
+            instance,
+            sender,
+            registered: Instant::now(),
+            payload_len,
+            span: tracing::Span::current(),
+        };
+        let mut g = self.waiting_probes.lock().await;
+        if let Some(stale) = g.insert(tparams.clone(), waiter) {
+            tracing::error!(
+                "{tparams:?} already present in waiting probes for probe {}, replacing it",
+                stale.index
+            );
+        }
+    }
+
+    async fn put_broadcast_sender(
+        &self,
+        tparams: TargetParams,
+        index: u64,
+        instance: Option<u16>,
+        sender: ACSender<P::Output>,
+        payload_len: usize,
+    ) {
+        let waiter = Waiter {
+            index,
+            instance,
+            sender,
+            registered: Instant::now(),
+            payload_len,
+            span: tracing::Span::current(),
+        };
+        let mut g = self.waiting_probes.lock().await;
+        if let Some(stale) = g.broadcasts.insert(tparams.clone(), waiter) {
+            tracing::error!(
+                "{tparams:?} already present in waiting broadcast probes for probe {}, replacing it",
+                stale.index
+            );
         }
     }
-}
 
-impl<P: Probe> ProbeListener<P> {
-    async fn listen_forever(mut self) {
-        loop {
-            let mut buf: Vec<u8> = Vec::with_capacity(4096);
-            if let Err(e) = self.recv(&mut buf).await {
-                log::debug!("ProbeListener receive failed: {e}");
-            }
-            if let Err(e) = self.handle_packet(&buf).await {
-                log::debug!("ProbeListener failed to handle packet: {e}");
-            }
+    /// Remove the broadcast waiter for `tparams` unless it has since been replaced by a waiter for
+    /// a more recent probe.
+    async fn expire_broadcast_sender(&self, tparams: &TargetParams, index: u64) {
+        let mut g = self.waiting_probes.lock().await;
+        if g.broadcasts.get(tparams).map(|w| w.index) == Some(index) {
+            g.broadcasts.remove(tparams);
         }
     }
 
-    async fn handle_packet(&mut self, buf: &[u8]) -> Result<()> {
-        log::debug!("received packet, checking for match with waiting probe");
-        if let Some((tparams, output)) = P::validate_response(buf) {
-            if let Some(sender) = self.get_probe_sender(&tparams).await {
-                if let Err(e) = sender.send(output).await {
-                    log::debug!(
-                        "failed to send output for {tparams:?} to handler, channel closed: {e}"
-                    );
-                    return Err(Error::OutputHandlerChannelClosed);
-                }
-            } else {
-                log::debug!("unable to match a detected packet to a probe waiter");
+    /// Pass `output` to every live broadcast waiter whose probe shares the sequence number of the
+    /// detected response. Replies to broadcast probes come from the responding hosts rather than
+    /// the target, so the sequence number is all there is to match on. Returns whether any
+    /// broadcast probe was waiting for the response.
+    async fn send_to_broadcast_waiters(&self, tparams: &TargetParams, output: &P::Output) -> bool {
+        let senders: Vec<ACSender<P::Output>> = {
+            let g = self.waiting_probes.lock().await;
+            g.broadcasts
+                .iter()
+                .filter(|(t, w)| {
+                    t.seq == tparams.seq
+                        && w.registered.elapsed() <= self.timeout
+                        && output.payload_len().is_none_or(|len| len == w.payload_len)
+                })
+                .map(|(_, w)| w.sender.clone())
+                .collect()
+        };
+        for sender in &senders {
+            if let Err(e) = sender.try_send(output.clone()) {
+                self.dropped_replies.fetch_add(1, Ordering::Relaxed);
+                tracing::debug!("failed to pass reply for {tparams:?} to broadcast probe: {e}");
             }
         }
+        !senders.is_empty()
+    }
 
-        Ok(())
+    async fn expected_payload_len(&self, tparams: &TargetParams) -> Option<usize> {
+        let g = self.waiting_probes.lock().await;
+        g.senders.get(tparams).map(|waiter| waiter.payload_len)
     }
 
-    async fn put_probe_sender(&self, tparams: TargetParams, sender: ACSender<P::Output>) {
+    /// Take the waiter for `tparams` to pass it a reply. A waiter registered longer than the
+    /// timeout ago is left in place: the reply is too late for its probe, most likely answering
+    /// an earlier one that shared its sequence number, and the probe's timer is about to report
+    /// it as timed out.
+    async fn get_probe_sender(&self, tparams: &TargetParams) -> Option<Waiter<P::Output>> {
         let mut g = self.waiting_probes.lock().await;
-        if let Some(_s) = g.insert(tparams.clone(), sender) {
-            log::error!("{tparams:?} already present in waiting probes");
+        match g.senders.get(tparams) {
+            Some(waiter) if waiter.registered.elapsed() > self.timeout => {
+                tracing::debug!(
+                    parent: &waiter.span,
+                    "dropping reply for {tparams} matching stale probe {}",
+                    waiter.index
+                );
+                None
+            }
+            Some(_) => g.remove(tparams),
+            None => {
+                tracing::debug!("unable to match a detected packet to a probe waiter");
+                None
+            }
         }
     }
 
-    async fn get_probe_sender(&self, tparams: &TargetParams) -> Option<ACSender<P::Output>> {
+    /// Remove the waiter for `tparams` unless it has since been replaced by a waiter for a more
+    /// recent probe sharing the same wire sequence number.
+    async fn expire_probe_sender(&self, tparams: &TargetParams, index: u64) {
         let mut g = self.waiting_probes.lock().await;
-        g.remove(tparams)
+        if g.senders.get(tparams).map(|w| w.index) == Some(index) {
+            g.remove(tparams);
+        }
     }
 
-    async fn recv(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
-        let mut uninit = buf.spare_capacity_mut();
-        match self.socket.recv(&mut uninit).await {
-            Err(e) => {
-                panic!("unhandled socket read error: {}", e);
-            }
-            Ok(len) => {
-                log::trace!("received {} bytes for packet", len);
-                // this is safe because we have the exact number of bytes written into the
-                // MaybeUninit buf
-                unsafe {
-                    buf.set_len(len);
-                }
-                Ok(len)
-            }
+    async fn is_outstanding(&self, tparams: &TargetParams) -> bool {
+        let g = self.waiting_probes.lock().await;
+        g.is_outstanding(tparams)
+    }
+
+    async fn find_by_seq(&self, tparams: &TargetParams) -> Option<TargetParams> {
+        let g = self.waiting_probes.lock().await;
+        g.find_by_seq(tparams)
+    }
+
+    /// Receive a packet into `buf`, returning its length and when the kernel received it, if
+    /// known.
+    async fn recv(&mut self, buf: &mut Vec<u8>) -> std::io::Result<(usize, Option<SystemTime>)> {
+        let uninit = buf.spare_capacity_mut();
+        let (len, stamp) = self.socket.recv_timestamped(uninit).await?;
+        tracing::trace!("received {} bytes for packet", len);
+        // this is safe because we have the exact number of bytes written into the MaybeUninit buf
+        unsafe {
+            buf.set_len(len);
         }
+        Ok((len, stamp))
     }
 }
 
@@ -263,82 +1506,390 @@ impl<P: Probe> ProbeListener<P> {
 /// makes concurrent network probes easy.
 #[derive(Clone)]
 pub struct Prober<P: Probe + Send + Sync + 'static + std::fmt::Debug> {
-    target_receiver: ACReceiver<TargetParams>,
-    output_sender: UnboundedSender<ProbeReport<P>>,
+    target_receiver: ACReceiver<ProbeRequest>,
+    output_sender: Sender<ProbeReport<P>>,
+
+    /// Requested receive buffer size of the listener socket; the kernel default if unset.
+    recv_buffer_size: Option<usize>,
+
+    /// Records sent frames and validated replies, if set.
+    capture: Option<Arc<PcapWriter>>,
+
+    /// How sends failing with transient errors are retried.
+    send_retry: SendRetry,
+
+    /// Number of receive workers, each with its own socket.
+    rx_workers: usize,
+
+    /// How the sender and receiver sockets are driven.
+    io_backend: IoBackend,
+
+    /// Whether to keep probing through outages of the interface, re-initializing it once it
+    /// comes back.
+    survive_link_down: bool,
+
+    /// What probes mark their requests with, shared with siblings.
+    tag: RequestTag,
 }
 
 impl<P: Probe + Send + Sync + 'static + std::fmt::Debug> Prober<P> {
-    pub fn new() -> Result<(
-        Self,
-        ACSender<TargetParams>,
-        UnboundedReceiver<ProbeReport<P>>,
-    )> {
-        let (output_sender, output_receiver) = unbounded_channel();
+    /// Create a `Prober` along with the channel on which it receives requests and the channel on
+    /// which it sends reports. Probing stalls if reports aren't consumed.
+    pub fn new() -> Result<(Self, ACSender<ProbeRequest>, Receiver<ProbeReport<P>>)> {
+        let (output_sender, output_receiver) = channel(OUTPUT_CHANNEL_CAPACITY);
         let (target_sender, target_receiver) = async_channel::unbounded();
 
         Ok((
             Self {
                 target_receiver,
                 output_sender,
+                recv_buffer_size: None,
+                capture: None,
+                send_retry: SendRetry::default(),
+                rx_workers: 1,
+                io_backend: IoBackend::default(),
+                survive_link_down: false,
+                tag: RequestTag {
+                    nonce: u32::from_ne_bytes(random_bytes()?),
+                    identifier_base: std::process::id() as u16,
+                    identifier_count: 1,
+                },
             },
             target_sender,
             output_receiver,
         ))
     }
 
+    /// Request a receive buffer of `size` bytes for the socket on which replies are received, so
+    /// that the kernel can queue more replies during bursts. Inherited by siblings created
+    /// afterwards.
+    pub fn set_recv_buffer_size(&mut self, size: usize) {
+        self.recv_buffer_size = Some(size);
+    }
+
+    /// Record every frame sent and every reply validated to `capture`. Inherited by siblings
+    /// created afterwards.
+    pub fn set_capture(&mut self, capture: Arc<PcapWriter>) {
+        self.capture = Some(capture);
+    }
+
+    /// Retry sends that fail with transient errors according to `send_retry`. Inherited by
+    /// siblings created afterwards.
+    pub fn set_send_retry(&mut self, send_retry: SendRetry) {
+        self.send_retry = send_retry;
+    }
+
+    /// Divide received packets among `rx_workers` sockets, each with its own receive loop, so
+    /// that a single loop doesn't limit the rate at which replies can be handled. Inherited by
+    /// siblings created afterwards.
+    pub fn set_rx_workers(&mut self, rx_workers: usize) {
+        self.rx_workers = rx_workers.max(1);
+    }
+
+    /// Drive sockets with `io_backend`, falling back to epoll if it's unavailable. Inherited by
+    /// siblings created afterwards.
+    pub fn set_io_backend(&mut self, io_backend: IoBackend) {
+        self.io_backend = io_backend;
+    }
+
+    /// Keep probing when sends fail because the interface is down, periodically re-initializing
+    /// it and its send socket until it comes back. Probes failing in the meantime are reported
+    /// as send failures. Inherited by siblings created afterwards.
+    pub fn set_survive_link_down(&mut self, survive_link_down: bool) {
+        self.survive_link_down = survive_link_down;
+    }
+
+    /// Reserve an ICMP identifier for each of `count` probe slots, so that each probe passed to
+    /// `run_probes` can use its own. Inherited by siblings created afterwards.
+    pub fn set_probe_slots(&mut self, count: usize) {
+        let count = count.clamp(1, 1 << 16) as u32;
+        self.tag.identifier_base = if count == 1 << 16 {
+            // every identifier is taken, so there's nothing to set apart from other processes and
+            // only the nonce tells their replies from ours
+            0
+        } else {
+            // scale the ID by the size of the range so that processes with consecutive IDs get
+            // adjacent ranges; identifiers wrap around, so ranges are disjoint only modulo 2^16,
+            // and processes whose IDs are further apart may overlap, which the nonce catches
+            (std::process::id() as u16).wrapping_mul(count as u16)
+        };
+        self.tag.identifier_count = count;
+    }
+
+    /// What probes sent by this `Prober` and its siblings must mark their requests with.
+    pub fn tag(&self) -> RequestTag {
+        self.tag
+    }
+
+    /// Create another `Prober` that reports to the same output channel as this one but receives
+    /// requests from its own channel. This allows probes bound to different interfaces to share a
+    /// single stream of `ProbeReport`s.
+    pub fn sibling(&self) -> (Self, ACSender<ProbeRequest>) {
+        let (target_sender, target_receiver) = async_channel::unbounded();
+        (
+            Self {
+                target_receiver,
+                output_sender: self.output_sender.clone(),
+                recv_buffer_size: self.recv_buffer_size,
+                capture: self.capture.clone(),
+                send_retry: self.send_retry,
+                rx_workers: self.rx_workers,
+                io_backend: self.io_backend,
+                survive_link_down: self.survive_link_down,
+                tag: self.tag,
+            },
+            target_sender,
+        )
+    }
+
     pub async fn run_probes(
+        self,
+        probes: Vec<P>,
+        ethernet_conf: EthernetConf,
+        timeout: Duration,
+    ) -> Result<ProberStats> {
+        let mut sender_socket = if ethernet_conf.is_ping() {
+            create_ping_socket(&ethernet_conf, self.tag.identifier(0))?
+        } else {
+            P::create_sender(&ethernet_conf)?
+        };
+        if let Some(capture) = &self.capture {
+            sender_socket.set_capture(capture.clone());
+        }
+
+        // replies to a ping socket's requests are only delivered to that socket
+        let mut receiver_sockets = if ethernet_conf.is_ping() {
+            if self.rx_workers > 1 {
+                tracing::warn!("receive workers need packet sockets, using a single worker");
+            }
+            vec![sender_socket.clone()]
+        } else {
+            P::create_receivers(&ethernet_conf, self.rx_workers)?
+        };
+        // replies are timed by the kernel where it can, so that time spent waiting for the
+        // receive loop isn't counted toward their RTTs
+        for receiver_socket in &mut receiver_sockets {
+            if let Err(e) = receiver_socket.enable_rx_timestamps() {
+                tracing::debug!(
+                    "kernel receive timestamps unavailable, timing replies as read: {e}"
+                );
+            }
+        }
+        if let Some(size) = self.recv_buffer_size {
+            let mut clamped = None;
+            for receiver_socket in &receiver_sockets {
+                let effective = receiver_socket.set_recv_buffer_size(size)?;
+                tracing::debug!("requested receive buffer of {size} bytes, got {effective} bytes");
+                // the kernel doubles unclamped requests
+                if effective < size.saturating_mul(2) {
+                    clamped = Some(effective);
+                }
+            }
+            if let Some(effective) = clamped {
+                tracing::warn!(
+                    "receive buffer clamped to {effective} bytes; raise net.core.rmem_max to allow \
+                     {size} bytes"
+                );
+            }
+        }
+
+        // kept to read drop statistics once the run is over, whichever backend drives them
+        let stats_sockets = receiver_sockets.clone();
+        let sender_socket = socket::with_backend(sender_socket, self.io_backend);
+        let receiver_sockets = receiver_sockets
+            .into_iter()
+            .map(|socket| socket::with_backend(socket, self.io_backend))
+            .collect();
+        let mut stats = self
+            .run_probes_on(
+                probes,
+                ethernet_conf,
+                timeout,
+                sender_socket,
+                receiver_sockets,
+            )
+            .await?;
+        for socket in &stats_sockets {
+            match socket.kernel_drops() {
+                Ok(drops) => stats.kernel_drops += drops.unwrap_or(0),
+                Err(e) => tracing::warn!("failed to read receive socket statistics: {e}"),
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Run `probes`, sending requests on `sender_socket` and receiving replies on
+    /// `receiver_sockets`, one receive worker per socket, rather than on sockets created by the
+    /// `Probe` implementation.
+    pub async fn run_probes_on(
         self,
         mut probes: Vec<P>,
         ethernet_conf: EthernetConf,
         timeout: Duration,
-    ) -> Result<()> {
-        let sender_socket = P::create_sender(&ethernet_conf)?;
+        sender_socket: Arc<dyn ProbeSocket>,
+        receiver_sockets: Vec<Arc<dyn ProbeSocket>>,
+    ) -> Result<ProberStats> {
+        let mut receiver_sockets = receiver_sockets.into_iter();
+        let first_socket = match receiver_sockets.next() {
+            Some(socket) => socket,
+            None => {
+                return Err(Error::GenericStringError(
+                    "at least one receiver socket is required".to_string(),
+                ))
+            }
+        };
         let mut join_set = JoinSet::new();
-
+        let ethernet_conf = Arc::new(ethernet_conf);
+        let link = self.survive_link_down.then(|| {
+            let capture = self.capture.clone();
+            let io_backend = self.io_backend;
+            Arc::new(LinkMonitor::new(
+                ethernet_conf.clone(),
+                sender_socket.clone(),
+                Box::new(move |ec: &EthernetConf| {
+                    let mut socket = P::create_sender(ec)?;
+                    if let Some(capture) = &capture {
+                        socket.set_capture(capture.clone());
+                    }
+                    Ok(socket::with_backend(socket, io_backend))
+                }),
+            ))
+        });
         let probe_listener = ProbeListener::<P> {
-            waiting_probes: Arc::new(Mutex::new(HashMap::new())),
-            socket: P::create_receiver(&ethernet_conf)?,
+            waiting_probes: Arc::new(Mutex::new(WaitingProbes::new())),
+            socket: first_socket,
+            capture: self.capture.clone(),
+            ethernet_conf,
+            dropped_replies: Arc::new(AtomicU64::new(0)),
+            timeout,
+            received: Arc::new(AtomicU64::new(0)),
+            output_sender: self.output_sender.clone(),
+            redirects: Arc::new(Mutex::new(Redirects::default())),
+            unexpected: Arc::new(Mutex::new(UnexpectedCounts::new())),
+            short_replies: Arc::new(Mutex::new(ShortReplyCounts::new())),
+            tag: self.tag,
         };
+        // every worker shares the waiting probes, so a reply may be matched by whichever worker
+        // the kernel hands it to
+        let mut workers = vec![probe_listener.clone()];
+        workers.extend(receiver_sockets.map(|socket| probe_listener.worker(socket)));
+        let (in_flight, mut in_flight_done) = channel::<()>(1);
 
         for probe in probes.drain(0..) {
             let mut probe_task = ProbeTask {
                 probe,
                 sender: sender_socket.clone(),
                 listener: probe_listener.clone(),
-                timeout: timeout.clone(),
+                timeout,
+                send_retry: self.send_retry,
+                send_retries: 0,
+                link: link.clone(),
+                link_generation: 0,
                 output_sender: self.output_sender.clone(),
+                in_flight: in_flight.clone(),
                 target_receiver: self.target_receiver.clone(),
             };
             join_set.spawn(async move {
                 match probe_task.run().await {
                     Ok(_) => (),
                     Err(e) => {
-                        log::error!("ProbeTask unexpectedly failed: {e:?}");
+                        tracing::error!("ProbeTask unexpectedly failed: {e:?}");
                     }
                 }
             });
         }
 
+        let dropped_replies = probe_listener.dropped_replies.clone();
+        let redirects = probe_listener.redirects.clone();
+        let unexpected = probe_listener.unexpected.clone();
+        let short_replies = probe_listener.short_replies.clone();
+        let worker_packets: Vec<Arc<AtomicU64>> =
+            workers.iter().map(|w| w.received.clone()).collect();
         let cancel = CancellationToken::new();
-        let cloned_cancel = cancel.clone();
-        let listener_fut = tokio::spawn(async move {
+        let mut listeners = JoinSet::new();
+        for worker in workers {
+            let cloned_cancel = cancel.clone();
+            listeners.spawn(async move {
+                tokio::select! {
+                    _ = cloned_cancel.cancelled() => Ok(()),
+                    result = worker.listen_forever() => result,
+                }
+            });
+        }
+
+        // a listener only stops before being cancelled if receiving failed, and without it no
+        // probe can be answered, so the run fails rather than timing every probe out; returning
+        // drops the probe tasks and the other listeners, aborting them
+        tracing::debug!("waiting for probe tasks to finish");
+        loop {
             tokio::select! {
-                _ = cloned_cancel.cancelled() => {},
-                _ = probe_listener.listen_forever() => {},
+                finished = join_set.join_next() => if finished.is_none() { break },
+                Some(result) = listeners.join_next() => result??,
             }
-        });
+        }
 
-        log::debug!("waiting for probe tasks to finish");
-        while join_set.join_next().await.is_some() {}
+        // keep listening until every in-flight probe has been answered or timed out; the channel
+        // closes once the last of them drops its sender
+        tracing::debug!("waiting for in-flight probes to finish");
+        drop(in_flight);
+        tokio::select! {
+            _ = in_flight_done.recv() => (),
+            Some(result) = listeners.join_next() => result??,
+        }
         cancel.cancel();
 
-        log::debug!("waiting for ProbeListener task to finish");
-        listener_fut.await?;
+        tracing::debug!("waiting for ProbeListener tasks to finish");
+        while let Some(result) = listeners.join_next().await {
+            result??;
+        }
 
-        Ok(())
+        let redirects = std::mem::take(&mut redirects.lock().await.counts);
+        let unexpected = std::mem::take(&mut *unexpected.lock().await);
+        let short_replies = std::mem::take(&mut *short_replies.lock().await);
+        Ok(ProberStats {
+            dropped_replies: dropped_replies.load(Ordering::Relaxed),
+            worker_packets: worker_packets
+                .iter()
+                .map(|received| received.load(Ordering::Relaxed))
+                .collect(),
+            kernel_drops: 0,
+            redirects,
+            unexpected,
+            short_replies,
+            outages: link.map(|link| link.outages()).unwrap_or_default(),
+        })
     }
 }
 
+/// Statistics about the internal operation of a `Prober` over the course of a run.
+#[derive(Debug, Default)]
+pub struct ProberStats {
+    /// Replies that matched a probe but couldn't be delivered to it. Such probes are reported as
+    /// timed out even though a reply arrived.
+    pub dropped_replies: u64,
+
+    /// Packets received by each receive worker, in worker order.
+    pub worker_packets: Vec<u64>,
+
+    /// Packets the kernel dropped before they could be received because a receive socket's
+    /// buffer was full. Their probes are reported as timed out.
+    pub kernel_drops: u64,
+
+    /// ICMP Redirects received for each target.
+    pub redirects: RedirectCounts,
+
+    /// ICMP error messages of unexpected types or codes received for each target, eg
+    /// Destination Unreachable.
+    pub unexpected: UnexpectedCounts,
+
+    /// Replies too short to be matched to a probe received from each target, eg because a
+    /// middlebox truncated them. Their probes are reported as timed out.
+    pub short_replies: ShortReplyCounts,
+
+    /// Periods during which the interface was down, if probing was kept up through them.
+    pub outages: Vec<Outage>,
+}
+
 fn create_receiver() -> Result<AsyncSocket> {
     // note: for some reason using Domain::PACKET as is done in zmap (libpcap, really) doesn't
     // work here -- the socket never becomes ready for reading. for now I'm setting it back to
@@ -350,7 +1901,7 @@ fn create_receiver() -> Result<AsyncSocket> {
     socket.set_write_timeout(rw_timeout)?;
     socket.set_read_timeout(rw_timeout)?;
 
-    Ok(AsyncSocket::new(socket)?)
+    AsyncSocket::new(socket)
 }
 
 /// # Notes on Socket choice:
@@ -370,15 +1921,68 @@ fn create_receiver() -> Result<AsyncSocket> {
 ///
 /// [1] https://zmap.io/paper.pdf
 fn create_sender(ethernet_conf: &EthernetConf) -> Result<AsyncSocket> {
+    if ethernet_conf.interface.link_type == LinkType::Loopback || ethernet_conf.is_inet_raw() {
+        return create_inet_raw_sender(ethernet_conf);
+    }
+
     // choose Domain::PACKET here so that we can cache ICMP reply packets and circumvent
-    // network-layer handling of packets in the kernel
-    let socket = Socket::new(Domain::PACKET, Type::RAW, None)?;
+    // network-layer handling of packets in the kernel. on links without Ethernet framing our
+    // packets start at the IP header, so let the kernel add whatever link-layer header the
+    // interface needs (eg the zeroed Ethernet header of the loopback interface)
+    let (socket_type, protocol) = match ethernet_conf.interface.link_type {
+        LinkType::Ethernet => (Type::RAW, libc::ETH_P_ALL as u16),
+        _ => (Type::DGRAM, (libc::ETH_P_IP as u16).to_be()),
+    };
+    let socket = Socket::new(Domain::PACKET, socket_type, None)?;
 
     socket.set_nonblocking(true)?;
     let rw_timeout = Some(Duration::from_millis(1));
     socket.set_write_timeout(rw_timeout)?;
     socket.set_read_timeout(rw_timeout)?;
 
+    let addr = packet_sockaddr(ethernet_conf.interface.index, protocol);
+
+    // trying to bind any other type of SockAddr (eg Ipv4Addr) than what we have initialized
+    // above would fail with an EINVAL error for an AF_PACKET
+    socket.bind(&addr)?;
+    bind_sender_to_device(&socket, ethernet_conf)?;
+
+    let mut socket = AsyncSocket::new(socket)?;
+    socket.set_link_type(ethernet_conf.interface.link_type);
+    Ok(socket)
+}
+
+/// Bind a send socket to the interface in `ethernet_conf` with SO_BINDTODEVICE, unless disabled.
+/// A bound AF_PACKET socket already only sends on its interface; this mostly matters for raw IPv4
+/// sockets, which would otherwise leave by whichever interface the routing table picks.
+fn bind_sender_to_device(socket: &Socket, ethernet_conf: &EthernetConf) -> Result<()> {
+    if !ethernet_conf.binds_device() {
+        return Ok(());
+    }
+    let name = ethernet_conf.interface.name();
+    // safe because the option value is the interface name, whose length is passed along with it
+    let rc = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            name.as_ptr().cast(),
+            name.len() as libc::socklen_t,
+        )
+    };
+    if rc != 0 {
+        let e = std::io::Error::last_os_error();
+        return Err(Error::GenericStringError(format!(
+            "failed to bind send socket to interface {name}: {e}"
+        )));
+    }
+    tracing::debug!("bound send socket to interface {name}");
+    Ok(())
+}
+
+/// Build the address to which an AF_PACKET socket is bound to receive (or send) `protocol`
+/// packets, in network byte order, on the interface with index `ifindex`.
+fn packet_sockaddr(ifindex: u32, protocol: u16) -> SockAddr {
     // initialize sockaddr_storage then reference as raw pointer to a sockaddr_ll in order to
     // set link-layer options on the addr before binding the socket. the intent here is to bind
     // the AF_PACKET socket by index to the interface in the given EthernetConf.
@@ -388,7 +1992,7 @@ fn create_sender(ethernet_conf: &EthernetConf) -> Result<AsyncSocket> {
     let mut addr_storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
     {
         // I don't really understand why this line isn't considered unsafe by the compiler
-        let mut addr_ll_ref: *mut libc::sockaddr_ll =
+        let addr_ll_ref: *mut libc::sockaddr_ll =
             (&mut addr_storage as *mut libc::sockaddr_storage).cast();
 
         // these operations are safe because according to 'man sockaddr_storage' it is designed
@@ -396,13 +2000,13 @@ fn create_sender(ethernet_conf: &EthernetConf) -> Result<AsyncSocket> {
         // types (such as sockaddr_ll here) so that the needed fields for the sockaddr_* type
         // can be set
         //let hw = ethernet_conf.ethernet_info.source.clone();
-        //log::debug!("hw addr: {:?}", hw);
+        //tracing::debug!("hw addr: {:?}", hw);
         unsafe {
             (*addr_ll_ref).sll_family = libc::AF_PACKET as u16;
-            (*addr_ll_ref).sll_ifindex = ethernet_conf.interface.index as i32;
-            (*addr_ll_ref).sll_protocol = libc::ETH_P_ALL as u16;
+            (*addr_ll_ref).sll_ifindex = ifindex as i32;
+            (*addr_ll_ref).sll_protocol = protocol;
             //(*addr_ll_ref).sll_addr = [hw.0, hw.1, hw.2, hw.3, hw.4, hw.5, 0, 0];
-            log::debug!("sockaddr_ll for bind set to: {:?}", *addr_ll_ref);
+            tracing::debug!("sockaddr_ll for bind set to: {:?}", *addr_ll_ref);
         }
     }
     let len = std::mem::size_of_val(&addr_storage) as libc::socklen_t;
@@ -410,11 +2014,676 @@ fn create_sender(ethernet_conf: &EthernetConf) -> Result<AsyncSocket> {
     // the following is safe because of the abovementioned explanations regarding initializing
     // the sockaddr_storage bits to 0 and casting to a sockaddr_ll to set link-layer fields for
     // the sockaddr; so we have correctly constructed our sockaddr_storage.
-    let addr = unsafe { SockAddr::new(addr_storage, len) };
+    unsafe { SockAddr::new(addr_storage, len) }
+}
 
-    // trying to bind any other type of SockAddr (eg Ipv4Addr) than what we have initialized
-    // above would fail with an EINVAL error for an AF_PACKET
-    socket.bind(&addr)?;
+/// PACKET_FANOUT and PACKET_IGNORE_OUTGOING socket options and fanout modes from
+/// linux/if_packet.h, which libc doesn't expose.
+const PACKET_FANOUT: libc::c_int = 18;
+const PACKET_IGNORE_OUTGOING: libc::c_int = 23;
+const PACKET_FANOUT_HASH: u32 = 0;
+const PACKET_FANOUT_FLAG_UNIQUEID: u32 = 0x2000;
+
+/// Open `count` AF_PACKET sockets receiving IPv4 packets on the interface in `ethernet_conf`,
+/// joined into a PACKET_FANOUT group so that the kernel spreads packets among them by flow hash.
+/// The group id is picked by the kernel, so the group can't collide with that of another `Prober`
+/// or process.
+fn create_fanout_receivers(ethernet_conf: &EthernetConf, count: usize) -> Result<Vec<AsyncSocket>> {
+    let protocol = (libc::ETH_P_IP as u16).to_be();
+    let addr = packet_sockaddr(ethernet_conf.interface.index, protocol);
+    let mut group = None;
+    let mut sockets = Vec::with_capacity(count);
+    for _ in 0..count {
+        let socket = Socket::new(Domain::PACKET, Type::DGRAM, None)?;
+        socket.set_nonblocking(true)?;
+        let rw_timeout = Some(Duration::from_millis(1));
+        socket.set_write_timeout(rw_timeout)?;
+        socket.set_read_timeout(rw_timeout)?;
+        // unlike a raw IPv4 socket, a packet socket also sees our own requests going out
+        if let Err(e) = set_packet_option(&socket, PACKET_IGNORE_OUTGOING, 1) {
+            tracing::debug!("failed to ignore outgoing packets on receive socket: {e}");
+        }
+        // the socket must be bound to a protocol before it can join a fanout group
+        socket.bind(&addr)?;
+        let fanout = match group {
+            Some(id) => id | PACKET_FANOUT_HASH << 16,
+            None => (PACKET_FANOUT_HASH | PACKET_FANOUT_FLAG_UNIQUEID) << 16,
+        };
+        set_packet_option(&socket, PACKET_FANOUT, fanout)?;
+        if group.is_none() {
+            let id = get_packet_fanout(&socket)? & 0xffff;
+            tracing::debug!("created fanout group {id} for {count} receive workers");
+            group = Some(id);
+        }
+        sockets.push(AsyncSocket::new(socket)?);
+    }
+    Ok(sockets)
+}
+
+fn set_ip_option(socket: &Socket, option: libc::c_int, value: libc::c_int) -> Result<()> {
+    // safe because the option value is a c_int whose size is passed along with it
+    let rc = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IP,
+            option,
+            (&value as *const libc::c_int).cast(),
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if rc != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+fn set_packet_option(socket: &Socket, option: libc::c_int, value: u32) -> Result<()> {
+    // safe because the option value is a u32 whose size is passed along with it
+    let rc = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_PACKET,
+            option,
+            (&value as *const u32).cast(),
+            std::mem::size_of::<u32>() as libc::socklen_t,
+        )
+    };
+    if rc != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+fn get_packet_fanout(socket: &Socket) -> Result<u32> {
+    let mut fanout: u32 = 0;
+    let mut len = std::mem::size_of::<u32>() as libc::socklen_t;
+    // safe because the kernel writes at most `len` bytes to the u32 it's given
+    let rc = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_PACKET,
+            PACKET_FANOUT,
+            (&mut fanout as *mut u32).cast(),
+            &mut len,
+        )
+    };
+    if rc != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(fanout)
+}
+
+/// The kernel treats loopback-addressed packets injected on the loopback interface at the link
+/// layer as martians, so loopback probes are sent through the IP stack on a raw IPv4 socket that
+/// accepts our prebuilt IPv4 headers. The same socket serves other interfaces where packet
+/// sockets are unavailable; the kernel then fills in the IP identification field and picks the
+/// destination MAC address from its own neighbor table.
+fn create_inet_raw_sender(ethernet_conf: &EthernetConf) -> Result<AsyncSocket> {
+    let socket = Socket::new(
+        Domain::IPV4,
+        Type::RAW,
+        Some(Protocol::from(libc::IPPROTO_RAW)),
+    )?;
+
+    socket.set_nonblocking(true)?;
+    let rw_timeout = Some(Duration::from_millis(1));
+    socket.set_write_timeout(rw_timeout)?;
+    socket.set_read_timeout(rw_timeout)?;
+    // raw IPv4 sockets refuse to send to broadcast addresses without SO_BROADCAST
+    socket.set_broadcast(true)?;
+
+    bind_sender_to_device(&socket, ethernet_conf)?;
+
+    let mut socket = AsyncSocket::new(socket)?;
+    socket.set_link_type(LinkType::Loopback);
+    Ok(socket)
+}
+
+/// Ping sockets let unprivileged users send echo requests, with the kernel building the IPv4 and
+/// link-layer headers and picking the route. The kernel replaces the identifier of every request
+/// with the port the socket is bound to and delivers the replies carrying it to that socket
+/// alone, so the socket is bound to `identifier` and also serves to receive replies. ICMP error
+/// messages, eg Time Exceeded, are never delivered, so their probes time out.
+fn create_ping_socket(ethernet_conf: &EthernetConf, identifier: u16) -> Result<AsyncSocket> {
+    let socket = socket::ping_socket().map_err(|e| {
+        Error::GenericStringError(format!(
+            "failed to open ping socket (is your group in net.ipv4.ping_group_range?): {e}"
+        ))
+    })?;
+
+    socket.set_nonblocking(true)?;
+    socket.set_broadcast(true)?;
+    // sibling Probers share the identifier, each on the address of its own interface
+    socket.set_reuse_address(true)?;
+    // the TTL and TOS of replies stand in for the IPv4 header the kernel strips
+    set_ip_option(&socket, libc::IP_RECVTTL, 1)?;
+    set_ip_option(&socket, libc::IP_RECVTOS, 1)?;
+
+    let address = ethernet_conf.interface.address;
+    socket
+        .bind(&SocketAddrV4::new(address, identifier).into())
+        .map_err(|e| {
+            Error::GenericStringError(format!(
+                "failed to bind ping socket to {address} with identifier {identifier}: {e}"
+            ))
+        })?;
+    tracing::debug!("bound ping socket to {address} with identifier {identifier}");
+
+    let mut socket = AsyncSocket::new(socket)?;
+    socket.set_ping_address(address);
+    Ok(socket)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::mem::MaybeUninit;
+
+    use pnet::packet::icmp::{IcmpCode, IcmpTypes, MutableIcmpPacket};
+    use pnet::packet::ipv4::{Ipv4Packet, MutableIpv4Packet};
+    use pnet::packet::MutablePacket;
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+    use super::*;
+    use crate::probes::icmp::{IcmpOptions, IcmpProbe};
+
+    const TARGET: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+    const TEST_SOURCE: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 100);
+
+    /// A `ProbeSocket` backed by channels rather than a network interface: buffers sent on it are
+    /// passed to the test, and buffers the test queues with `reply_after` are received from it.
+    #[derive(Debug)]
+    struct MockSocket {
+        sent: UnboundedSender<Vec<u8>>,
+        replies: Mutex<UnboundedReceiver<Vec<u8>>>,
+        reply_sender: UnboundedSender<Vec<u8>>,
+
+        /// Errors returned by the next sends, in order, before sends succeed again.
+        send_errors: std::sync::Mutex<VecDeque<std::io::Error>>,
+
+        /// Errors returned by the next receives, in order, before replies are received again.
+        recv_errors: std::sync::Mutex<VecDeque<std::io::Error>>,
+    }
+
+    impl MockSocket {
+        /// Create a socket along with the channel on which the buffers sent on it are passed.
+        fn new() -> (Arc<Self>, UnboundedReceiver<Vec<u8>>) {
+            let (sent, sent_receiver) = unbounded_channel();
+            let (reply_sender, replies) = unbounded_channel();
+            let socket = Self {
+                sent,
+                replies: Mutex::new(replies),
+                reply_sender,
+                send_errors: std::sync::Mutex::new(VecDeque::new()),
+                recv_errors: std::sync::Mutex::new(VecDeque::new()),
+            };
+            (Arc::new(socket), sent_receiver)
+        }
+
+        /// Have `buf` received from the socket once `delay` has passed.
+        fn reply_after(&self, delay: Duration, buf: Vec<u8>) {
+            let reply_sender = self.reply_sender.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(delay).await;
+                let _ = reply_sender.send(buf);
+            });
+        }
+
+        /// Have the next sends fail with `errors`, one each.
+        fn fail_sends(&self, errors: impl IntoIterator<Item = std::io::Error>) {
+            self.send_errors.lock().unwrap().extend(errors);
+        }
+
+        /// Have the next receives fail with `errors`, one each.
+        fn fail_recvs(&self, errors: impl IntoIterator<Item = std::io::Error>) {
+            self.recv_errors.lock().unwrap().extend(errors);
+        }
+    }
+
+    #[async_trait]
+    impl ProbeSocket for MockSocket {
+        async fn recv(&self, buf: &mut [MaybeUninit<u8>]) -> std::io::Result<usize> {
+            if let Some(e) = self.recv_errors.lock().unwrap().pop_front() {
+                return Err(e);
+            }
+            let reply = self
+                .replies
+                .lock()
+                .await
+                .recv()
+                .await
+                .ok_or(std::io::ErrorKind::BrokenPipe)?;
+            for (slot, byte) in buf.iter_mut().zip(&reply) {
+                slot.write(*byte);
+            }
+            Ok(reply.len().min(buf.len()))
+        }
+
+        async fn send(&self, buf: &[u8]) -> std::io::Result<usize> {
+            if let Some(e) = self.send_errors.lock().unwrap().pop_front() {
+                return Err(e);
+            }
+            let _ = self.sent.send(buf.to_vec());
+            Ok(buf.len())
+        }
+    }
+
+    /// The echo reply the target of `request`, an IPv4 packet carrying an echo request, would
+    /// send back.
+    fn echo_reply(request: &[u8]) -> Vec<u8> {
+        let mut reply = request.to_vec();
+        let mut ipv4_packet = MutableIpv4Packet::new(&mut reply).unwrap();
+        let (source, destination) = (ipv4_packet.get_source(), ipv4_packet.get_destination());
+        ipv4_packet.set_source(destination);
+        ipv4_packet.set_destination(source);
+        ipv4_packet.set_ttl(64);
+        ipv4_packet.set_checksum(0);
+        let checksum = pnet::packet::ipv4::checksum(&ipv4_packet.to_immutable());
+        ipv4_packet.set_checksum(checksum);
+        let mut icmp_packet = MutableIcmpPacket::new(ipv4_packet.payload_mut()).unwrap();
+        icmp_packet.set_icmp_type(IcmpTypes::EchoReply);
+        icmp_packet.set_checksum(0);
+        let checksum = pnet::packet::icmp::checksum(&icmp_packet.to_immutable());
+        icmp_packet.set_checksum(checksum);
+        reply
+    }
+
+    /// The wire sequence number of the echo request `request`.
+    fn sequence_number(request: &[u8]) -> u16 {
+        let offset = Ipv4Packet::minimum_packet_size() + 6;
+        u16::from_be_bytes([request[offset], request[offset + 1]])
+    }
+
+    fn request(seq: u16) -> ProbeRequest {
+        ProbeRequest {
+            tparams: TargetParams {
+                addr: TARGET,
+                seq,
+                probe: ProbeType::Echo,
+            },
+            index: u64::from(seq),
+            interval: None,
+            notify: None,
+            broadcast: false,
+            warmup: false,
+            instance: None,
+            send_options: SendOptions {
+                payload_size: 4,
+                ..SendOptions::default()
+            },
+            scheduled: None,
+            overran: false,
+            slot: None,
+            span: tracing::Span::none(),
+        }
+    }
+
+    /// A `Prober` running a single `IcmpProbe` on `socket`, along with the channel on which it
+    /// receives requests, the channel on which it sends reports and the task running it.
+    fn start(
+        socket: Arc<MockSocket>,
+        timeout: Duration,
+    ) -> (
+        ACSender<ProbeRequest>,
+        Receiver<ProbeReport<IcmpProbe>>,
+        tokio::task::JoinHandle<Result<ProberStats>>,
+    ) {
+        let (prober, requests, reports) = Prober::<IcmpProbe>::new().unwrap();
+        let ethernet_conf = EthernetConf::fabricated(LinkType::RawIp, &[(TEST_SOURCE, 24)]);
+        let options = IcmpOptions {
+            record_route: false,
+            timestamp: false,
+            echo_type: IcmpTypes::EchoRequest,
+            code: IcmpCode(0),
+            tag: prober.tag(),
+        };
+        let probe = IcmpProbe::new(
+            Arc::new(ethernet_conf.clone()),
+            &options,
+            prober.tag().identifier(0),
+        )
+        .unwrap();
+        let run = tokio::spawn(prober.run_probes_on(
+            vec![probe],
+            ethernet_conf,
+            timeout,
+            socket.clone(),
+            vec![socket],
+        ));
+        (requests, reports, run)
+    }
+
+    #[tokio::test]
+    async fn reply_completes_probe() {
+        let (socket, mut sent) = MockSocket::new();
+        let (requests, mut reports, run) = start(socket.clone(), Duration::from_secs(1));
+
+        requests.send(request(7)).await.unwrap();
+        let frame = sent.recv().await.unwrap();
+        let ipv4_packet = Ipv4Packet::new(&frame).unwrap();
+        assert_eq!(ipv4_packet.get_source(), TEST_SOURCE);
+        assert_eq!(ipv4_packet.get_destination(), TARGET);
+        assert_eq!(sequence_number(&frame), 7);
+        socket.reply_after(Duration::ZERO, echo_reply(&frame));
+
+        let report = reports.recv().await.unwrap();
+        assert_eq!(report.tparams.seq, 7);
+        assert_eq!(report.index, 7);
+        match &report.outcome {
+            ProbeOutcome::ReceivedOutput(output, _) => {
+                assert_eq!(output.status(), ProbeStatus::Replied);
+                assert_eq!(output.source(), TARGET);
+                assert_eq!(output.reply_ttl(), Some(64));
+                assert_eq!(output.corruption(), None);
+            }
+            _ => panic!("expected a reply, got {:?}", report.outcome.status()),
+        }
+        assert!(report.sent_at.is_some());
+
+        drop(requests);
+        let stats = run.await.unwrap().unwrap();
+        assert_eq!(stats.dropped_replies, 0);
+        assert_eq!(stats.worker_packets, vec![1]);
+    }
 
-    Ok(AsyncSocket::new(socket)?)
+    #[tokio::test(start_paused = true)]
+    async fn unanswered_probe_times_out() {
+        let (socket, mut sent) = MockSocket::new();
+        let (requests, mut reports, run) = start(socket.clone(), Duration::from_secs(1));
+
+        let start = tokio::time::Instant::now();
+        requests.send(request(1)).await.unwrap();
+        let frame = sent.recv().await.unwrap();
+
+        let report = reports.recv().await.unwrap();
+        assert_eq!(report.outcome.status(), ProbeStatus::TimedOut);
+        assert!(start.elapsed() >= Duration::from_secs(1));
+
+        // a reply arriving after the probe timed out matches nothing
+        socket.reply_after(Duration::ZERO, echo_reply(&frame));
+        drop(requests);
+        run.await.unwrap().unwrap();
+        assert!(reports.try_recv().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn probe_whose_waiter_is_replaced_is_reported() {
+        let (socket, mut sent) = MockSocket::new();
+        let (requests, mut reports, run) = start(socket.clone(), Duration::from_secs(1));
+
+        // a probe sharing the wire sequence number of one still awaiting its reply, as after
+        // the sequence numbers of a long run wrap around, takes over the waiter
+        let start = tokio::time::Instant::now();
+        requests.send(request(1)).await.unwrap();
+        sent.recv().await.unwrap();
+        let mut wrapped = request(1);
+        wrapped.index += 1 << 16;
+        requests.send(wrapped).await.unwrap();
+        let frame = sent.recv().await.unwrap();
+
+        let report = reports.recv().await.unwrap();
+        assert_eq!(report.index, 1);
+        assert_eq!(report.outcome.status(), ProbeStatus::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        socket.reply_after(Duration::ZERO, echo_reply(&frame));
+        let report = reports.recv().await.unwrap();
+        assert_eq!(report.index, 1 + (1 << 16));
+        assert_eq!(report.outcome.status(), ProbeStatus::Replied);
+
+        drop(requests);
+        run.await.unwrap().unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn replies_out_of_order_complete_their_own_probes() {
+        let (socket, mut sent) = MockSocket::new();
+        let (requests, mut reports, run) = start(socket.clone(), Duration::from_secs(1));
+
+        for seq in 0..3 {
+            requests.send(request(seq)).await.unwrap();
+        }
+        let mut frames = Vec::new();
+        for _ in 0..3 {
+            frames.push(sent.recv().await.unwrap());
+        }
+        // the first request is answered last and the second not at all
+        socket.reply_after(Duration::from_millis(30), echo_reply(&frames[0]));
+        socket.reply_after(Duration::from_millis(10), echo_reply(&frames[2]));
+
+        let mut outcomes = Vec::new();
+        for _ in 0..3 {
+            let report = reports.recv().await.unwrap();
+            outcomes.push((report.tparams.seq, report.outcome.status()));
+        }
+        assert_eq!(
+            outcomes,
+            vec![
+                (2, ProbeStatus::Replied),
+                (0, ProbeStatus::Replied),
+                (1, ProbeStatus::TimedOut),
+            ]
+        );
+        drop(requests);
+        run.await.unwrap().unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn send_is_retried_while_socket_would_block() {
+        let (socket, mut sent) = MockSocket::new();
+        socket.fail_sends([
+            std::io::ErrorKind::WouldBlock.into(),
+            std::io::ErrorKind::WouldBlock.into(),
+        ]);
+        let (requests, mut reports, run) = start(socket.clone(), Duration::from_secs(1));
+
+        requests.send(request(5)).await.unwrap();
+        let frame = sent.recv().await.unwrap();
+        socket.reply_after(Duration::ZERO, echo_reply(&frame));
+
+        let report = reports.recv().await.unwrap();
+        assert_eq!(report.outcome.status(), ProbeStatus::Replied);
+        assert_eq!(report.send_retries, 2);
+        drop(requests);
+        run.await.unwrap().unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn send_fails_once_retries_are_exhausted() {
+        let (socket, _sent) = MockSocket::new();
+        socket.fail_sends((0..3).map(|_| std::io::Error::from_raw_os_error(libc::ENOBUFS)));
+        let (requests, mut reports, run) = start(socket.clone(), Duration::from_secs(1));
+
+        requests.send(request(5)).await.unwrap();
+        let report = reports.recv().await.unwrap();
+        assert_eq!(report.outcome.status(), ProbeStatus::SendFailed);
+        assert_eq!(report.send_retries, 2);
+        assert!(report.sent_at.is_none());
+
+        // the next probe goes out normally
+        requests.send(request(6)).await.unwrap();
+        assert_eq!(
+            reports.recv().await.unwrap().outcome.status(),
+            ProbeStatus::TimedOut
+        );
+        drop(requests);
+        run.await.unwrap().unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn permanent_send_errors_are_not_retried() {
+        let (socket, mut sent) = MockSocket::new();
+        socket.fail_sends([
+            std::io::ErrorKind::PermissionDenied.into(),
+            std::io::ErrorKind::WouldBlock.into(),
+        ]);
+        let (requests, mut reports, run) = start(socket.clone(), Duration::from_secs(1));
+
+        requests.send(request(5)).await.unwrap();
+        let report = reports.recv().await.unwrap();
+        assert_eq!(report.outcome.status(), ProbeStatus::SendFailed);
+        assert_eq!(report.send_retries, 0);
+        // the would-block error was left for the next probe rather than taken by a retry
+        requests.send(request(6)).await.unwrap();
+        let frame = sent.recv().await.unwrap();
+        assert_eq!(sequence_number(&frame), 6);
+        drop(requests);
+        run.await.unwrap().unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn transient_recv_errors_are_retried() {
+        let (socket, mut sent) = MockSocket::new();
+        socket.fail_recvs([
+            std::io::ErrorKind::Interrupted.into(),
+            std::io::Error::from_raw_os_error(libc::ENOBUFS),
+        ]);
+        let (requests, mut reports, run) = start(socket.clone(), Duration::from_secs(1));
+
+        requests.send(request(5)).await.unwrap();
+        let frame = sent.recv().await.unwrap();
+        socket.reply_after(Duration::ZERO, echo_reply(&frame));
+
+        let report = reports.recv().await.unwrap();
+        assert_eq!(report.outcome.status(), ProbeStatus::Replied);
+        drop(requests);
+        run.await.unwrap().unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn permanent_recv_errors_fail_the_run() {
+        let (socket, _sent) = MockSocket::new();
+        socket.fail_recvs([std::io::ErrorKind::PermissionDenied.into()]);
+        let (requests, _reports, run) = start(socket.clone(), Duration::from_secs(1));
+
+        requests.send(request(5)).await.unwrap();
+        let err = run.await.unwrap().unwrap_err();
+        assert!(err.to_string().contains("failed to receive replies"));
+    }
+
+    #[test]
+    fn probe_slots_own_an_identifier_each() {
+        let (mut prober, _requests, _reports) = Prober::<IcmpProbe>::new().unwrap();
+        prober.set_probe_slots(1000);
+        let tag = prober.tag();
+        let owned: HashSet<u16> = (0..=u16::MAX).filter(|id| tag.owns(*id)).collect();
+        let slots: HashSet<u16> = (0..1000).map(|slot| tag.identifier(slot)).collect();
+        assert_eq!(owned.len(), 1000);
+        assert_eq!(owned, slots);
+    }
+
+    #[test]
+    fn probe_slots_may_take_every_identifier() {
+        let (mut prober, _requests, _reports) = Prober::<IcmpProbe>::new().unwrap();
+        prober.set_probe_slots(1 << 16);
+        let tag = prober.tag();
+        assert_eq!(tag.identifier(0), 0);
+        assert_eq!(tag.identifier(0xffff), 0xffff);
+        assert!((0..=u16::MAX).all(|id| tag.owns(id)));
+        assert_eq!(tag.to_string(), "identifiers 0..=65535");
+
+        // slots beyond the identifiers share them
+        prober.set_probe_slots((1 << 16) + 1);
+        assert_eq!(prober.tag().identifier(1 << 16), 0);
+    }
+
+    /// A probe for a peer that sends each request back as its response. Requests carry just the
+    /// target, the sequence number and the nonce.
+    #[derive(Debug)]
+    struct EchoBack {
+        tag: RequestTag,
+    }
+
+    #[derive(Clone, Debug, Serialize)]
+    struct Echoed {
+        addr: Ipv4Addr,
+        seq: u16,
+    }
+
+    impl std::fmt::Display for Echoed {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "{},{}", self.addr, self.seq)
+        }
+    }
+
+    impl ProbeOutput for Echoed {
+        fn source(&self) -> Ipv4Addr {
+            self.addr
+        }
+    }
+
+    #[async_trait]
+    impl Probe for EchoBack {
+        type Output = Echoed;
+
+        async fn send(
+            &mut self,
+            socket: &dyn ProbeSocket,
+            params: &TargetParams,
+            _options: &SendOptions,
+        ) -> Result<()> {
+            let mut request = params.addr.octets().to_vec();
+            request.extend_from_slice(&params.seq.to_be_bytes());
+            request.extend_from_slice(&self.tag.nonce.to_be_bytes());
+            socket.send(&request).await?;
+            Ok(())
+        }
+
+        fn reconfigure(&mut self, _ec: &EthernetConf) -> Result<()> {
+            Ok(())
+        }
+
+        fn validate_response(
+            buf: &[u8],
+            _ec: &EthernetConf,
+            tag: &RequestTag,
+        ) -> Option<Response<Echoed>> {
+            // responses to other `Prober`s' requests don't carry our nonce
+            if buf.len() != 10 || buf[6..] != tag.nonce.to_be_bytes() {
+                return None;
+            }
+            let addr = Ipv4Addr::new(buf[0], buf[1], buf[2], buf[3]);
+            let seq = u16::from_be_bytes([buf[4], buf[5]]);
+            // the same parameters the request was sent with
+            let params = TargetParams {
+                addr,
+                seq,
+                probe: ProbeType::Echo,
+            };
+            Some(Response::Matched(params, Echoed { addr, seq }))
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn documented_probe_upholds_contract() {
+        let (socket, mut sent) = MockSocket::new();
+        let (prober, requests, mut reports) = Prober::<EchoBack>::new().unwrap();
+        let ethernet_conf = EthernetConf::fabricated(LinkType::RawIp, &[(TEST_SOURCE, 24)]);
+        let probes = vec![EchoBack { tag: prober.tag() }];
+        let run = tokio::spawn(prober.run_probes_on(
+            probes,
+            ethernet_conf,
+            Duration::from_secs(1),
+            socket.clone(),
+            vec![socket.clone()],
+        ));
+
+        requests.send(request(1)).await.unwrap();
+        requests.send(request(2)).await.unwrap();
+        let answered = sent.recv().await.unwrap();
+        let mut forged = sent.recv().await.unwrap();
+        socket.reply_after(Duration::ZERO, answered);
+        // a response with someone else's nonce doesn't answer the probe
+        forged[6] ^= 0xff;
+        socket.reply_after(Duration::ZERO, forged);
+
+        let first = reports.recv().await.unwrap();
+        assert_eq!(first.tparams.seq, 1);
+        assert_eq!(first.outcome.status(), ProbeStatus::Replied);
+        let second = reports.recv().await.unwrap();
+        assert_eq!(second.tparams.seq, 2);
+        assert_eq!(second.outcome.status(), ProbeStatus::TimedOut);
+        drop(requests);
+        run.await.unwrap().unwrap();
+    }
 }
@@ -1,205 +1,1249 @@
+use std::collections::HashMap;
 use std::net::Ipv4Addr;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::{Instant, SystemTime};
 
 use async_trait::async_trait;
 use pnet::packet::{
     ethernet::MutableEthernetPacket,
     icmp::echo_reply::EchoReplyPacket,
-    icmp::{echo_request::MutableEchoRequestPacket, IcmpCode, IcmpPacket, IcmpTypes},
+    icmp::echo_request::{EchoRequestPacket, MutableEchoRequestPacket},
+    icmp::time_exceeded::TimeExceededPacket,
+    icmp::{IcmpCode, IcmpPacket, IcmpType, IcmpTypes},
     ip::IpNextHeaderProtocols,
-    ipv4::{Ipv4Packet, MutableIpv4Packet},
+    ipv4::{Ipv4Flags, Ipv4Packet, MutableIpv4Packet},
     MutablePacket, Packet,
 };
 use serde::Serialize;
 
+use crate::checksum;
 use crate::error::Result;
-use crate::ethernet::EthernetConf;
-use crate::prober::{Probe, TargetParams};
-use crate::socket::AsyncSocket;
+use crate::ethernet::{EthernetConf, IpVersion};
+use crate::prober::{
+    Corruption, Probe, ProbeOutput, ProbeStatus, RequestTag, Response, SendOptions, TargetParams,
+};
+use crate::socket::ProbeSocket;
+use crate::target::ProbeType;
 
-const ETHERNET_PACKET_MIN_SIZE: usize = MutableEthernetPacket::minimum_packet_size();
 const IPV4_PACKET_MIN_SIZE: usize = Ipv4Packet::minimum_packet_size();
-const ICMP_REQUEST_PACKET_SIZE: usize = ETHERNET_PACKET_MIN_SIZE
-    + IPV4_PACKET_MIN_SIZE
-    + MutableEchoRequestPacket::minimum_packet_size();
-//TODO: check reply packet size in validation method
+/// Size of the IPv4 Record Route option including its trailing End of Options List padding byte:
+/// type, length and pointer bytes followed by room for nine addresses.
+const RECORD_ROUTE_OPTION_SIZE: usize = 40;
+const RECORD_ROUTE_OPTION_TYPE: u8 = 7;
+/// Size of the nonce following the ICMP Echo header.
+const ICMP_ECHO_NONCE_SIZE: usize = 4;
+/// Size of the originate, receive and transmit timestamps following the ICMP Timestamp header.
+const ICMP_TIMESTAMP_DATA_SIZE: usize = 12;
+/// ICMP timestamps count milliseconds since midnight UT.
+const MILLIS_PER_DAY: i64 = 24 * 60 * 60 * 1000;
+/// Timestamps with the high-order bit set aren't milliseconds since midnight UT.
+const NONSTANDARD_TIMESTAMP: u32 = 1 << 31;
 const ICMP_REPLY_PACKET_SIZE: usize = EchoReplyPacket::minimum_packet_size();
 
+/// Optional features of the echo requests sent by an `IcmpProbe`.
+#[derive(Clone, Debug)]
+pub struct IcmpOptions {
+    /// Include the IPv4 Record Route option so that replies carry the addresses of up to nine
+    /// hops along the path.
+    pub record_route: bool,
+
+    /// Send ICMP Timestamp requests rather than echo requests, so that replies carry the
+    /// target's clock.
+    pub timestamp: bool,
+
+    /// Type of echo requests, normally Echo Request. Timestamp requests keep their own type.
+    pub echo_type: IcmpType,
+
+    /// Code of all requests, normally zero.
+    pub code: IcmpCode,
+
+    /// The `Prober`'s tag. Requests carry one of its identifiers, and echo requests carry its
+    /// nonce as their payload; timestamp requests have a fixed size and carry none.
+    pub tag: RequestTag,
+}
+
+/// Length of the IPv4 packets carrying echo requests with `payload_size` bytes of data.
+pub fn echo_packet_len(record_route: bool, payload_size: usize) -> usize {
+    let ipv4_header_len = if record_route {
+        IPV4_PACKET_MIN_SIZE + RECORD_ROUTE_OPTION_SIZE
+    } else {
+        IPV4_PACKET_MIN_SIZE
+    };
+    ipv4_header_len + MutableEchoRequestPacket::minimum_packet_size() + payload_size
+}
+
+/// Length of the IPv4 packets carrying requests of kind `probe`, where echo requests carry
+/// `payload_size` bytes of data.
+pub fn request_packet_len(probe: ProbeType, record_route: bool, payload_size: usize) -> usize {
+    match probe {
+        ProbeType::Echo => echo_packet_len(record_route, payload_size),
+        // timestamp requests share the echo request header
+        ProbeType::Timestamp => echo_packet_len(record_route, ICMP_TIMESTAMP_DATA_SIZE),
+    }
+}
+
 /// Implementation of `Probe` trait to enable a `Prober` to conduct ICMP echo probes.
 #[derive(Debug)]
 pub struct IcmpProbe {
-    buf: Arc<Mutex<[u8; ICMP_REQUEST_PACKET_SIZE]>>,
+    /// Requests by the size of the data following the ICMP header and whether they set Don't
+    /// Fragment, each built when first needed and then updated in place for every request of that
+    /// kind.
+    templates: HashMap<(usize, bool), Vec<u8>>,
+
+    /// ICMP identifier of this probe's slot, carried by all its requests.
+    identifier: u16,
+
+    /// Offset of the IPv4 header within each request; zero on links without Ethernet framing.
+    ipv4_offset: usize,
+
+    /// Whether requests are Timestamp rather than Echo requests.
+    timestamp: bool,
+    ethernet_conf: Arc<EthernetConf>,
+
+    /// Options the request was built with, kept to rebuild it for a new link configuration.
+    options: IcmpOptions,
 }
 
 impl IcmpProbe {
-    pub fn many(count: usize, ethernet_conf: &EthernetConf) -> Result<Vec<Self>> {
+    pub fn many(
+        count: usize,
+        ethernet_conf: &EthernetConf,
+        options: &IcmpOptions,
+    ) -> Result<Vec<Self>> {
+        let ethernet_conf = Arc::new(ethernet_conf.clone());
         let mut v = Vec::new();
-        for _ in 0..count {
-            v.push(Self::new(ethernet_conf)?);
+        for slot in 0..count {
+            let identifier = options.tag.identifier(slot);
+            v.push(Self::new(ethernet_conf.clone(), options, identifier)?);
         }
         Ok(v)
     }
 
-    pub fn new(ethernet_conf: &EthernetConf) -> Result<Self> {
-        let mut buf = [0u8; ICMP_REQUEST_PACKET_SIZE];
-        {
-            let mut ethernet_packet = MutableEthernetPacket::new(&mut buf).expect("meow");
-            log::trace!("ethernet_packet len: {}", ethernet_packet.packet().len());
+    pub fn new(
+        ethernet_conf: Arc<EthernetConf>,
+        options: &IcmpOptions,
+        identifier: u16,
+    ) -> Result<Self> {
+        Ok(Self {
+            templates: HashMap::new(),
+            identifier,
+            ipv4_offset: ethernet_conf.link_header_len(),
+            timestamp: options.timestamp,
+            ethernet_conf,
+            options: options.clone(),
+        })
+    }
+
+    /// Size of the data following the ICMP header of requests sent with `options`. Timestamp
+    /// requests carry exactly three timestamps, while echo requests carry at least the nonce.
+    fn data_size(&self, options: &SendOptions) -> usize {
+        if self.timestamp {
+            ICMP_TIMESTAMP_DATA_SIZE
+        } else {
+            options.payload_size.max(ICMP_ECHO_NONCE_SIZE)
+        }
+    }
+
+    /// Build a request carrying `data_size` bytes of data after the ICMP header, addressed to no
+    /// one in particular. With `dont_fragment` it sets the Don't Fragment flag, so that routers
+    /// report it if it's too big for the next hop rather than fragmenting it.
+    fn build(&self, data_size: usize, dont_fragment: bool) -> Vec<u8> {
+        let ethernet_conf = &self.ethernet_conf;
+        let options = &self.options;
+        // every header following the IPv4 options shifts along by their size
+        let ipv4_header_len = if options.record_route {
+            IPV4_PACKET_MIN_SIZE + RECORD_ROUTE_OPTION_SIZE
+        } else {
+            IPV4_PACKET_MIN_SIZE
+        };
+        // timestamp requests share the echo request header
+        let icmp_len = MutableEchoRequestPacket::minimum_packet_size() + data_size;
+        let ipv4_offset = self.ipv4_offset;
+        let len = ipv4_offset + ipv4_header_len + icmp_len;
+        let mut buf = vec![0u8; len];
+        if ipv4_offset > 0 {
+            let mut ethernet_packet = MutableEthernetPacket::new(&mut buf[..len]).expect("meow");
+            tracing::trace!("ethernet_packet len: {}", ethernet_packet.packet().len());
             ethernet_packet.set_source(ethernet_conf.ethernet_info.source);
             ethernet_packet.set_destination(ethernet_conf.ethernet_info.destination);
-            ethernet_packet.set_ethertype(ethernet_conf.ethernet_info.ethertype);
-
-            log::trace!(
-                "ethernet_packet payload len: {}",
-                ethernet_packet.payload().len()
-            );
-            let mut ipv4_packet =
-                MutableIpv4Packet::new(ethernet_packet.payload_mut()).expect("meow");
-            log::trace!("ipv4_packetlen: {}", ipv4_packet.packet().len());
+            ethernet_packet.set_ethertype(ethernet_conf.ethertype(IpVersion::V4));
+        }
+        {
+            let mut ipv4_packet = MutableIpv4Packet::new(&mut buf[ipv4_offset..len]).expect("meow");
+            tracing::trace!("ipv4_packetlen: {}", ipv4_packet.packet().len());
             ipv4_packet.set_version(4);
             ipv4_packet.set_source(ethernet_conf.interface.address);
             ipv4_packet.set_next_level_protocol(IpNextHeaderProtocols::Icmp);
-            ipv4_packet.set_header_length(5);
+            ipv4_packet.set_header_length((ipv4_header_len / 4) as u8);
+            if options.record_route {
+                ipv4_packet.packet_mut()[IPV4_PACKET_MIN_SIZE..ipv4_header_len]
+                    .copy_from_slice(&record_route_option());
+            }
             ipv4_packet.set_ttl(101); // not sure what a good value here would be so i picked this
+            if dont_fragment {
+                ipv4_packet.set_flags(Ipv4Flags::DontFragment);
+            }
             ipv4_packet.set_checksum(0); // not sure what a good value here would be so i picked this
-            ipv4_packet.set_total_length(
-                (MutableIpv4Packet::minimum_packet_size()
-                    + MutableEchoRequestPacket::minimum_packet_size()) as u16,
-            );
+            ipv4_packet.set_total_length((ipv4_header_len + icmp_len) as u16);
             // arbitrarily
             let checksum = pnet::packet::ipv4::checksum(
                 &Ipv4Packet::new(ipv4_packet.packet()).expect("the buf size should be fine"),
             );
             ipv4_packet.set_checksum(checksum);
 
-            log::trace!("ipv4 len: {}", MutableIpv4Packet::minimum_packet_size());
-            log::trace!(
+            tracing::trace!("ipv4 len: {}", MutableIpv4Packet::minimum_packet_size());
+            tracing::trace!(
                 "icmp min len: {}",
                 MutableEchoRequestPacket::minimum_packet_size()
             );
-            log::trace!("ipv4_packet total len: {}", ipv4_packet.get_total_length());
-            log::trace!("ipv4_packet payload len: {}", ipv4_packet.payload().len());
+            tracing::trace!("ipv4_packet total len: {}", ipv4_packet.get_total_length());
+            tracing::trace!("ipv4_packet payload len: {}", ipv4_packet.payload().len());
             let mut icmp_packet = MutableEchoRequestPacket::new(ipv4_packet.payload_mut())
-                .expect("the buf size should be exactly the minimum icmp packet size");
-            icmp_packet.set_icmp_type(IcmpTypes::EchoRequest);
-            icmp_packet.set_icmp_code(IcmpCode(0));
-            icmp_packet.set_identifier(42);
+                .expect("the buf size should be at least the minimum icmp packet size");
+            icmp_packet.set_icmp_type(if options.timestamp {
+                IcmpTypes::Timestamp
+            } else {
+                options.echo_type
+            });
+            icmp_packet.set_icmp_code(options.code);
+            icmp_packet.set_identifier(self.identifier);
+            if !options.timestamp {
+                let payload = icmp_packet.payload_mut();
+                payload[..ICMP_ECHO_NONCE_SIZE].copy_from_slice(&options.tag.nonce.to_be_bytes());
+                for (offset, byte) in payload.iter_mut().enumerate().skip(ICMP_ECHO_NONCE_SIZE) {
+                    *byte = padding_byte(offset);
+                }
+            }
+            // later updates adjust this checksum incrementally, so it must start out valid
+            let checksum = pnet::packet::icmp::checksum(
+                &IcmpPacket::new(icmp_packet.packet())
+                    .expect("the buf size should be at least the minimum icmp packet size"),
+            );
+            icmp_packet.set_checksum(checksum);
         }
-
-        Ok(Self {
-            buf: Arc::new(Mutex::new(buf)),
-        })
+        buf
     }
 
-    /// Updates the icmp buffer with the current icmp sequence and the new icmp checksum.
-    async fn update_icmp_request_packet(&mut self, addr: &Ipv4Addr, seq: u16) {
-        let mut buf = self.buf.lock().await;
-        let slice = buf.as_mut_slice();
-        let mut ethernet_packet = MutableEthernetPacket::new(slice).expect("meow");
+    /// Updates the request of `template` (its data size and whether it sets Don't Fragment) with
+    /// the current icmp sequence and the new icmp checksum. Only the source, destination, DSCP,
+    /// sequence number and timestamp change between probes of the same kind, so checksums are
+    /// adjusted for the changed fields rather than recomputed.
+    async fn update_icmp_request_packet(
+        &mut self,
+        addr: &Ipv4Addr,
+        seq: u16,
+        dscp: u8,
+        template: (usize, bool),
+    ) {
+        let destination_mac = if self.ipv4_offset > 0 {
+            Some(self.ethernet_conf.resolve_destination_mac(addr).await)
+        } else {
+            None
+        };
+        if !self.templates.contains_key(&template) {
+            let (data_size, dont_fragment) = template;
+            let request = self.build(data_size, dont_fragment);
+            self.templates.insert(template, request);
+        }
+        let slice = self
+            .templates
+            .get_mut(&template)
+            .expect("the template was just inserted");
+        if let Some(destination_mac) = destination_mac {
+            let mut ethernet_packet = MutableEthernetPacket::new(slice).expect("meow");
+            ethernet_packet.set_destination(destination_mac);
+        }
 
-        let mut ipv4_packet = MutableIpv4Packet::new(ethernet_packet.payload_mut()).expect("meow");
-        ipv4_packet.set_destination(addr.clone());
-        ipv4_packet.set_checksum(0);
-        let checksum = pnet::packet::ipv4::checksum(
-            &Ipv4Packet::new(ipv4_packet.packet()).expect("the buf size should be fine"),
+        let mut ipv4_packet = MutableIpv4Packet::new(&mut slice[self.ipv4_offset..]).expect("meow");
+        let mut checksum = checksum::update(
+            ipv4_packet.get_checksum(),
+            &ipv4_packet.get_destination().octets(),
+            &addr.octets(),
+        );
+        ipv4_packet.set_destination(*addr);
+        // the ICMP checksum doesn't cover the IPv4 addresses, unlike those of TCP or UDP
+        let source = self.ethernet_conf.source_address(addr);
+        checksum = checksum::update(
+            checksum,
+            &ipv4_packet.get_source().octets(),
+            &source.octets(),
+        );
+        ipv4_packet.set_source(source);
+        // the ToS byte shares a 16-bit word with the version and header length
+        let old_word = [ipv4_packet.packet()[0], ipv4_packet.packet()[1]];
+        ipv4_packet.set_dscp(dscp);
+        ipv4_packet.set_ecn(0);
+        checksum = checksum::update(
+            checksum,
+            &old_word,
+            &[ipv4_packet.packet()[0], ipv4_packet.packet()[1]],
         );
         ipv4_packet.set_checksum(checksum);
+        debug_assert_eq!(
+            checksum,
+            pnet::packet::ipv4::checksum(&ipv4_packet.to_immutable())
+        );
 
         let mut icmp_packet = MutableEchoRequestPacket::new(ipv4_packet.payload_mut())
-            .expect("the buf size should be exactly the minimum icmp packet size");
-        icmp_packet.set_sequence_number(seq);
-        icmp_packet.set_checksum(0);
-
-        let checksum = pnet::packet::icmp::checksum(
-            &IcmpPacket::new(icmp_packet.packet())
-                .expect("the buf size should be exactly the minimum icmp packet size"),
+            .expect("the buf size should be at least the minimum icmp packet size");
+        let mut checksum = checksum::update(
+            icmp_packet.get_checksum(),
+            &icmp_packet.get_sequence_number().to_be_bytes(),
+            &seq.to_be_bytes(),
         );
+        icmp_packet.set_sequence_number(seq);
+        if self.timestamp {
+            let originate = millis_since_midnight().to_be_bytes();
+            let payload = icmp_packet.payload_mut();
+            checksum = checksum::update(checksum, &payload[..4], &originate);
+            payload[..4].copy_from_slice(&originate);
+        }
         icmp_packet.set_checksum(checksum);
+        debug_assert_eq!(
+            checksum,
+            pnet::packet::icmp::checksum(
+                &IcmpPacket::new(icmp_packet.packet())
+                    .expect("the buf size should be at least the minimum icmp packet size"),
+            )
+        );
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Serialize)]
 pub struct IcmpOutput {
+    /// The host that sent the reply; for Time Exceeded messages this is the router at which the
+    /// probe's TTL expired rather than the target.
     addr: Ipv4Addr,
     seq: u16,
+    ttl_expired: bool,
+
+    /// Whether `addr` reported that the probe needed fragmenting but had Don't Fragment set,
+    /// along with the MTU of its next hop if it said.
+    frag_needed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_hop_mtu: Option<u16>,
+
+    /// Addresses recorded by the Record Route option of the reply, if present.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    route: Option<Vec<Ipv4Addr>>,
+
+    /// Estimated offset in milliseconds of the responder's clock from ours, for Timestamp replies
+    /// carrying standard timestamps.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clock_offset_ms: Option<i64>,
+
+    /// ToS byte of the reply, so that re-marking along the path is visible. Not set for Time
+    /// Exceeded messages, which routers mark as they please.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_tos: Option<u8>,
+
+    /// TTL of the reply as it arrived. Not set for ICMP error messages, which come from routers
+    /// along the path rather than the target.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_ttl: Option<u8>,
+
+    /// Number of hops the reply is estimated to have traversed, from its TTL.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hops: Option<u8>,
+
+    /// The gateway that a Redirect message from `addr` told us to use for the target instead.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gateway: Option<Ipv4Addr>,
+
+    /// Size of the data carried by an Echo Reply.
+    #[serde(skip)]
+    payload_len: Option<usize>,
+
+    /// Offset within an Echo Reply's data of the first byte differing from our padding.
+    #[serde(skip)]
+    padding_mismatch: Option<usize>,
+
+    /// How an Echo Reply's data differed from its request's, once checked.
+    #[serde(skip)]
+    corruption: Option<Corruption>,
+
+    /// Description of an ICMP error message of an unexpected type or code about the probe.
+    #[serde(skip)]
+    unexpected: Option<String>,
+
+    /// When the response arrived, as timestamped by the kernel or else when it was read.
+    #[serde(skip)]
+    received_at: Option<Instant>,
 }
 
 impl std::fmt::Display for IcmpOutput {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{},{}", self.addr, self.seq)
+        if let Some(gateway) = self.gateway {
+            write!(
+                f,
+                "redirected toward {gateway} by {},{}",
+                self.addr, self.seq
+            )
+        } else if self.ttl_expired {
+            write!(f, "TTL expired at {},{}", self.addr, self.seq)
+        } else if self.frag_needed {
+            write!(f, "fragmentation needed at {},{}", self.addr, self.seq)
+        } else {
+            write!(f, "{},{}", self.addr, self.seq)
+        }
+    }
+}
+
+impl ProbeOutput for IcmpOutput {
+    fn status(&self) -> ProbeStatus {
+        if self.gateway.is_some() {
+            ProbeStatus::Redirected
+        } else if self.ttl_expired {
+            ProbeStatus::TtlExpired
+        } else if self.frag_needed {
+            ProbeStatus::TooBig
+        } else {
+            match self.corruption {
+                Some(Corruption::Altered { .. }) => ProbeStatus::Corrupted,
+                Some(Corruption::Truncated { .. }) => ProbeStatus::Truncated,
+                None => ProbeStatus::Replied,
+            }
+        }
+    }
+
+    fn source(&self) -> Ipv4Addr {
+        self.addr
+    }
+
+    fn route(&self) -> Option<&[Ipv4Addr]> {
+        self.route.as_deref()
+    }
+
+    fn clock_offset_ms(&self) -> Option<i64> {
+        self.clock_offset_ms
+    }
+
+    fn reply_tos(&self) -> Option<u8> {
+        self.reply_tos
+    }
+
+    fn reply_ttl(&self) -> Option<u8> {
+        self.reply_ttl
+    }
+
+    fn hops(&self) -> Option<u8> {
+        self.hops
+    }
+
+    fn payload_len(&self) -> Option<usize> {
+        self.payload_len
+    }
+
+    fn gateway(&self) -> Option<Ipv4Addr> {
+        self.gateway
+    }
+
+    fn next_hop_mtu(&self) -> Option<u16> {
+        self.next_hop_mtu
+    }
+
+    fn unexpected(&self) -> Option<&str> {
+        self.unexpected.as_deref()
+    }
+
+    fn check_payload(&mut self, expected_len: usize) {
+        let len = match self.payload_len {
+            Some(len) => len,
+            None => return,
+        };
+        self.corruption = if len < expected_len {
+            Some(Corruption::Truncated {
+                len,
+                expected: expected_len,
+            })
+        } else if let Some(offset) = self.padding_mismatch.filter(|o| *o < expected_len) {
+            Some(Corruption::Altered { offset })
+        } else if len > expected_len {
+            // extra data is as much a departure from the request as altered data
+            Some(Corruption::Altered {
+                offset: expected_len,
+            })
+        } else {
+            None
+        };
+    }
+
+    fn corruption(&self) -> Option<Corruption> {
+        self.corruption
+    }
+
+    fn received_at(&self) -> Option<Instant> {
+        self.received_at
+    }
+
+    fn set_received_at(&mut self, at: Instant) {
+        self.received_at = Some(at);
+    }
+}
+
+/// The current time as an ICMP timestamp.
+fn millis_since_midnight() -> u32 {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    (now.as_millis() % MILLIS_PER_DAY as u128) as u32
+}
+
+/// The difference `a - b` between two ICMP timestamps, allowing for either having wrapped around
+/// at midnight.
+fn timestamp_diff(a: u32, b: u32) -> i64 {
+    let diff = (i64::from(a) - i64::from(b)).rem_euclid(MILLIS_PER_DAY);
+    if diff > MILLIS_PER_DAY / 2 {
+        diff - MILLIS_PER_DAY
+    } else {
+        diff
+    }
+}
+
+/// Estimate the offset of the responder's clock from ours given the data of a Timestamp reply
+/// arriving at `arrival`, assuming the path is symmetric.
+fn clock_offset_ms(data: &[u8], arrival: u32) -> Option<i64> {
+    let timestamp = |i: usize| -> Option<u32> {
+        Some(u32::from_be_bytes(data.get(i..i + 4)?.try_into().ok()?))
+    };
+    let (originate, receive, transmit) = (timestamp(0)?, timestamp(4)?, timestamp(8)?);
+    if (receive | transmit) & NONSTANDARD_TIMESTAMP != 0 {
+        tracing::trace!("timestamp reply carries non-standard timestamps");
+        return None;
     }
+    Some((timestamp_diff(receive, originate) + timestamp_diff(transmit, arrival)) / 2)
+}
+
+/// Estimate the number of hops a reply traversed from the TTL it arrived with, assuming the
+/// responder started it at the smallest of the common initial TTLs (64, 128 and 255) that's at
+/// least as large. Paths longer than the gap between those would be underestimated, but are rare.
+fn estimate_hops(ttl: u8) -> u8 {
+    let initial = [64, 128, 255]
+        .into_iter()
+        .find(|initial| ttl <= *initial)
+        .unwrap_or(u8::MAX);
+    initial - ttl
+}
+
+/// Build an empty Record Route option padded out to `RECORD_ROUTE_OPTION_SIZE` bytes.
+fn record_route_option() -> [u8; RECORD_ROUTE_OPTION_SIZE] {
+    let mut option = [0u8; RECORD_ROUTE_OPTION_SIZE];
+    option[0] = RECORD_ROUTE_OPTION_TYPE;
+    // the option itself excludes the final padding byte
+    option[1] = (RECORD_ROUTE_OPTION_SIZE - 1) as u8;
+    // the pointer is the 1-based offset within the option of the next free address slot
+    option[2] = 4;
+    option
+}
+
+/// Extract the addresses recorded by a Record Route option among the given IPv4 header options.
+/// Returns `None` if there is no such option, eg because a router stripped it, and stops at the
+/// first malformed option.
+fn parse_record_route(options: &[u8]) -> Option<Vec<Ipv4Addr>> {
+    let mut i = 0;
+    while i < options.len() {
+        match options[i] {
+            // End of Options List
+            0 => break,
+            // No Operation
+            1 => i += 1,
+            option_type => {
+                let len = *options.get(i + 1)? as usize;
+                if len < 2 || i + len > options.len() {
+                    tracing::trace!("malformed ip option of type {option_type}");
+                    return None;
+                }
+                if option_type == RECORD_ROUTE_OPTION_TYPE && len >= 3 {
+                    let option = &options[i..i + len];
+                    // recorded addresses occupy the bytes before the pointer
+                    let end = (option[2] as usize).saturating_sub(1).clamp(3, len);
+                    let route: Vec<Ipv4Addr> = option[3..end]
+                        .chunks_exact(4)
+                        .map(|a| Ipv4Addr::new(a[0], a[1], a[2], a[3]))
+                        .collect();
+                    if option[2] as usize > len {
+                        // hops beyond this point, including any on the return path, went
+                        // unrecorded
+                        tracing::debug!("record route option filled up after {} hops", route.len());
+                    }
+                    return Some(route);
+                }
+                i += len;
+            }
+        }
+    }
+    None
+}
+
+/// Recover the target of the request embedded in an ICMP error message sent by `router`. The
+/// embedded packet is our original IPv4 header followed by at least the first 8 bytes of its
+/// payload, which for our requests includes the identifier and sequence number. Returns `None` if
+/// the embedded packet is truncated or isn't one of our requests.
+fn parse_embedded_request(
+    router: Ipv4Addr,
+    embedded: &[u8],
+    ec: &EthernetConf,
+    tag: &RequestTag,
+) -> Option<TargetParams> {
+    let original = Ipv4Packet::new(embedded)?;
+    let header_len = original.get_header_length() as usize * 4;
+    if header_len < IPV4_PACKET_MIN_SIZE
+        || embedded.len() < header_len + EchoRequestPacket::minimum_packet_size()
+    {
+        tracing::trace!("icmp error from {router} has a truncated embedded packet");
+        return None;
+    }
+    if original.get_next_level_protocol() != IpNextHeaderProtocols::Icmp
+        || !ec.is_local_address(&original.get_source())
+    {
+        tracing::trace!("icmp error from {router} isn't for one of our probes");
+        return None;
+    }
+    let request = EchoRequestPacket::new(&embedded[header_len..])?;
+    // echo requests may be of any type given --icmp-type; the identifier check below still rules
+    // out other programs' requests
+    let probe = match request.get_icmp_type() {
+        IcmpTypes::Timestamp => ProbeType::Timestamp,
+        _ => ProbeType::Echo,
+    };
+    if !tag.owns(request.get_identifier()) {
+        tracing::trace!("icmp error from {router} isn't for one of our requests");
+        return None;
+    }
+    Some(TargetParams {
+        addr: original.get_destination(),
+        seq: request.get_sequence_number(),
+        probe,
+    })
+}
+
+/// Recover the target of the request embedded in a Time Exceeded message sent by `router`.
+fn parse_time_exceeded(
+    router: Ipv4Addr,
+    icmp_buf: &[u8],
+    ec: &EthernetConf,
+    tag: &RequestTag,
+) -> Option<(TargetParams, IcmpOutput)> {
+    let time_exceeded = TimeExceededPacket::new(icmp_buf)?;
+    let tparams = parse_embedded_request(router, time_exceeded.payload(), ec, tag)?;
+    let seq = tparams.seq;
+    Some((
+        tparams,
+        IcmpOutput {
+            addr: router,
+            seq,
+            ttl_expired: true,
+            frag_needed: false,
+            next_hop_mtu: None,
+            route: None,
+            clock_offset_ms: None,
+            reply_tos: None,
+            reply_ttl: None,
+            hops: None,
+            gateway: None,
+            payload_len: None,
+            padding_mismatch: None,
+            corruption: None,
+            unexpected: None,
+            received_at: None,
+        },
+    ))
+}
+
+/// Recover the target of the request embedded in a Destination Unreachable message sent by
+/// `router` because the request needed fragmenting but had Don't Fragment set, along with the MTU
+/// of the router's next hop, which RFC 1191 places in the low half of the unused word.
+fn parse_frag_needed(
+    router: Ipv4Addr,
+    icmp_buf: &[u8],
+    ec: &EthernetConf,
+    tag: &RequestTag,
+) -> Option<(TargetParams, IcmpOutput)> {
+    let mtu = u16::from_be_bytes(icmp_buf.get(6..8)?.try_into().ok()?);
+    let tparams = parse_embedded_request(router, icmp_buf.get(8..)?, ec, tag)?;
+    let seq = tparams.seq;
+    Some((
+        tparams,
+        IcmpOutput {
+            addr: router,
+            seq,
+            ttl_expired: false,
+            frag_needed: true,
+            // routers predating RFC 1191 leave it zero
+            next_hop_mtu: (mtu != 0).then_some(mtu),
+            route: None,
+            clock_offset_ms: None,
+            reply_tos: None,
+            reply_ttl: None,
+            hops: None,
+            gateway: None,
+            payload_len: None,
+            padding_mismatch: None,
+            corruption: None,
+            unexpected: None,
+            received_at: None,
+        },
+    ))
+}
+
+/// The byte at `offset` of an echo request's payload following the nonce. A varying pattern
+/// rather than zeros makes altered replies easier to spot.
+fn padding_byte(offset: usize) -> u8 {
+    offset as u8
+}
+
+/// The kind of probe answered by ICMP messages of the given type, if they're replies, along with
+/// the smallest reply of that kind that can be matched to one of our requests: its header plus the
+/// nonce or timestamps it carries back.
+fn reply_kind(icmp_type: IcmpType) -> Option<(ProbeType, usize)> {
+    match icmp_type {
+        IcmpTypes::EchoReply => Some((
+            ProbeType::Echo,
+            ICMP_REPLY_PACKET_SIZE + ICMP_ECHO_NONCE_SIZE,
+        )),
+        IcmpTypes::TimestampReply => Some((
+            ProbeType::Timestamp,
+            ICMP_REPLY_PACKET_SIZE + ICMP_TIMESTAMP_DATA_SIZE,
+        )),
+        _ => None,
+    }
+}
+
+/// Describe an ICMP message type and code, eg `destination unreachable (type 3, code 1)`.
+fn describe_icmp(icmp_type: IcmpType, icmp_code: IcmpCode) -> String {
+    let name = match icmp_type {
+        IcmpTypes::EchoReply => "echo reply",
+        IcmpTypes::DestinationUnreachable => "destination unreachable",
+        IcmpTypes::SourceQuench => "source quench",
+        IcmpTypes::RedirectMessage => "redirect",
+        IcmpTypes::EchoRequest => "echo request",
+        IcmpTypes::TimeExceeded => "time exceeded",
+        IcmpTypes::ParameterProblem => "parameter problem",
+        IcmpTypes::Timestamp => "timestamp",
+        IcmpTypes::TimestampReply => "timestamp reply",
+        _ => "unknown",
+    };
+    format!("{name} (type {}, code {})", icmp_type.0, icmp_code.0)
+}
+
+/// Recover the target of the request embedded in an ICMP error message of a type or code we
+/// don't otherwise handle, eg Destination Unreachable, so that it can be counted against the
+/// target.
+fn parse_unexpected_error(
+    router: Ipv4Addr,
+    icmp_type: IcmpType,
+    icmp_code: IcmpCode,
+    icmp_buf: &[u8],
+    ec: &EthernetConf,
+    tag: &RequestTag,
+) -> Option<(TargetParams, IcmpOutput)> {
+    let tparams = parse_embedded_request(router, icmp_buf.get(8..)?, ec, tag)?;
+    let seq = tparams.seq;
+    Some((
+        tparams,
+        IcmpOutput {
+            addr: router,
+            seq,
+            ttl_expired: false,
+            frag_needed: false,
+            next_hop_mtu: None,
+            route: None,
+            clock_offset_ms: None,
+            reply_tos: None,
+            reply_ttl: None,
+            hops: None,
+            gateway: None,
+            payload_len: None,
+            padding_mismatch: None,
+            corruption: None,
+            unexpected: Some(describe_icmp(icmp_type, icmp_code)),
+            received_at: None,
+        },
+    ))
+}
+
+/// Recover the target of the request embedded in a Redirect message sent by `router`, along with
+/// the gateway the router would have us use for it instead.
+fn parse_redirect(
+    router: Ipv4Addr,
+    icmp_buf: &[u8],
+    ec: &EthernetConf,
+    tag: &RequestTag,
+) -> Option<(TargetParams, IcmpOutput)> {
+    // the gateway address takes the place of the unused word in other ICMP error messages
+    let gateway: [u8; 4] = icmp_buf.get(4..8)?.try_into().ok()?;
+    let tparams = parse_embedded_request(router, icmp_buf.get(8..)?, ec, tag)?;
+    let seq = tparams.seq;
+    Some((
+        tparams,
+        IcmpOutput {
+            addr: router,
+            seq,
+            ttl_expired: false,
+            frag_needed: false,
+            next_hop_mtu: None,
+            route: None,
+            clock_offset_ms: None,
+            reply_tos: None,
+            reply_ttl: None,
+            hops: None,
+            gateway: Some(Ipv4Addr::from(gateway)),
+            payload_len: None,
+            padding_mismatch: None,
+            corruption: None,
+            unexpected: None,
+            received_at: None,
+        },
+    ))
 }
 
 #[async_trait]
 impl Probe for IcmpProbe {
     type Output = IcmpOutput;
 
-    async fn send(&mut self, socket: AsyncSocket, tparams: &TargetParams) -> Result<()> {
-        self.update_icmp_request_packet(&tparams.addr, tparams.seq)
+    async fn send(
+        &mut self,
+        socket: &dyn ProbeSocket,
+        tparams: &TargetParams,
+        options: &SendOptions,
+    ) -> Result<()> {
+        let template = (self.data_size(options), options.dont_fragment);
+        tracing::Span::current().record("identifier", self.identifier);
+        self.update_icmp_request_packet(&tparams.addr, tparams.seq, options.dscp, template)
             .await;
-        match socket.send(self.buf.lock().await.as_slice()).await {
-            Err(e) => {
-                panic!("unhandled socket send error: {}", e);
-            }
-            Ok(length) => {
-                log::trace!("sent {} bytes for request {}", length, tparams);
-            }
-        }
+        let length = socket.send(&self.templates[&template]).await?;
+        tracing::trace!("sent {} bytes for request {}", length, tparams);
+        Ok(())
+    }
+
+    fn reconfigure(&mut self, ec: &EthernetConf) -> Result<()> {
+        *self = Self::new(Arc::new(ec.clone()), &self.options, self.identifier)?;
         Ok(())
     }
 
     /// Check that the given buffer is:
+    /// * addressed to the source address of our requests
     /// * the right kind of IP packet (ICMP)
-    /// * the right kind of ICMP packet (Echo Reply)
-    /// If so, return the detected target params and probe output.
-    fn validate_response(buf: &[u8]) -> Option<(TargetParams, <IcmpProbe as Probe>::Output)> {
+    /// * the right kind of ICMP packet (Echo or Timestamp Reply, or Time Exceeded or Redirect for
+    ///   one of our requests)
+    /// * for Echo Replies, carrying our nonce
+    /// If so, return the detected target params and probe output. Replies too short to carry our
+    /// identifier and nonce or timestamps are returned as `Response::Short` instead.
+    fn validate_response(
+        buf: &[u8],
+        ec: &EthernetConf,
+        tag: &RequestTag,
+    ) -> Option<Response<<IcmpProbe as Probe>::Output>> {
         // check that it's an ICMP packet
-        let ipv4_packet = Ipv4Packet::new(&buf)
-            .expect("packet length already verified to be at least ICMP_REPLY_PACKET_SIZE");
+        let ipv4_packet = match Ipv4Packet::new(buf) {
+            Some(packet) => packet,
+            None => {
+                tracing::trace!("{} byte packet is too short for an ipv4 header", buf.len());
+                return None;
+            }
+        };
         let source = &ipv4_packet.get_source();
+        let destination = ipv4_packet.get_destination();
+        if !ec.is_local_address(&destination) {
+            tracing::trace!("packet addressed to {destination} rather than one of our addresses");
+            return None;
+        }
+        // the receive buffer may have been too small for the whole packet
+        if buf.len() < usize::from(ipv4_packet.get_total_length()) {
+            tracing::trace!("packet from {source} was truncated on receipt");
+            return None;
+        }
         let protocol = ipv4_packet.get_next_level_protocol();
         match protocol {
             IpNextHeaderProtocols::Icmp => (),
             _ => {
-                log::trace!("unexpected ip next level protocol number: {}", protocol);
+                tracing::trace!("unexpected ip next level protocol number: {}", protocol);
+                return None;
+            }
+        }
+        // a reply too short to be matched to a probe, eg because a middlebox truncated it, is still
+        // reported so that it can be told apart from loss; one long enough to carry an identifier
+        // must carry one of ours
+        let icmp_buf = ipv4_packet.payload();
+        if let Some((probe, min_len)) = icmp_buf.first().and_then(|t| reply_kind(IcmpType(*t))) {
+            if icmp_buf.len() < min_len {
+                let identifier = icmp_buf
+                    .get(4..6)
+                    .map(|id| u16::from_be_bytes([id[0], id[1]]));
+                if identifier.is_none_or(|id| tag.owns(id)) {
+                    return Some(Response::Short {
+                        addr: *source,
+                        probe,
+                        seq: icmp_buf
+                            .get(6..8)
+                            .map(|seq| u16::from_be_bytes([seq[0], seq[1]])),
+                        len: icmp_buf.len(),
+                    });
+                }
+                tracing::trace!("short reply from {source} isn't for one of our requests");
                 return None;
             }
         }
         // check that it's the right ICMP packet type
-        {
-            let icmp_packet = IcmpPacket::new(ipv4_packet.payload())
-                .expect("packet length already verified to be at least ICMP_REPLY_PACKET_SIZE");
+        let probe = {
+            let icmp_packet = match IcmpPacket::new(icmp_buf) {
+                Some(packet) => packet,
+                None => {
+                    tracing::trace!("packet from {source} is too short for an icmp header");
+                    return None;
+                }
+            };
             match (icmp_packet.get_icmp_type(), icmp_packet.get_icmp_code()) {
-                (IcmpTypes::EchoReply, IcmpCode(0)) => (),
+                // some stacks copy a non-zero code from the request, and the nonce identifies
+                // the reply regardless
+                (IcmpTypes::EchoReply, _) => ProbeType::Echo,
+                (IcmpTypes::TimestampReply, IcmpCode(0)) => ProbeType::Timestamp,
+                (IcmpTypes::TimeExceeded, IcmpCode(0)) => {
+                    return parse_time_exceeded(*source, icmp_buf, ec, tag)
+                        .map(|(tparams, output)| Response::Matched(tparams, output));
+                }
+                (IcmpTypes::DestinationUnreachable, IcmpCode(4)) => {
+                    return parse_frag_needed(*source, icmp_buf, ec, tag)
+                        .map(|(tparams, output)| Response::Matched(tparams, output));
+                }
+                // redirects for the network, host, or either combined with the ToS
+                (IcmpTypes::RedirectMessage, IcmpCode(0..=3)) => {
+                    return parse_redirect(*source, icmp_buf, ec, tag)
+                        .map(|(tparams, output)| Response::Matched(tparams, output));
+                }
                 (t, c) => {
-                    log::trace!("unexpected icmp (type, code): ({:?}, {:?})", t, c);
-                    return None;
+                    tracing::trace!("unexpected icmp (type, code): ({:?}, {:?})", t, c);
+                    // error messages quote the request they're about, so they can be counted
+                    // against its target
+                    return match t {
+                        IcmpTypes::DestinationUnreachable
+                        | IcmpTypes::SourceQuench
+                        | IcmpTypes::RedirectMessage
+                        | IcmpTypes::TimeExceeded
+                        | IcmpTypes::ParameterProblem => {
+                            parse_unexpected_error(*source, t, c, icmp_buf, ec, tag)
+                                .map(|(tparams, output)| Response::Matched(tparams, output))
+                        }
+                        _ => None,
+                    };
                 }
             }
-        }
-        log::trace!("ipv4 header len: {}", ipv4_packet.get_header_length());
-        log::trace!("ipv4 total len: {}", ipv4_packet.get_total_length());
-        let ipv4_header_len =
-            ipv4_packet.get_total_length() as usize - ipv4_packet.payload().len() as usize;
+        };
+        tracing::trace!("ipv4 header len: {}", ipv4_packet.get_header_length());
+        tracing::trace!("ipv4 total len: {}", ipv4_packet.get_total_length());
+        let ipv4_header_len = ipv4_packet.get_total_length() as usize - ipv4_packet.payload().len();
 
-        log::trace!("ipv4 header len: {}", ipv4_header_len);
+        tracing::trace!("ipv4 header len: {}", ipv4_header_len);
+        let route = buf
+            .get(IPV4_PACKET_MIN_SIZE..ipv4_header_len)
+            .and_then(parse_record_route);
         let echo_reply_buf = &buf[ipv4_header_len..];
-        log::trace!("echo reply buf len: {}", echo_reply_buf.len());
+        tracing::trace!("echo reply buf len: {}", echo_reply_buf.len());
         let reply_packet = EchoReplyPacket::new(echo_reply_buf)
-            .expect("packet length already verified to be at least ICMP_REPLY_PACKET_SIZE");
+            .expect("reply length already verified to be at least ICMP_REPLY_PACKET_SIZE");
 
+        // the raw socket also sees replies to other programs' pings
+        if !tag.owns(reply_packet.get_identifier()) {
+            tracing::trace!("reply from {source} isn't for one of our requests");
+            return None;
+        }
+        // echo replies carry our payload back, so one without our nonce was forged or is a
+        // stale reply from an earlier run
+        if probe == ProbeType::Echo
+            && reply_packet.payload().get(..ICMP_ECHO_NONCE_SIZE) != Some(&tag.nonce.to_be_bytes())
+        {
+            tracing::trace!("reply from {source} doesn't carry our nonce");
+            return None;
+        }
         let seq = reply_packet.get_sequence_number();
+        // bounded by the IPv4 total length, unlike the reply buffer, which may carry link padding
+        let payload_len = (probe == ProbeType::Echo).then(|| {
+            ipv4_packet
+                .payload()
+                .len()
+                .saturating_sub(EchoReplyPacket::minimum_packet_size())
+        });
+        let padding_mismatch = match payload_len {
+            Some(len) => reply_packet.payload()[..len.min(reply_packet.payload().len())]
+                .iter()
+                .enumerate()
+                .skip(ICMP_ECHO_NONCE_SIZE)
+                .find(|(offset, byte)| **byte != padding_byte(*offset))
+                .map(|(offset, _)| offset),
+            None => None,
+        };
+        let clock_offset_ms = if probe == ProbeType::Timestamp {
+            clock_offset_ms(reply_packet.payload(), millis_since_midnight())
+        } else {
+            None
+        };
 
-        Some((
+        Some(Response::Matched(
             TargetParams {
-                addr: source.clone(),
+                addr: *source,
                 seq,
+                probe,
             },
             IcmpOutput {
-                addr: source.clone(),
+                addr: *source,
                 seq,
+                ttl_expired: false,
+                frag_needed: false,
+                next_hop_mtu: None,
+                route,
+                clock_offset_ms,
+                reply_tos: Some(ipv4_packet.get_dscp() << 2 | ipv4_packet.get_ecn()),
+                reply_ttl: Some(ipv4_packet.get_ttl()),
+                hops: Some(estimate_hops(ipv4_packet.get_ttl())),
+                gateway: None,
+                payload_len,
+                padding_mismatch,
+                corruption: None,
+                unexpected: None,
+                received_at: None,
             },
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::ethernet::LinkType;
+    use crate::prober::{ProbeOutcome, ProbeRequest, Prober};
+
+    use super::*;
+
+    const SOURCE: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 100);
+    const TARGET: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+    const ROUTER: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 1);
+
+    fn ethernet_conf() -> EthernetConf {
+        EthernetConf::fabricated(LinkType::RawIp, &[(SOURCE, 24)])
+    }
+
+    /// The tag of a `Prober` with `slots` probe slots.
+    fn tag(slots: usize) -> RequestTag {
+        let (mut prober, _, _) = Prober::<IcmpProbe>::new().unwrap();
+        prober.set_probe_slots(slots);
+        prober.tag()
+    }
+
+    /// The echo request `IcmpProbe` sends to `TARGET` with sequence number `seq`, with its
+    /// identifier replaced by `identifier`.
+    async fn request(tag: RequestTag, identifier: u16, seq: u16) -> Vec<u8> {
+        let options = IcmpOptions {
+            record_route: false,
+            timestamp: false,
+            echo_type: IcmpTypes::EchoRequest,
+            code: IcmpCode(0),
+            tag,
+        };
+        let mut probe = IcmpProbe::new(Arc::new(ethernet_conf()), &options, identifier).unwrap();
+        let template = (ICMP_ECHO_NONCE_SIZE, false);
+        probe
+            .update_icmp_request_packet(&TARGET, seq, 0, template)
+            .await;
+        probe.templates[&template].clone()
+    }
+
+    /// The reply `TARGET` would send to `request`.
+    fn echo_reply(request: &[u8]) -> Vec<u8> {
+        let mut reply = request.to_vec();
+        let mut ipv4_packet = MutableIpv4Packet::new(&mut reply).unwrap();
+        ipv4_packet.set_source(TARGET);
+        ipv4_packet.set_destination(SOURCE);
+        ipv4_packet.set_ttl(64);
+        let mut icmp_packet = MutableEchoRequestPacket::new(ipv4_packet.payload_mut()).unwrap();
+        icmp_packet.set_icmp_type(IcmpTypes::EchoReply);
+        reply
+    }
+
+    /// The Time Exceeded message `ROUTER` would send about `request`, quoting its IPv4 header and
+    /// the first 8 bytes of its ICMP message.
+    fn time_exceeded(request: &[u8]) -> Vec<u8> {
+        let quoted = &request[..IPV4_PACKET_MIN_SIZE + 8];
+        let mut message = vec![0u8; IPV4_PACKET_MIN_SIZE + 8 + quoted.len()];
+        let len = message.len() as u16;
+        let mut ipv4_packet = MutableIpv4Packet::new(&mut message).unwrap();
+        ipv4_packet.set_version(4);
+        ipv4_packet.set_header_length(5);
+        ipv4_packet.set_total_length(len);
+        ipv4_packet.set_ttl(250);
+        ipv4_packet.set_next_level_protocol(IpNextHeaderProtocols::Icmp);
+        ipv4_packet.set_source(ROUTER);
+        ipv4_packet.set_destination(SOURCE);
+        let icmp_buf = ipv4_packet.payload_mut();
+        icmp_buf[0] = IcmpTypes::TimeExceeded.0;
+        icmp_buf[8..].copy_from_slice(quoted);
+        message
+    }
+
+    fn matched(buf: &[u8], tag: &RequestTag) -> Option<(TargetParams, IcmpOutput)> {
+        match IcmpProbe::validate_response(buf, &ethernet_conf(), tag)? {
+            Response::Matched(tparams, output) => Some((tparams, output)),
+            Response::Short { .. } => panic!("reply unexpectedly short"),
+        }
+    }
+
+    #[tokio::test]
+    async fn replies_within_identifier_range_are_accepted() {
+        let tag = tag(4);
+        for slot in 0..4 {
+            let request = request(tag, tag.identifier(slot), 9).await;
+            let (tparams, output) = matched(&echo_reply(&request), &tag).unwrap();
+            assert_eq!(tparams.addr, TARGET);
+            assert_eq!(tparams.seq, 9);
+            assert_eq!(tparams.probe, ProbeType::Echo);
+            assert_eq!(output.status(), ProbeStatus::Replied);
+        }
+    }
+
+    #[tokio::test]
+    async fn replies_with_foreign_identifiers_are_rejected() {
+        let tag = tag(4);
+        let foreign = [
+            tag.identifier(0).wrapping_sub(1),
+            tag.identifier(3).wrapping_add(1),
+            tag.identifier(0).wrapping_add(0x8000),
+        ];
+        for identifier in foreign {
+            assert!(!tag.owns(identifier));
+            let request = request(tag, identifier, 9).await;
+            assert!(matched(&echo_reply(&request), &tag).is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn replies_without_our_nonce_are_rejected() {
+        let ours = tag(1);
+        let request = request(ours, ours.identifier(0), 9).await;
+        let mut other = ours;
+        other.nonce = !ours.nonce;
+        assert!(matched(&echo_reply(&request), &other).is_none());
+    }
+
+    #[tokio::test]
+    async fn errors_about_foreign_requests_are_rejected() {
+        let tag = tag(2);
+        let ours = request(tag, tag.identifier(1), 3).await;
+        let (tparams, output) = matched(&time_exceeded(&ours), &tag).unwrap();
+        assert_eq!(tparams.addr, TARGET);
+        assert_eq!(tparams.seq, 3);
+        assert_eq!(output.status(), ProbeStatus::TtlExpired);
+        assert_eq!(output.source(), ROUTER);
+
+        let foreign = request(tag, tag.identifier(1).wrapping_add(1), 3).await;
+        assert!(matched(&time_exceeded(&foreign), &tag).is_none());
+    }
+
+    const VETH: &str = "pingers-veth0";
+    const VETH_PEER: &str = "pingers-veth1";
+    const VETH_PEER_MAC: &str = "02:00:00:00:00:02";
+    const NETNS: &str = "pingers-test";
+
+    fn ip(args: &str) {
+        let status = std::process::Command::new("ip")
+            .args(args.split_whitespace())
+            .status()
+            .expect("failed to run ip");
+        assert!(status.success(), "ip {args} failed");
+    }
+
+    /// A veth pair from `SOURCE` to `TARGET`, whose far end is in a network namespace of its own
+    /// where the kernel answers our requests. Both are removed on drop.
+    struct Veth;
+
+    impl Veth {
+        fn create() -> Self {
+            // left behind by an earlier run that was killed
+            drop(Veth);
+            // dropped, undoing what was set up, if a step fails
+            let veth = Veth;
+            ip(&format!("netns add {NETNS}"));
+            ip(&format!(
+                "link add {VETH} type veth peer name {VETH_PEER} address {VETH_PEER_MAC} \
+                 netns {NETNS}"
+            ));
+            ip(&format!("addr add {SOURCE}/24 dev {VETH}"));
+            ip(&format!("link set {VETH} up"));
+            ip(&format!("-n {NETNS} addr add {TARGET}/24 dev {VETH_PEER}"));
+            ip(&format!("-n {NETNS} link set {VETH_PEER} up"));
+            // the interface's next hop is taken from its neighbors, which are otherwise empty
+            // until something resolves the far end
+            ip(&format!(
+                "neigh replace {TARGET} lladdr {VETH_PEER_MAC} dev {VETH} nud permanent"
+            ));
+            veth
+        }
+    }
+
+    impl Drop for Veth {
+        fn drop(&mut self) {
+            // deleting the namespace deletes the far end, and with it ours
+            let _ = std::process::Command::new("ip")
+                .args(["netns", "del", NETNS])
+                .stderr(std::process::Stdio::null())
+                .status();
+        }
+    }
+
+    /// Runs as root, or with CAP_NET_ADMIN and CAP_NET_RAW, with eg
+    /// `sudo -E cargo test -- --ignored veth`.
+    #[tokio::test]
+    #[ignore = "needs CAP_NET_ADMIN to create a network namespace and veth pair"]
+    async fn echo_request_is_answered_across_veth_pair() {
+        let _veth = Veth::create();
+        let ethernet_conf = EthernetConf::new(VETH.to_string()).await.unwrap();
+        let (prober, requests, mut reports) = Prober::<IcmpProbe>::new().unwrap();
+        let options = IcmpOptions {
+            record_route: false,
+            timestamp: false,
+            echo_type: IcmpTypes::EchoRequest,
+            code: IcmpCode(0),
+            tag: prober.tag(),
+        };
+        let probes = IcmpProbe::many(1, &ethernet_conf, &options).unwrap();
+        let run = tokio::spawn(prober.run_probes(probes, ethernet_conf, Duration::from_secs(2)));
+
+        requests
+            .send(ProbeRequest {
+                tparams: TargetParams {
+                    addr: TARGET,
+                    seq: 7,
+                    probe: ProbeType::Echo,
+                },
+                index: 7,
+                interval: None,
+                notify: None,
+                broadcast: false,
+                warmup: false,
+                instance: None,
+                send_options: SendOptions {
+                    payload_size: 32,
+                    ..SendOptions::default()
+                },
+                scheduled: None,
+                overran: false,
+                slot: None,
+                span: tracing::Span::none(),
+            })
+            .await
+            .unwrap();
+        let report = reports.recv().await.unwrap();
+        let output = match report.outcome {
+            ProbeOutcome::ReceivedOutput(output, _) => output,
+            outcome => panic!("expected a reply, got {:?}", outcome.status()),
+        };
+        assert_eq!(output.status(), ProbeStatus::Replied);
+        assert_eq!(output.addr, TARGET);
+        assert_eq!(output.seq, 7);
+        assert!(output.corruption().is_none());
+        // the reply crossed no routers
+        assert_eq!(output.reply_ttl, Some(64));
+
+        drop(requests);
+        run.await.unwrap().unwrap();
+    }
+}
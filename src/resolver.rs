@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use socket2::SockAddr;
+use tokio::sync::oneshot;
+
+/// Resolves the names of addresses by reverse DNS in the background, remembering the results for
+/// the rest of the run.
+#[derive(Clone, Debug)]
+pub struct Resolver {
+    /// Names by address. An address maps to `None` while its lookup is pending or if it failed.
+    names: Arc<Mutex<HashMap<Ipv4Addr, Option<String>>>>,
+
+    /// How long to wait for a single lookup before giving up on it.
+    timeout: Duration,
+}
+
+impl Resolver {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            names: Arc::new(Mutex::new(HashMap::new())),
+            timeout,
+        }
+    }
+
+    /// Return the name of `addr` if it has already been resolved. Otherwise start resolving it in
+    /// the background, unless that has already been tried, and return `None` without waiting.
+    pub fn lookup(&self, addr: Ipv4Addr) -> Option<String> {
+        let mut names = self.names.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(name) = names.get(&addr) {
+            return name.clone();
+        }
+        names.insert(addr, None);
+
+        let names = self.names.clone();
+        let timeout = self.timeout;
+        tokio::spawn(async move {
+            let (sender, receiver) = oneshot::channel();
+            // getnameinfo blocks and can't be cancelled, so run it on a detached thread that
+            // won't hold up exit if the resolver never answers
+            std::thread::spawn(move || {
+                let _ = sender.send(reverse_lookup(addr));
+            });
+            match tokio::time::timeout(timeout, receiver).await {
+                Ok(Ok(Some(name))) => {
+                    tracing::debug!("resolved {addr} to {name}");
+                    names
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .insert(addr, Some(name));
+                }
+                Ok(_) => tracing::debug!("no name found for {addr}"),
+                Err(_) => tracing::debug!("reverse lookup of {addr} timed out"),
+            }
+        });
+        None
+    }
+}
+
+/// Look up the name of `addr` with the system resolver, blocking until it answers.
+fn reverse_lookup(addr: Ipv4Addr) -> Option<String> {
+    let sockaddr = SockAddr::from(SocketAddrV4::new(addr, 0));
+    let mut host = [0 as libc::c_char; libc::NI_MAXHOST as usize];
+    // safe because the sockaddr and host buffer are valid for the lengths given, and
+    // getnameinfo nul-terminates the host name it writes on success
+    let ret = unsafe {
+        libc::getnameinfo(
+            sockaddr.as_ptr(),
+            sockaddr.len(),
+            host.as_mut_ptr(),
+            host.len() as libc::socklen_t,
+            std::ptr::null_mut(),
+            0,
+            libc::NI_NAMEREQD,
+        )
+    };
+    if ret != 0 {
+        return None;
+    }
+    let name = unsafe { CStr::from_ptr(host.as_ptr()) };
+    Some(name.to_string_lossy().into_owned())
+}
@@ -1,48 +1,578 @@
 use std::mem::MaybeUninit;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::MetadataExt;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
-use socket2::Socket;
+use async_trait::async_trait;
+use pnet::packet::ip::IpNextHeaderProtocols;
+use pnet::packet::ipv4::{Ipv4Packet, MutableIpv4Packet};
+use pnet::util::MacAddr;
+use socket2::{Domain, SockAddr, Socket, Type};
 use tokio::io::unix::AsyncFd;
 
 use crate::error::Result;
+use crate::ethernet::LinkType;
+use crate::pcap::PcapWriter;
+
+/// Length of the IPv4 header put in front of messages received on ping sockets.
+const IPV4_HEADER_LEN: usize = Ipv4Packet::minimum_packet_size();
+
+/// How sockets are driven.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, clap::ValueEnum)]
+pub enum IoBackend {
+    /// A syscall per packet on sockets polled with epoll.
+    #[default]
+    Epoll,
+    /// Sends and multishot receives submitted through an io_uring. Requires the `uring` build
+    /// feature and Linux 6.0 or later; falls back to epoll otherwise.
+    Uring,
+}
+
+/// Which kind of socket requests are sent on.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, clap::ValueEnum)]
+pub enum SocketBackend {
+    /// Packet sockets, unless they can't be opened here.
+    #[default]
+    Auto,
+    /// AF_PACKET sockets carrying the Ethernet frames we build.
+    Packet,
+    /// Raw IPv4 sockets carrying the IPv4 packets we build, leaving the link-layer header to the
+    /// kernel.
+    InetRaw,
+    /// Unprivileged ICMP datagram ("ping") sockets carrying only the ICMP messages we build,
+    /// leaving the IPv4 and link-layer headers to the kernel. Needs no privileges where the
+    /// user's group is in `net.ipv4.ping_group_range`.
+    Ping,
+}
+
+impl SocketBackend {
+    /// Resolve `Auto` to packet sockets, or to raw IPv4 sockets if opening a packet socket fails
+    /// with EPERM or EAFNOSUPPORT, as in containers and on hosts that block them, or to ping
+    /// sockets if raw IPv4 sockets can't be opened either. `Ping` is resolved the same way as
+    /// `Auto` if ping sockets can't be opened.
+    pub fn resolve(self) -> Self {
+        match self {
+            SocketBackend::Auto => (),
+            SocketBackend::Ping => match ping_socket() {
+                Err(e) if is_unavailable(&e) => {
+                    tracing::warn!("ping sockets unavailable, falling back to raw sockets: {e}");
+                    return SocketBackend::Auto.resolve();
+                }
+                _ => return self,
+            },
+            _ => return self,
+        }
+        match Socket::new(Domain::PACKET, Type::DGRAM, None) {
+            Err(e) if is_unavailable(&e) => {
+                tracing::warn!("packet sockets unavailable, falling back to raw IPv4 sockets: {e}");
+            }
+            // any other failure is reported when the send socket is created
+            _ => return SocketBackend::Packet,
+        }
+        match Socket::new(Domain::IPV4, Type::RAW, Some(libc::IPPROTO_RAW.into())) {
+            Err(e) if e.raw_os_error() == Some(libc::EPERM) => {
+                tracing::warn!("raw IPv4 sockets unavailable, falling back to ping sockets: {e}");
+                SocketBackend::Ping
+            }
+            _ => SocketBackend::InetRaw,
+        }
+    }
+}
+
+/// Whether opening a socket failed because sockets of its kind aren't permitted or supported
+/// here, rather than for some passing reason.
+fn is_unavailable(e: &std::io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::EPERM | libc::EACCES | libc::EAFNOSUPPORT | libc::EPROTONOSUPPORT)
+    )
+}
+
+/// Open an ICMP datagram ("ping") socket. The kernel refuses with EACCES unless the user's group
+/// is in `net.ipv4.ping_group_range`.
+pub fn ping_socket() -> std::io::Result<Socket> {
+    Socket::new(Domain::IPV4, Type::DGRAM, Some(libc::IPPROTO_ICMP.into()))
+}
+
+/// Whether falling back from io_uring has already been logged, since every socket would
+/// otherwise log the same reason.
+static URING_FALLBACK_LOGGED: AtomicBool = AtomicBool::new(false);
+
+/// Drive `socket` with `backend`, falling back to epoll if the backend is unavailable.
+pub fn with_backend(socket: AsyncSocket, backend: IoBackend) -> Arc<dyn ProbeSocket> {
+    match backend {
+        IoBackend::Epoll => Arc::new(socket),
+        // ping sockets need the control messages that come with each send and receive
+        IoBackend::Uring if socket.is_ping() => {
+            if !URING_FALLBACK_LOGGED.swap(true, Ordering::Relaxed) {
+                tracing::warn!(
+                    "io_uring backend doesn't drive ping sockets, falling back to epoll"
+                );
+            }
+            Arc::new(socket)
+        }
+        IoBackend::Uring => match uring_socket(socket.clone()) {
+            Ok(socket) => socket,
+            Err(reason) => {
+                if !URING_FALLBACK_LOGGED.swap(true, Ordering::Relaxed) {
+                    tracing::warn!("io_uring backend unavailable, falling back to epoll: {reason}");
+                }
+                Arc::new(socket)
+            }
+        },
+    }
+}
+
+#[cfg(feature = "uring")]
+fn uring_socket(socket: AsyncSocket) -> std::result::Result<Arc<dyn ProbeSocket>, String> {
+    match crate::uring::UringSocket::new(socket) {
+        Ok(socket) => Ok(Arc::new(socket)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[cfg(not(feature = "uring"))]
+fn uring_socket(_socket: AsyncSocket) -> std::result::Result<Arc<dyn ProbeSocket>, String> {
+    Err("pingers was built without the uring feature".to_string())
+}
+
+/// A socket on which probes send requests and from which replies are received. Abstracts over
+/// `AsyncSocket` so that `Probe`s and the `Prober` don't depend on a real network interface.
+#[async_trait]
+pub trait ProbeSocket: std::fmt::Debug + Send + Sync {
+    /// Populate given MaybeUninit buffer asynchronously.
+    async fn recv(&self, buf: &mut [MaybeUninit<u8>]) -> std::io::Result<usize>;
+
+    /// Populate given MaybeUninit buffer asynchronously, also returning when the kernel received
+    /// the packet, if the socket was asked to timestamp packets and the backend passes the
+    /// timestamp on.
+    async fn recv_timestamped(
+        &self,
+        buf: &mut [MaybeUninit<u8>],
+    ) -> std::io::Result<(usize, Option<SystemTime>)> {
+        Ok((self.recv(buf).await?, None))
+    }
+
+    /// Send referenced buffer asynchronously.
+    async fn send(&self, buf: &[u8]) -> std::io::Result<usize>;
+}
 
 /// Clonable async socket wrapper with convenience methods for performing async send/recv
 /// operations.
 #[derive(Clone, Debug)]
 pub struct AsyncSocket {
     inner: Arc<AsyncFd<Socket>>,
+
+    /// Records every buffer successfully sent, if set.
+    capture: Option<Arc<PcapWriter>>,
+
+    /// Framing of the buffers sent on this socket, which determines how they are sent and
+    /// captured.
+    link_type: LinkType,
+
+    /// Address a ping socket is bound to, which received messages are given as their
+    /// destination. Unset for other sockets.
+    ping_address: Option<Ipv4Addr>,
+
+    /// Whether the kernel timestamps received packets, which are then read with their control
+    /// messages.
+    rx_timestamps: bool,
 }
 
 impl AsyncSocket {
     pub fn new(s: Socket) -> Result<Self> {
         Ok(Self {
             inner: Arc::new(AsyncFd::new(s)?),
+            capture: None,
+            link_type: LinkType::Ethernet,
+            ping_address: None,
+            rx_timestamps: false,
         })
     }
 
-    /// Populate given MaybeUninit buffer asynchronously.
-    pub async fn recv(&self, buf: &mut [MaybeUninit<u8>]) -> std::io::Result<usize> {
+    /// Declare this a ping socket bound to `address`. Buffers sent on it are IPv4 packets, of
+    /// which only the ICMP message is sent, and received messages are given back an IPv4 header,
+    /// so that probes needn't tell ping sockets from others.
+    pub fn set_ping_address(&mut self, address: Ipv4Addr) {
+        self.link_type = LinkType::RawIp;
+        self.ping_address = Some(address);
+    }
+
+    pub fn is_ping(&self) -> bool {
+        self.ping_address.is_some()
+    }
+
+    /// Declare how buffers sent on this socket are framed. Defaults to Ethernet.
+    pub fn set_link_type(&mut self, link_type: LinkType) {
+        self.link_type = link_type;
+    }
+
+    #[cfg(feature = "uring")]
+    pub fn link_type(&self) -> LinkType {
+        self.link_type
+    }
+
+    /// The underlying socket, for backends that drive it other than through epoll.
+    #[cfg(feature = "uring")]
+    pub(crate) fn socket(&self) -> &Socket {
+        self.inner.get_ref()
+    }
+
+    /// Record every buffer sent on this socket (and clones made afterwards) to `capture`.
+    pub fn set_capture(&mut self, capture: Arc<PcapWriter>) {
+        self.capture = Some(capture);
+    }
+
+    /// Have the kernel timestamp each packet as it's received (SO_TIMESTAMPNS), so that
+    /// `recv_timestamped` can tell when a packet arrived rather than only when it was read.
+    pub fn enable_rx_timestamps(&mut self) -> Result<()> {
+        let enable: libc::c_int = 1;
+        // safe because the option value is a c_int whose size is passed along with it
+        let rc = unsafe {
+            libc::setsockopt(
+                self.inner.get_ref().as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_TIMESTAMPNS,
+                (&enable as *const libc::c_int).cast(),
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if rc != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        self.rx_timestamps = true;
+        Ok(())
+    }
+
+    /// Request a receive buffer (SO_RCVBUF) of `size` bytes, returning the effective size. The
+    /// kernel doubles the request to allow for bookkeeping overhead and clamps it to
+    /// `net.core.rmem_max`.
+    pub fn set_recv_buffer_size(&self, size: usize) -> Result<usize> {
+        let socket = self.inner.get_ref();
+        socket.set_recv_buffer_size(size)?;
+        Ok(socket.recv_buffer_size()?)
+    }
+
+    /// Packets the kernel dropped because this socket's receive buffer was full, or `None` for
+    /// sockets whose drops can't be read. Packet socket counters reset when read, so this is
+    /// meant to be called once, at the end of a run.
+    pub fn kernel_drops(&self) -> Result<Option<u64>> {
+        let mut stats = TpacketStats::default();
+        let mut len = std::mem::size_of::<TpacketStats>() as libc::socklen_t;
+        // safe because the kernel writes at most `len` bytes to the struct it's given
+        let rc = unsafe {
+            libc::getsockopt(
+                self.inner.get_ref().as_raw_fd(),
+                libc::SOL_PACKET,
+                PACKET_STATISTICS,
+                (&mut stats as *mut TpacketStats).cast(),
+                &mut len,
+            )
+        };
+        if rc != 0 {
+            let e = std::io::Error::last_os_error();
+            // other socket families don't understand SOL_PACKET options; raw IPv4 sockets
+            // instead report their drops through procfs
+            if matches!(e.raw_os_error(), Some(libc::ENOPROTOOPT | libc::EOPNOTSUPP)) {
+                let fd = self.inner.get_ref().as_raw_fd();
+                let inode = std::fs::metadata(format!("/proc/self/fd/{fd}"))?.ino();
+                return raw_socket_drops(inode);
+            }
+            return Err(e.into());
+        }
+        tracing::debug!(
+            "receive socket saw {} packets, {} dropped",
+            stats.tp_packets,
+            stats.tp_drops
+        );
+        Ok(Some(u64::from(stats.tp_drops)))
+    }
+
+    /// Capture a buffer that was sent on this socket, if capturing.
+    pub(crate) fn record_sent(&self, sent: &[u8]) {
+        if let Some(capture) = &self.capture {
+            let result = match self.link_type {
+                LinkType::Ethernet => capture.write_frame(sent),
+                LinkType::Loopback | LinkType::RawIp => {
+                    capture.write_ipv4_packet(sent, MacAddr::zero(), MacAddr::zero())
+                }
+            };
+            if let Err(e) = result {
+                tracing::warn!("failed to capture sent frame: {e}");
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl ProbeSocket for AsyncSocket {
+    async fn recv(&self, buf: &mut [MaybeUninit<u8>]) -> std::io::Result<usize> {
+        self.recv_timestamped(buf).await.map(|(len, _)| len)
+    }
+
+    async fn recv_timestamped(
+        &self,
+        buf: &mut [MaybeUninit<u8>],
+    ) -> std::io::Result<(usize, Option<SystemTime>)> {
         loop {
-            log::trace!("waiting for receiver to be readable");
+            tracing::trace!("waiting for receiver to be readable");
             let mut guard = self.inner.readable().await?;
-            log::trace!("receiver is readable");
+            tracing::trace!("receiver is readable");
 
-            match guard.try_io(|receiver| receiver.get_ref().recv(buf)) {
+            match guard.try_io(|receiver| match (self.ping_address, self.rx_timestamps) {
+                (Some(address), _) => recv_ping(receiver.get_ref(), address, buf),
+                (None, true) => recv_stamped(receiver.get_ref(), buf),
+                (None, false) => receiver.get_ref().recv(buf).map(|len| (len, None)),
+            }) {
                 Ok(result) => return result,
                 Err(_would_block) => continue,
             }
         }
     }
 
-    /// Send referenced buffer asynchronously.
-    pub async fn send(&self, buf: &[u8]) -> std::io::Result<usize> {
+    async fn send(&self, buf: &[u8]) -> std::io::Result<usize> {
         loop {
             let mut guard = self.inner.writable().await?;
 
-            match guard.try_io(|sender| sender.get_ref().send(buf)) {
+            match guard.try_io(|sender| match (self.ping_address, self.link_type) {
+                (Some(_), _) => send_ping(sender.get_ref(), buf),
+                // raw IPv4 sockets aren't connected, so send each packet to the destination in
+                // its header
+                (None, LinkType::Loopback) => {
+                    sender.get_ref().send_to(buf, &ipv4_destination(buf)?)
+                }
+                (None, _) => sender.get_ref().send(buf),
+            }) {
+                Ok(Ok(length)) => {
+                    self.record_sent(&buf[..length]);
+                    return Ok(length);
+                }
                 Ok(result) => return result,
                 Err(_would_block) => continue,
             }
         }
     }
 }
+
+/// PACKET_STATISTICS socket option and its value from linux/if_packet.h, which libc doesn't
+/// expose.
+const PACKET_STATISTICS: libc::c_int = 6;
+
+#[repr(C)]
+#[derive(Default)]
+struct TpacketStats {
+    tp_packets: u32,
+    tp_drops: u32,
+}
+
+/// Packets dropped by the raw IPv4 socket with `inode`, from the `drops` column of
+/// /proc/net/raw, or `None` if the socket isn't listed.
+fn raw_socket_drops(inode: u64) -> Result<Option<u64>> {
+    let table = std::fs::read_to_string("/proc/net/raw")?;
+    for line in table.lines().skip(1) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.get(9).and_then(|f| f.parse::<u64>().ok()) != Some(inode) {
+            continue;
+        }
+        return Ok(fields.last().and_then(|drops| drops.parse().ok()));
+    }
+    Ok(None)
+}
+
+pub(crate) fn ipv4_destination(packet: &[u8]) -> std::io::Result<SockAddr> {
+    let header = Ipv4Packet::new(packet).ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "truncated IPv4 packet")
+    })?;
+    Ok(SocketAddrV4::new(header.get_destination(), 0).into())
+}
+
+/// Send the ICMP message of the IPv4 `packet` on a ping socket to the packet's destination, with
+/// the packet's TTL and TOS. The kernel builds the IPv4 header itself, and replaces the ICMP
+/// identifier with the port the socket is bound to. Returns the length of the whole packet.
+fn send_ping(socket: &Socket, packet: &[u8]) -> std::io::Result<usize> {
+    let header = Ipv4Packet::new(packet).ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "truncated IPv4 packet")
+    })?;
+    let header_len = usize::from(header.get_header_length()) * 4;
+    let message = packet.get(header_len..).ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "truncated IPv4 packet")
+    })?;
+    // safe because all zeroes is a valid sockaddr_in
+    let mut destination: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+    destination.sin_family = libc::AF_INET as libc::sa_family_t;
+    destination.sin_addr.s_addr = u32::from(header.get_destination()).to_be();
+    let mut iov = libc::iovec {
+        iov_base: message.as_ptr() as *mut libc::c_void,
+        iov_len: message.len(),
+    };
+    let options = [
+        (libc::IP_TTL, libc::c_int::from(header.get_ttl())),
+        (
+            libc::IP_TOS,
+            libc::c_int::from(header.get_dscp() << 2 | header.get_ecn()),
+        ),
+    ];
+    // u64s keep the control messages aligned for their headers
+    let mut control = [0u64; 8];
+    // safe because every pointer in `msg` refers to a live buffer of the length given with it,
+    // and the control messages written fit in `control`, whose size the kernel is told
+    let sent = unsafe {
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_name = (&mut destination as *mut libc::sockaddr_in).cast();
+        msg.msg_namelen = std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen =
+            options.len() * libc::CMSG_SPACE(std::mem::size_of::<libc::c_int>() as u32) as usize;
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        for (option, value) in options {
+            (*cmsg).cmsg_level = libc::IPPROTO_IP;
+            (*cmsg).cmsg_type = option;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(std::mem::size_of::<libc::c_int>() as u32) as _;
+            libc::CMSG_DATA(cmsg)
+                .cast::<libc::c_int>()
+                .write_unaligned(value);
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+        libc::sendmsg(socket.as_raw_fd(), &msg, 0)
+    };
+    if sent < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(header_len + sent as usize)
+}
+
+/// Receive a packet along with the kernel's timestamp of its arrival, if it was given one.
+fn recv_stamped(
+    socket: &Socket,
+    buf: &mut [MaybeUninit<u8>],
+) -> std::io::Result<(usize, Option<SystemTime>)> {
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+    // u64s keep the control messages aligned for their headers
+    let mut control = [0u64; 8];
+    let mut stamp = None;
+    // safe because every pointer in `msg` refers to a live buffer of the length given with it,
+    // and the kernel writes at most that much to each, leaving well-formed control messages
+    let len = unsafe {
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = std::mem::size_of_val(&control) as _;
+        let len = libc::recvmsg(socket.as_raw_fd(), &mut msg, 0);
+        if len < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if ((*cmsg).cmsg_level, (*cmsg).cmsg_type) == (libc::SOL_SOCKET, libc::SCM_TIMESTAMPNS)
+            {
+                stamp = kernel_timestamp(
+                    libc::CMSG_DATA(cmsg)
+                        .cast::<libc::timespec>()
+                        .read_unaligned(),
+                );
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+        len as usize
+    };
+    Ok((len, stamp))
+}
+
+/// The time a kernel timestamp stands for, unless it's unset, as it is for packets that arrived
+/// before timestamping was enabled.
+fn kernel_timestamp(ts: libc::timespec) -> Option<SystemTime> {
+    if ts.tv_sec <= 0 && ts.tv_nsec <= 0 {
+        return None;
+    }
+    Some(SystemTime::UNIX_EPOCH + Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32))
+}
+
+/// Receive an ICMP message on a ping socket bound to `address`, preceded in `buf` by an IPv4
+/// header rebuilt from the message's source and the TTL and TOS it arrived with, which the kernel
+/// strips. A truncated message is given a total length longer than what was received. Also
+/// returns the kernel's timestamp of the message's arrival, if it was given one.
+fn recv_ping(
+    socket: &Socket,
+    address: Ipv4Addr,
+    buf: &mut [MaybeUninit<u8>],
+) -> std::io::Result<(usize, Option<SystemTime>)> {
+    if buf.len() <= IPV4_HEADER_LEN {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "receive buffer too small for an IPv4 header",
+        ));
+    }
+    let (header, message) = buf.split_at_mut(IPV4_HEADER_LEN);
+    // safe because all zeroes is a valid sockaddr_in
+    let mut source: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+    let mut iov = libc::iovec {
+        iov_base: message.as_mut_ptr().cast(),
+        iov_len: message.len(),
+    };
+    // u64s keep the control messages aligned for their headers
+    let mut control = [0u64; 16];
+    let (mut ttl, mut tos, mut stamp) = (0u8, 0u8, None);
+    // safe because every pointer in `msg` refers to a live buffer of the length given with it,
+    // and the kernel writes at most that much to each, leaving well-formed control messages
+    let (len, flags) = unsafe {
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_name = (&mut source as *mut libc::sockaddr_in).cast();
+        msg.msg_namelen = std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = std::mem::size_of_val(&control) as _;
+        let len = libc::recvmsg(socket.as_raw_fd(), &mut msg, 0);
+        if len < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            let data = libc::CMSG_DATA(cmsg);
+            match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+                (libc::IPPROTO_IP, libc::IP_TTL) => {
+                    ttl = data.cast::<libc::c_int>().read_unaligned() as u8;
+                }
+                (libc::IPPROTO_IP, libc::IP_TOS) => tos = *data,
+                (libc::SOL_SOCKET, libc::SCM_TIMESTAMPNS) => {
+                    stamp = kernel_timestamp(data.cast::<libc::timespec>().read_unaligned());
+                }
+                _ => (),
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+        (len as usize, msg.msg_flags)
+    };
+    let total_len = if flags & libc::MSG_TRUNC != 0 {
+        u16::MAX
+    } else {
+        (IPV4_HEADER_LEN + len) as u16
+    };
+    let mut rebuilt = [0u8; IPV4_HEADER_LEN];
+    {
+        let mut packet =
+            MutableIpv4Packet::new(&mut rebuilt).expect("the buffer fits an IPv4 header");
+        packet.set_version(4);
+        packet.set_header_length((IPV4_HEADER_LEN / 4) as u8);
+        packet.set_dscp(tos >> 2);
+        packet.set_ecn(tos & 0b11);
+        packet.set_total_length(total_len);
+        packet.set_ttl(ttl);
+        packet.set_next_level_protocol(IpNextHeaderProtocols::Icmp);
+        packet.set_source(Ipv4Addr::from(u32::from_be(source.sin_addr.s_addr)));
+        packet.set_destination(address);
+    }
+    for (slot, byte) in header.iter_mut().zip(rebuilt) {
+        slot.write(byte);
+    }
+    Ok((IPV4_HEADER_LEN + len, stamp))
+}
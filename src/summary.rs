@@ -0,0 +1,1353 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::net::Ipv4Addr;
+use std::process::ExitCode;
+use std::time::{Duration, Instant, SystemTime};
+
+use crossterm::style::{Color, Stylize};
+use serde::{Deserialize, Serialize};
+
+use crate::histogram::Histogram;
+use crate::link::Outage;
+use crate::prober::{Probe, ProbeOutcome, ProbeOutput, ProbeReport, ProbeStatus};
+use crate::target::ProbeType;
+
+/// Exit code used when no target breached a failure threshold.
+pub const EXIT_SUCCESS: u8 = 0;
+
+/// Exit code used when some, but not all, targets breached a failure threshold.
+pub const EXIT_PARTIAL_FAILURE: u8 = 2;
+
+/// Exit code used when every target breached a failure threshold.
+pub const EXIT_TOTAL_FAILURE: u8 = 3;
+
+/// Number of most recent probes over which the table's rolling loss and RTT history are shown.
+const RECENT_PROBES: usize = 30;
+
+/// Number of probes whose outcomes may be held back waiting for an earlier probe's before jitter
+/// is computed from them; beyond this the missing outcome is skipped over.
+const JITTER_REORDER_WINDOW: usize = 1024;
+
+/// Largest difference between the TTLs of a target's replies that's put down to the route
+/// changing length rather than to the replies coming from different hosts.
+const MULTIPATH_TTL_SPREAD: u8 = 1;
+
+/// Fraction of the rate asked for by a target's interval that its probes must achieve for the
+/// summary not to point out the shortfall.
+const RATE_SHORTFALL: f64 = 0.95;
+
+/// Bars of increasing height used to draw RTT history.
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// What the failure thresholds are evaluated against.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum FailScope {
+    /// Each target on its own.
+    #[default]
+    Target,
+    /// The combined loss of each group. Targets outside any group are evaluated on their own.
+    Group,
+}
+
+/// Thresholds beyond which a target, or a group with `FailScope::Group`, counts as failed.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Thresholds {
+    /// Percentage of probes that may be lost. Without one only total loss is a breach.
+    pub loss: Option<f64>,
+
+    /// Percentage of probes whose RTT may exceed their target's warning threshold.
+    pub rtt_warn: Option<f64>,
+
+    pub scope: FailScope,
+}
+
+/// The threshold a target or group breached.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Breach {
+    Loss,
+    RttWarn,
+}
+
+impl std::fmt::Display for Breach {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Loss => write!(f, "packet loss threshold"),
+            Self::RttWarn => write!(f, "RTT warning threshold"),
+        }
+    }
+}
+
+/// Check counts of probes sent, received and over their RTT warning threshold against
+/// `thresholds`.
+fn breach(sent: u64, received: u64, rtt_exceeded: u64, thresholds: &Thresholds) -> Option<Breach> {
+    let lost = match thresholds.loss {
+        Some(threshold) => percent(sent - received, sent) > threshold,
+        None => received == 0,
+    };
+    if lost {
+        return Some(Breach::Loss);
+    }
+    match thresholds.rtt_warn {
+        Some(threshold) if percent(rtt_exceeded, sent) > threshold => Some(Breach::RttWarn),
+        _ => None,
+    }
+}
+
+/// `count` as a percentage of `total`, which is 100% when nothing was sent at all.
+fn percent(count: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 100.0;
+    }
+    count as f64 * 100.0 / total as f64
+}
+
+/// Statistics accumulated for a single target over the course of a run.
+#[derive(Debug, Default)]
+pub struct TargetSummary {
+    pub sent: u64,
+    pub received: u64,
+
+    /// Probes whose TTL expired in transit, which also count as lost.
+    pub ttl_expired: u64,
+
+    /// Probes whose reply carried back an altered or truncated payload, which also count as lost.
+    corrupted: u64,
+    truncated: u64,
+
+    /// Probes a router reported too big to forward without fragmenting, which also count as lost.
+    too_big: u64,
+
+    /// ICMP Redirects received for the target's probes.
+    pub redirects: u64,
+
+    /// ICMP error messages of unexpected types or codes received for the target's probes, eg
+    /// Destination Unreachable, by their description.
+    unexpected: BTreeMap<String, u64>,
+
+    /// Replies from the target too short to be matched to a probe, eg because a middlebox
+    /// truncated them. Their probes time out and so count as lost.
+    short_replies: u64,
+
+    /// Probes whose RTT exceeded the target's warning threshold, including those that timed out.
+    pub rtt_exceeded: u64,
+
+    /// Replies that came from an address other than the target's, eg through NAT or anycast.
+    pub responded_by_other: u64,
+
+    /// Smallest and largest TTL of the target's replies as they arrived.
+    reply_ttl_min: Option<u8>,
+    reply_ttl_max: Option<u8>,
+    rtt_min: Option<Duration>,
+    rtt_max: Option<Duration>,
+    rtt_last: Option<Duration>,
+    rtt_total: Duration,
+
+    /// Number of RTTs making up `rtt_total`. Every response of a broadcast target contributes
+    /// one, so a host answering each of the target's probes counts once per probe.
+    rtt_samples: u32,
+    rtt_histogram: Histogram,
+
+    /// Index and RTT of the reply to the earliest of the target's probes that was answered,
+    /// which may have paid for eg resolving the target's MAC address where later ones didn't.
+    first_reply: Option<(u64, Duration)>,
+
+    /// RTTs of the most recent probes, oldest first, with `None` for those that were lost.
+    recent: VecDeque<Option<Duration>>,
+
+    /// Outcomes of probes completing ahead of earlier ones, by probe index, with `None` for those
+    /// that were lost. Jitter is computed in send order, while replies arrive in any order.
+    reordered: BTreeMap<u64, Option<Duration>>,
+
+    /// Index of the next probe in send order whose outcome jitter hasn't taken into account.
+    next_index: u64,
+
+    /// RTT of the last reply taken into account for jitter.
+    jitter_last: Option<Duration>,
+
+    /// RFC 3550 smoothed jitter and largest difference between the RTTs of successive replies,
+    /// once there have been two.
+    jitter: Option<Duration>,
+    rtt_delta_max: Option<Duration>,
+
+    /// Delays between when probes were scheduled and sent.
+    send_delay_max: Option<Duration>,
+    send_delay_total: Duration,
+    send_delays: u32,
+
+    /// Probes whose tick passed while the target's previous probe was still being sent.
+    overruns: u64,
+
+    /// Probes per second asked for by the intervals of the loops probing the target, if they're
+    /// all sent at fixed intervals.
+    requested_rate: Option<f64>,
+
+    /// When the first and last of the target's probes finished sending, and how many did.
+    first_sent: Option<Instant>,
+    last_sent: Option<Instant>,
+    timed_sends: u64,
+
+    /// Distinct hosts that responded, tracked only for broadcast targets. Loss doesn't apply to
+    /// these since any number of hosts may respond to each probe.
+    responders: Option<HashSet<Ipv4Addr>>,
+
+    /// Free-form label given to the target in the target list.
+    label: Option<String>,
+
+    /// Group the target belongs to, if any.
+    group: Option<String>,
+}
+
+impl TargetSummary {
+    fn record<P: Probe>(&mut self, report: &ProbeReport<P>) {
+        if let Some(delay) = report.send_delay {
+            self.send_delay_total += delay;
+            self.send_delays += 1;
+            self.send_delay_max = Some(self.send_delay_max.map_or(delay, |max| max.max(delay)));
+        }
+        let outcome = &report.outcome;
+        // notices are reported alongside probes rather than in place of them
+        if let ProbeOutcome::Notice(_) = outcome {
+            return;
+        }
+        if let Some(responders) = &mut self.responders {
+            // broadcast probes produce a report per responding host, so sent and received counts
+            // would be meaningless
+            if let Some(responder) = report.responder() {
+                responders.insert(responder);
+            }
+            if let Some(rtt) = outcome.rtt() {
+                self.record_rtt(rtt);
+                self.record_recent(Some(rtt));
+            }
+            return;
+        }
+        self.sent += 1;
+        if report.overran {
+            self.overruns += 1;
+        }
+        if let Some(sent_at) = report.sent_at {
+            self.first_sent = Some(self.first_sent.map_or(sent_at, |first| first.min(sent_at)));
+            self.last_sent = Some(self.last_sent.map_or(sent_at, |last| last.max(sent_at)));
+            self.timed_sends += 1;
+        }
+        if report.rtt_exceeded() {
+            self.rtt_exceeded += 1;
+        }
+        match (outcome.status(), outcome.rtt()) {
+            (ProbeStatus::Replied, Some(rtt)) => {
+                self.received += 1;
+                if report.responded_by().is_some() {
+                    self.responded_by_other += 1;
+                }
+                if let ProbeOutcome::ReceivedOutput(output, _) = outcome {
+                    self.record_reply_ttl(output.reply_ttl());
+                }
+                if !matches!(self.first_reply, Some((index, _)) if index < report.index) {
+                    self.first_reply = Some((report.index, rtt));
+                }
+                self.record_rtt(rtt);
+            }
+            (ProbeStatus::TtlExpired, _) => self.ttl_expired += 1,
+            (ProbeStatus::Corrupted, _) => self.corrupted += 1,
+            (ProbeStatus::Truncated, _) => self.truncated += 1,
+            (ProbeStatus::TooBig, _) => self.too_big += 1,
+            _ => (),
+        }
+        let rtt = outcome
+            .rtt()
+            .filter(|_| outcome.status() == ProbeStatus::Replied);
+        self.record_recent(rtt);
+        self.record_in_order(report.index, rtt);
+    }
+
+    /// Take the outcome of the probe with `index` into account for jitter once those of all
+    /// earlier probes have been.
+    fn record_in_order(&mut self, index: u64, rtt: Option<Duration>) {
+        // eg a loop restarted by a reload numbering its probes from zero again
+        if index < self.next_index {
+            return;
+        }
+        self.reordered.insert(index, rtt);
+        if self.reordered.len() > JITTER_REORDER_WINDOW {
+            // an earlier probe's outcome is never coming, so skip it
+            if let Some(first) = self.reordered.keys().next() {
+                self.next_index = *first;
+            }
+        }
+        while let Some(rtt) = self.reordered.remove(&self.next_index) {
+            self.next_index += 1;
+            let Some(rtt) = rtt else { continue };
+            if let Some(last) = self.jitter_last {
+                let delta = rtt.abs_diff(last);
+                // J += (|D| - J) / 16
+                let jitter = self.jitter.unwrap_or_default();
+                self.jitter = Some(jitter.mul_f64(15.0 / 16.0) + delta / 16);
+                self.rtt_delta_max = Some(self.rtt_delta_max.map_or(delta, |max| max.max(delta)));
+            }
+            self.jitter_last = Some(rtt);
+        }
+    }
+
+    fn record_reply_ttl(&mut self, ttl: Option<u8>) {
+        let Some(ttl) = ttl else { return };
+        self.reply_ttl_min = Some(self.reply_ttl_min.map_or(ttl, |min| min.min(ttl)));
+        self.reply_ttl_max = Some(self.reply_ttl_max.map_or(ttl, |max| max.max(ttl)));
+    }
+
+    /// Whether the TTLs of the target's replies varied by more than a change in route length
+    /// accounts for, suggesting that different hosts answered, eg instances of an anycast address
+    /// or hosts behind ECMP load balancing that start replies at different TTLs.
+    fn multipath(&self) -> bool {
+        match (self.reply_ttl_min, self.reply_ttl_max) {
+            (Some(min), Some(max)) => max - min > MULTIPATH_TTL_SPREAD,
+            _ => false,
+        }
+    }
+
+    fn record_recent(&mut self, rtt: Option<Duration>) {
+        if self.recent.len() == RECENT_PROBES {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(rtt);
+    }
+
+    /// Loss over the most recent probes, if any have completed.
+    fn recent_loss(&self) -> Option<f64> {
+        let lost = self.recent.iter().filter(|rtt| rtt.is_none()).count();
+        (!self.recent.is_empty()).then(|| percent(lost as u64, self.recent.len() as u64))
+    }
+
+    fn record_rtt(&mut self, rtt: Duration) {
+        self.rtt_total += rtt;
+        self.rtt_samples += 1;
+        self.rtt_min = Some(self.rtt_min.map_or(rtt, |min| min.min(rtt)));
+        self.rtt_max = Some(self.rtt_max.map_or(rtt, |max| max.max(rtt)));
+        self.rtt_last = Some(rtt);
+        self.rtt_histogram.record(rtt);
+    }
+
+    /// Number of distinct hosts that responded, for broadcast targets.
+    pub fn responders(&self) -> Option<usize> {
+        self.responders.as_ref().map(HashSet::len)
+    }
+
+    /// Whether any probe was answered, or for broadcast targets any host responded.
+    fn replied(&self) -> bool {
+        match self.responders() {
+            Some(responders) => responders > 0,
+            None => self.received > 0,
+        }
+    }
+
+    /// The color the target's statistics are shown in: green without loss, yellow with some and
+    /// red with total loss. Broadcast targets are red only if no host responded.
+    fn color(&self) -> Option<Color> {
+        if !self.replied() {
+            Some(Color::Red)
+        } else if self.responders.is_some() {
+            None
+        } else if self.received < self.sent {
+            Some(Color::Yellow)
+        } else {
+            Some(Color::Green)
+        }
+    }
+
+    /// Percentage of probes that didn't receive a reply. A target that never had a probe sent is
+    /// considered entirely lost.
+    pub fn loss(&self) -> f64 {
+        percent(self.sent - self.received, self.sent)
+    }
+
+    fn rtt_avg(&self) -> Option<Duration> {
+        (self.rtt_samples > 0).then(|| self.rtt_total / self.rtt_samples)
+    }
+
+    /// RTT of the first reply and average RTT of the rest, once there are some, so that the cost
+    /// of a cold path can be told from its steady state.
+    fn rtt_first_and_rest(&self) -> Option<(Duration, Duration)> {
+        let (_, first) = self.first_reply?;
+        let rest = self.received.checked_sub(1).filter(|rest| *rest > 0)?;
+        Some((first, self.rtt_total.saturating_sub(first) / rest as u32))
+    }
+
+    fn send_delay_avg(&self) -> Option<Duration> {
+        (self.send_delays > 0).then(|| self.send_delay_total / self.send_delays)
+    }
+
+    /// Probes per second actually sent, from the time between the first and last sends.
+    fn achieved_rate(&self) -> Option<f64> {
+        let elapsed = self.last_sent?.duration_since(self.first_sent?);
+        (self.timed_sends > 1 && !elapsed.is_zero())
+            .then(|| (self.timed_sends - 1) as f64 / elapsed.as_secs_f64())
+    }
+
+    /// The requested and achieved probe rates, if the target's probes fell short of the rate
+    /// its interval asks for.
+    fn rate_shortfall(&self) -> Option<(f64, f64)> {
+        let (requested, achieved) = (self.requested_rate?, self.achieved_rate()?);
+        (achieved < requested * RATE_SHORTFALL).then_some((requested, achieved))
+    }
+
+    /// Estimate an RTT percentile, clamped to the observed range since buckets are reported by
+    /// their midpoint.
+    fn rtt_percentile(&self, percentile: f64) -> Option<Duration> {
+        let (min, max) = (self.rtt_min?, self.rtt_max?);
+        Some(self.rtt_histogram.percentile(percentile)?.clamp(min, max))
+    }
+
+    /// Summarize this target's statistics in a form suitable for serialization.
+    fn stats(&self, (addr, probe, instance): TargetKey) -> TargetStats {
+        let broadcast = self.responders.is_some();
+        TargetStats {
+            addr,
+            probe,
+            instance,
+            label: self.label.clone(),
+            sent: (!broadcast).then_some(self.sent),
+            received: (!broadcast).then_some(self.received),
+            loss_percent: (!broadcast).then(|| self.loss()),
+            responders: self.responders(),
+            ttl_expired: self.ttl_expired,
+            corrupted: self.corrupted,
+            truncated: self.truncated,
+            too_big: self.too_big,
+            redirects: self.redirects,
+            unexpected_icmp: self.unexpected.clone(),
+            short_replies: self.short_replies,
+            rtt_exceeded: self.rtt_exceeded,
+            responded_by_other: self.responded_by_other,
+            reply_ttl_min: self.reply_ttl_min,
+            reply_ttl_max: self.reply_ttl_max,
+            multipath: self.multipath(),
+            rtt_min_ms: self.rtt_min.map(as_ms),
+            rtt_avg_ms: self.rtt_avg().map(as_ms),
+            rtt_max_ms: self.rtt_max.map(as_ms),
+            rtt_first_ms: self.first_reply.map(|(_, rtt)| as_ms(rtt)),
+            rtt_rest_avg_ms: self.rtt_first_and_rest().map(|(_, rest)| as_ms(rest)),
+            rtt_p50_ms: self.rtt_percentile(50.0).map(as_ms),
+            rtt_p90_ms: self.rtt_percentile(90.0).map(as_ms),
+            rtt_p99_ms: self.rtt_percentile(99.0).map(as_ms),
+            rtt_p999_ms: self.rtt_percentile(99.9).map(as_ms),
+            jitter_ms: self.jitter.map(as_ms),
+            rtt_delta_max_ms: self.rtt_delta_max.map(as_ms),
+            send_delay_avg_ms: self.send_delay_avg().map(as_ms),
+            send_delay_max_ms: self.send_delay_max.map(as_ms),
+            overruns: self.overruns,
+            requested_pps: self.requested_rate,
+            achieved_pps: self.achieved_rate(),
+        }
+    }
+
+    /// Which of the given thresholds this target breaches, if any. A broadcast target breaches
+    /// only if no host responded.
+    fn breaches(&self, thresholds: &Thresholds) -> Option<Breach> {
+        if let Some(responders) = &self.responders {
+            return responders.is_empty().then_some(Breach::Loss);
+        }
+        breach(self.sent, self.received, self.rtt_exceeded, thresholds)
+    }
+}
+
+impl std::fmt::Display for TargetSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.responders() {
+            Some(responders) => write!(f, "{responders} unique responders")?,
+            None => write!(
+                f,
+                "{} sent, {} received, {:.1}% loss",
+                self.sent,
+                self.received,
+                self.loss()
+            )?,
+        }
+        if self.ttl_expired > 0 {
+            write!(f, ", {} ttl expired", self.ttl_expired)?;
+        }
+        if self.corrupted > 0 {
+            write!(f, ", {} corrupted", self.corrupted)?;
+        }
+        if self.truncated > 0 {
+            write!(f, ", {} truncated", self.truncated)?;
+        }
+        if self.too_big > 0 {
+            write!(f, ", {} too big", self.too_big)?;
+        }
+        if self.redirects > 0 {
+            write!(f, ", {} redirects", self.redirects)?;
+        }
+        if !self.unexpected.is_empty() {
+            let unexpected: Vec<String> = self
+                .unexpected
+                .iter()
+                .map(|(kind, count)| format!("{count} {kind}"))
+                .collect();
+            write!(f, ", unexpected icmp: {}", unexpected.join(", "))?;
+        }
+        if self.short_replies > 0 {
+            write!(f, ", {} short replies", self.short_replies)?;
+        }
+        if self.rtt_exceeded > 0 {
+            write!(f, ", {} probes over threshold", self.rtt_exceeded)?;
+        }
+        if self.responded_by_other > 0 {
+            write!(f, ", {} from other addresses", self.responded_by_other)?;
+        }
+        if let (Some(min), Some(max)) = (self.reply_ttl_min, self.reply_ttl_max) {
+            if self.multipath() {
+                write!(
+                    f,
+                    ", reply ttl {min}-{max} (possibly several responders: anycast or multipath)"
+                )?;
+            }
+        }
+        if let (Some(min), Some(avg), Some(max)) = (self.rtt_min, self.rtt_avg(), self.rtt_max) {
+            write!(
+                f,
+                ", rtt min/avg/max = {:.3}/{:.3}/{:.3} ms",
+                min.as_secs_f64() * 1000.0,
+                avg.as_secs_f64() * 1000.0,
+                max.as_secs_f64() * 1000.0,
+            )?;
+        }
+        if let Some((first, rest)) = self.rtt_first_and_rest() {
+            write!(
+                f,
+                ", rtt first/rest avg = {:.3}/{:.3} ms",
+                as_ms(first),
+                as_ms(rest)
+            )?;
+        }
+        let percentiles: Option<Vec<Duration>> = [50.0, 90.0, 99.0, 99.9]
+            .into_iter()
+            .map(|p| self.rtt_percentile(p))
+            .collect();
+        if let Some(p) = percentiles {
+            write!(
+                f,
+                ", rtt p50/p90/p99/p99.9 = {:.3}/{:.3}/{:.3}/{:.3} ms",
+                as_ms(p[0]),
+                as_ms(p[1]),
+                as_ms(p[2]),
+                as_ms(p[3]),
+            )?;
+        }
+        if let (Some(jitter), Some(max)) = (self.jitter, self.rtt_delta_max) {
+            write!(
+                f,
+                ", jitter/max delta = {:.3}/{:.3} ms",
+                as_ms(jitter),
+                as_ms(max)
+            )?;
+        }
+        if let (Some(avg), Some(max)) = (self.send_delay_avg(), self.send_delay_max) {
+            write!(
+                f,
+                ", send delay avg/max = {:.3}/{:.3} ms",
+                as_ms(avg),
+                as_ms(max)
+            )?;
+        }
+        if self.overruns > 0 {
+            write!(f, ", {} intervals overrun", self.overruns)?;
+        }
+        if let Some((requested, achieved)) = self.rate_shortfall() {
+            write!(
+                f,
+                ", requested {requested:.1} pps but only achieved {achieved:.1}"
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Serializable statistics for a single target. Percentiles are estimated from a bucketed
+/// histogram and so are accurate to within a few percent.
+#[derive(Debug, Serialize)]
+pub struct TargetStats {
+    addr: Ipv4Addr,
+    probe: ProbeType,
+
+    /// Which of several rows probing the same address these are the statistics of, if there are
+    /// several.
+    instance: Option<u16>,
+    label: Option<String>,
+    sent: Option<u64>,
+    received: Option<u64>,
+    loss_percent: Option<f64>,
+    responders: Option<usize>,
+    ttl_expired: u64,
+    corrupted: u64,
+    truncated: u64,
+    too_big: u64,
+    redirects: u64,
+    unexpected_icmp: BTreeMap<String, u64>,
+    short_replies: u64,
+    rtt_exceeded: u64,
+    responded_by_other: u64,
+
+    /// Range of the TTLs of replies, and whether it's wide enough to suggest that several hosts
+    /// answered for the target.
+    reply_ttl_min: Option<u8>,
+    reply_ttl_max: Option<u8>,
+    multipath: bool,
+    rtt_min_ms: Option<f64>,
+    rtt_avg_ms: Option<f64>,
+    rtt_max_ms: Option<f64>,
+
+    /// RTT of the reply to the earliest probe answered, and average RTT of the later replies,
+    /// which is null with fewer than two replies.
+    rtt_first_ms: Option<f64>,
+    rtt_rest_avg_ms: Option<f64>,
+    rtt_p50_ms: Option<f64>,
+    rtt_p90_ms: Option<f64>,
+    rtt_p99_ms: Option<f64>,
+    rtt_p999_ms: Option<f64>,
+
+    /// RFC 3550 smoothed jitter and largest difference between the RTTs of successive replies,
+    /// in send order. Null with fewer than two replies.
+    jitter_ms: Option<f64>,
+    rtt_delta_max_ms: Option<f64>,
+    send_delay_avg_ms: Option<f64>,
+    send_delay_max_ms: Option<f64>,
+
+    /// Probes sent late because sending the previous one overran the target's interval, and the
+    /// probe rates asked for and achieved. The requested rate is null unless probes are sent at
+    /// fixed intervals.
+    overruns: u64,
+    requested_pps: Option<f64>,
+    achieved_pps: Option<f64>,
+}
+
+/// Statistics aggregated across the members of a group.
+#[derive(Debug, Default)]
+struct GroupSummary {
+    members: Vec<TargetKey>,
+    sent: u64,
+    received: u64,
+    rtt_exceeded: u64,
+    rtt_min: Option<Duration>,
+    rtt_max: Option<Duration>,
+    rtt_total: Duration,
+    rtt_samples: u32,
+    rtt_histogram: Histogram,
+
+    /// The member with the highest loss, ties going to the one with the higher average RTT.
+    /// Broadcast members have no loss and so are never the worst.
+    worst: Option<TargetKey>,
+}
+
+impl GroupSummary {
+    /// Combined loss across the group's members. As for a single target, a group that never had
+    /// a probe sent is considered entirely lost.
+    fn loss(&self) -> f64 {
+        percent(self.sent - self.received, self.sent)
+    }
+
+    fn rtt_avg(&self) -> Option<Duration> {
+        (self.rtt_samples > 0).then(|| self.rtt_total / self.rtt_samples)
+    }
+
+    fn rtt_percentile(&self, percentile: f64) -> Option<Duration> {
+        let (min, max) = (self.rtt_min?, self.rtt_max?);
+        Some(self.rtt_histogram.percentile(percentile)?.clamp(min, max))
+    }
+}
+
+/// Serializable statistics for a group, with those of its members.
+#[derive(Debug, Serialize)]
+pub struct GroupStats {
+    name: String,
+    sent: u64,
+    received: u64,
+    loss_percent: f64,
+    rtt_exceeded: u64,
+    worst: Option<Ipv4Addr>,
+    worst_loss_percent: Option<f64>,
+    rtt_min_ms: Option<f64>,
+    rtt_avg_ms: Option<f64>,
+    rtt_max_ms: Option<f64>,
+    rtt_p50_ms: Option<f64>,
+    rtt_p90_ms: Option<f64>,
+    rtt_p99_ms: Option<f64>,
+    rtt_p999_ms: Option<f64>,
+    targets: Vec<TargetStats>,
+}
+
+/// Serializable statistics for a whole run. Targets belonging to a group are nested under it
+/// rather than listed in `targets`.
+#[derive(Debug, Serialize)]
+pub struct SummaryStats {
+    groups: Vec<GroupStats>,
+    targets: Vec<TargetStats>,
+    dropped_replies: u64,
+    kernel_drops: u64,
+    limiter_delay_ms: Option<u128>,
+    worker_packets: Vec<u64>,
+    outages: Vec<Outage>,
+}
+
+/// Serializable statistics for a whole run, along with details of the run itself, written as a
+/// single document to be kept as an artifact of the run.
+#[derive(Debug, Serialize)]
+pub struct RunStats {
+    /// When the run started and ended, in milliseconds since the Unix epoch.
+    started_ms: u128,
+    ended_ms: u128,
+
+    /// Names of the interfaces probes were sent from.
+    interfaces: Vec<String>,
+
+    /// Probes sent and replies received across all targets but broadcast ones.
+    sent: u64,
+    received: u64,
+
+    #[serde(flatten)]
+    stats: SummaryStats,
+}
+
+/// A target address, the kind of probe sent to it and, if several rows probe the address that
+/// way, the instance of the row's loop. Targets probed in several ways, or by several rows, are
+/// summarized separately for each so that eg rows at different intervals get their own jitter.
+pub type TargetKey = (Ipv4Addr, ProbeType, Option<u16>);
+
+/// Statistics for all targets in a run, reported in target order.
+#[derive(Debug, Default)]
+pub struct Summary {
+    order: Vec<TargetKey>,
+    targets: HashMap<TargetKey, TargetSummary>,
+
+    /// Cumulative delay introduced by the global rate limiter, if one was in use.
+    pub limiter_delay: Option<Duration>,
+
+    /// Replies that arrived but were dropped internally before reaching their probe.
+    pub dropped_replies: u64,
+
+    /// Packets the kernel dropped before they could be received, eg because a receive buffer
+    /// was full.
+    pub kernel_drops: u64,
+
+    /// Packets received by each receive worker, summed across interfaces.
+    pub worker_packets: Vec<u64>,
+
+    /// Periods during which an interface was down, with --survive-link-down.
+    pub outages: Vec<Outage>,
+
+    /// Whether each target's statistics are colored by its loss.
+    pub colored: bool,
+}
+
+impl Summary {
+    /// Start summarizing `key`, if it isn't already, so that it's reported even if none of its
+    /// probes complete.
+    pub fn add(&mut self, key: TargetKey) {
+        self.entry(key);
+    }
+
+    fn entry(&mut self, key: TargetKey) -> &mut TargetSummary {
+        if !self.targets.contains_key(&key) {
+            self.order.push(key);
+        }
+        self.targets.entry(key).or_default()
+    }
+
+    /// Treat `addr` as a broadcast target, for which the number of unique responders is reported
+    /// rather than loss.
+    pub fn set_broadcast(&mut self, key: TargetKey) {
+        self.entry(key).responders = Some(HashSet::new());
+    }
+
+    /// Record `count` ICMP Redirects received for the target's probes.
+    pub fn add_redirects(&mut self, key: TargetKey, count: u64) {
+        self.entry(key).redirects += count;
+    }
+
+    /// Record `count` ICMP error messages described by `kind` received for the target's probes.
+    pub fn add_unexpected(&mut self, key: TargetKey, kind: String, count: u64) {
+        *self.entry(key).unexpected.entry(kind).or_default() += count;
+    }
+
+    /// Record `count` replies too short to be matched to a probe of the target. Those too short
+    /// to carry a sequence number can't be told apart between rows probing the address, and come
+    /// without an instance; they're counted against the first row.
+    pub fn add_short_replies(&mut self, key: TargetKey, count: u64) {
+        let (addr, probe, _) = key;
+        let key = if self.targets.contains_key(&key) {
+            key
+        } else {
+            self.order
+                .iter()
+                .find(|(a, p, _)| *a == addr && *p == probe)
+                .copied()
+                .unwrap_or(key)
+        };
+        self.entry(key).short_replies += count;
+    }
+
+    /// Record that the target's intervals ask for `rate` probes per second.
+    pub fn set_requested_rate(&mut self, key: TargetKey, rate: f64) {
+        self.entry(key).requested_rate = Some(rate);
+    }
+
+    /// Label the target in the summary and serialized statistics.
+    pub fn set_label(&mut self, key: TargetKey, label: String) {
+        self.entry(key).label = Some(label);
+    }
+
+    /// Add the target to the named group, whose statistics are aggregated across its members.
+    pub fn set_group(&mut self, key: TargetKey, group: String) {
+        self.entry(key).group = Some(group);
+    }
+
+    /// Aggregate the statistics of each group, in the order groups first appear among targets.
+    fn groups(&self) -> Vec<(&str, GroupSummary)> {
+        let mut groups: Vec<(&str, GroupSummary)> = Vec::new();
+        for key in &self.order {
+            let t = &self.targets[key];
+            let name = match &t.group {
+                Some(name) => name.as_str(),
+                None => continue,
+            };
+            let index = match groups.iter().position(|(n, _)| *n == name) {
+                Some(index) => index,
+                None => {
+                    groups.push((name, GroupSummary::default()));
+                    groups.len() - 1
+                }
+            };
+            let g = &mut groups[index].1;
+            g.members.push(*key);
+            g.sent += t.sent;
+            g.received += t.received;
+            g.rtt_exceeded += t.rtt_exceeded;
+            g.rtt_total += t.rtt_total;
+            g.rtt_samples += t.rtt_samples;
+            g.rtt_histogram.merge(&t.rtt_histogram);
+            if let Some(min) = t.rtt_min {
+                g.rtt_min = Some(g.rtt_min.map_or(min, |m| m.min(min)));
+            }
+            if let Some(max) = t.rtt_max {
+                g.rtt_max = Some(g.rtt_max.map_or(max, |m| m.max(max)));
+            }
+            if t.responders.is_none() {
+                let rank = |t: &TargetSummary| (t.loss(), t.rtt_avg().unwrap_or_default());
+                let worse = match g.worst {
+                    Some(worst) => rank(t) > rank(&self.targets[&worst]),
+                    None => true,
+                };
+                if worse {
+                    g.worst = Some(*key);
+                }
+            }
+        }
+        groups
+    }
+
+    /// The target's address, followed by the kind of probe if the address is probed in several
+    /// ways and by the instance of its row if several rows probe it.
+    fn name(&self, key: &TargetKey) -> String {
+        let (addr, probe, instance) = key;
+        let mut name = addr.to_string();
+        if self.order.iter().any(|(a, p, _)| a == addr && p != probe) {
+            name.push_str(&format!(" {probe}"));
+        }
+        if let Some(instance) = instance {
+            name.push_str(&format!(" #{instance}"));
+        }
+        name
+    }
+
+    /// The target's name followed by its label, if any, for headings.
+    pub fn heading(&self, key: &TargetKey) -> String {
+        match &self.targets[key].label {
+            Some(label) => format!("{} [{label}]", self.name(key)),
+            None => self.name(key),
+        }
+    }
+
+    /// Render one line per target reading `<name> up` if any of its probes was answered and
+    /// `<name> down` otherwise.
+    pub fn reachability(&self) -> String {
+        let mut out = String::new();
+        for key in &self.order {
+            let state = if self.targets[key].replied() {
+                "up"
+            } else {
+                "down"
+            };
+            out.push_str(&format!("{} {state}\n", self.name(key)));
+        }
+        out
+    }
+
+    /// Record the outcome of a probe. Warm-up probes are ignored.
+    pub fn record<P: Probe>(&mut self, report: &ProbeReport<P>) {
+        if report.warmup {
+            return;
+        }
+        self.entry((report.tparams.addr, report.tparams.probe, report.instance))
+            .record(report);
+    }
+
+    /// Names of the targets, or with `FailScope::Group` the groups and ungrouped targets, that
+    /// breach the given thresholds and which they breached, along with the number evaluated.
+    pub fn breached(&self, thresholds: &Thresholds) -> (Vec<(String, Breach)>, usize) {
+        let mut breached = Vec::new();
+        let mut evaluated = 0;
+        if thresholds.scope == FailScope::Group {
+            for (name, g) in self.groups() {
+                evaluated += 1;
+                // as for a single broadcast target, a group of them breaches only if none
+                // had a responder
+                let breach = if g.sent == 0 {
+                    g.members
+                        .iter()
+                        .all(|key| self.targets[key].breaches(thresholds).is_some())
+                        .then_some(Breach::Loss)
+                } else {
+                    breach(g.sent, g.received, g.rtt_exceeded, thresholds)
+                };
+                if let Some(breach) = breach {
+                    breached.push((format!("group {name}"), breach));
+                }
+            }
+        }
+        for key in &self.order {
+            let t = &self.targets[key];
+            if thresholds.scope == FailScope::Group && t.group.is_some() {
+                continue;
+            }
+            evaluated += 1;
+            if let Some(breach) = t.breaches(thresholds) {
+                breached.push((self.heading(key), breach));
+            }
+        }
+        (breached, evaluated)
+    }
+
+    /// Render a table with one row per target showing sent/received counts, overall and recent
+    /// loss, RTTs and a sparkline of recent RTTs in which lost probes are marked `!`.
+    pub fn table(&self) -> String {
+        let mut table = format!(
+            "{:<16} {:>8} {:>8} {:>7} {:>7} {:>12} {:>12} {:>12}  {}\n",
+            "TARGET", "SENT", "RECV", "LOSS", "RECENT", "LAST RTT", "AVG RTT", "MAX RTT", "HISTORY"
+        );
+        for key in &self.order {
+            let t = &self.targets[key];
+            // broadcast targets show the number of unique responders as received, without loss
+            let (sent, received, loss, recent_loss) = match t.responders() {
+                Some(responders) => (
+                    "-".to_string(),
+                    responders,
+                    "-".to_string(),
+                    "-".to_string(),
+                ),
+                None => (
+                    t.sent.to_string(),
+                    t.received as usize,
+                    format!("{:.1}%", t.loss()),
+                    t.recent_loss()
+                        .map_or("-".to_string(), |loss| format!("{loss:.1}%")),
+                ),
+            };
+            table.push_str(&format!(
+                "{:<16} {:>8} {:>8} {:>7} {:>7} {:>12} {:>12} {:>12}  {}\n",
+                self.name(key),
+                sent,
+                received,
+                loss,
+                recent_loss,
+                format_rtt(t.rtt_last),
+                format_rtt(t.rtt_avg()),
+                format_rtt(t.rtt_max),
+                sparkline(&t.recent),
+            ));
+        }
+        table
+    }
+
+    /// Whether enough packets were dropped locally, at least 1% of probes sent, to noticeably
+    /// inflate loss. The receive socket also sees ICMP unrelated to our probes, so a handful of
+    /// drops isn't necessarily ours.
+    fn kernel_drops_significant(&self) -> bool {
+        let sent: u64 = self.targets.values().map(|t| t.sent).sum();
+        self.kernel_drops > 0 && self.kernel_drops * 100 >= sent
+    }
+
+    /// Collect per-group, per-target and run-wide statistics for serialization.
+    pub fn stats(&self) -> SummaryStats {
+        let groups = self
+            .groups()
+            .into_iter()
+            .map(|(name, g)| GroupStats {
+                name: name.to_string(),
+                sent: g.sent,
+                received: g.received,
+                loss_percent: g.loss(),
+                rtt_exceeded: g.rtt_exceeded,
+                worst: g.worst.map(|(addr, _, _)| addr),
+                worst_loss_percent: g.worst.map(|key| self.targets[&key].loss()),
+                rtt_min_ms: g.rtt_min.map(as_ms),
+                rtt_avg_ms: g.rtt_avg().map(as_ms),
+                rtt_max_ms: g.rtt_max.map(as_ms),
+                rtt_p50_ms: g.rtt_percentile(50.0).map(as_ms),
+                rtt_p90_ms: g.rtt_percentile(90.0).map(as_ms),
+                rtt_p99_ms: g.rtt_percentile(99.0).map(as_ms),
+                rtt_p999_ms: g.rtt_percentile(99.9).map(as_ms),
+                targets: g
+                    .members
+                    .iter()
+                    .map(|key| self.targets[key].stats(*key))
+                    .collect(),
+            })
+            .collect();
+        SummaryStats {
+            groups,
+            targets: self
+                .order
+                .iter()
+                .filter(|key| self.targets[*key].group.is_none())
+                .map(|key| self.targets[key].stats(*key))
+                .collect(),
+            dropped_replies: self.dropped_replies,
+            kernel_drops: self.kernel_drops,
+            limiter_delay_ms: self.limiter_delay.map(|d| d.as_millis()),
+            worker_packets: self.worker_packets.clone(),
+            outages: self.outages.clone(),
+        }
+    }
+
+    /// Collect statistics for a run that started at `started`, sending probes from
+    /// `interfaces`, and ends now.
+    pub fn run_stats(&self, started: SystemTime, interfaces: Vec<String>) -> RunStats {
+        let unix_ms = |time: SystemTime| {
+            time.duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis()
+        };
+        let unicast = || self.targets.values().filter(|t| t.responders.is_none());
+        RunStats {
+            started_ms: unix_ms(started),
+            ended_ms: unix_ms(SystemTime::now()),
+            interfaces,
+            sent: unicast().map(|t| t.sent).sum(),
+            received: unicast().map(|t| t.received).sum(),
+            stats: self.stats(),
+        }
+    }
+
+    /// Render a histogram of RTTs for each target that received replies, in target order.
+    pub fn histograms(&self) -> String {
+        let mut out = String::new();
+        for key in &self.order {
+            let t = &self.targets[key];
+            if t.rtt_min.is_none() {
+                continue;
+            }
+            out.push_str(&format!("--- {} rtt histogram ---\n", self.heading(key)));
+            out.push_str(&t.rtt_histogram.render());
+        }
+        out
+    }
+
+    /// Determine the process exit code given the failure thresholds.
+    pub fn exit_code(&self, thresholds: &Thresholds) -> ExitCode {
+        let (breached, evaluated) = self.breached(thresholds);
+        let code = if breached.is_empty() {
+            EXIT_SUCCESS
+        } else if breached.len() < evaluated {
+            EXIT_PARTIAL_FAILURE
+        } else {
+            EXIT_TOTAL_FAILURE
+        };
+        ExitCode::from(code)
+    }
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for key in &self.order {
+            writeln!(f, "--- {} statistics ---", self.heading(key))?;
+            let target = &self.targets[key];
+            match target.color().filter(|_| self.colored) {
+                Some(color) => writeln!(f, "{}", target.to_string().with(color))?,
+                None => writeln!(f, "{target}")?,
+            }
+        }
+        for (name, g) in self.groups() {
+            writeln!(f, "--- group {name} statistics ---")?;
+            write!(
+                f,
+                "{} target{}, {} sent, {} received, {:.1}% loss",
+                g.members.len(),
+                if g.members.len() == 1 { "" } else { "s" },
+                g.sent,
+                g.received,
+                g.loss()
+            )?;
+            if let Some(worst) = g.worst {
+                write!(
+                    f,
+                    ", worst {} ({:.1}% loss)",
+                    self.name(&worst),
+                    self.targets[&worst].loss()
+                )?;
+            }
+            if let (Some(min), Some(avg), Some(max)) = (g.rtt_min, g.rtt_avg(), g.rtt_max) {
+                write!(
+                    f,
+                    ", rtt min/avg/max = {:.3}/{:.3}/{:.3} ms",
+                    as_ms(min),
+                    as_ms(avg),
+                    as_ms(max)
+                )?;
+            }
+            let percentiles: Option<Vec<Duration>> = [50.0, 90.0, 99.0, 99.9]
+                .into_iter()
+                .map(|p| g.rtt_percentile(p))
+                .collect();
+            if let Some(p) = percentiles {
+                write!(
+                    f,
+                    ", rtt p50/p90/p99/p99.9 = {:.3}/{:.3}/{:.3}/{:.3} ms",
+                    as_ms(p[0]),
+                    as_ms(p[1]),
+                    as_ms(p[2]),
+                    as_ms(p[3]),
+                )?;
+            }
+            writeln!(f)?;
+        }
+        if self.dropped_replies > 0 {
+            writeln!(
+                f,
+                "{} replies were dropped internally and their probes counted as lost",
+                self.dropped_replies
+            )?;
+        }
+        if self.kernel_drops > 0 {
+            writeln!(
+                f,
+                "{} packets locally dropped by the kernel before they could be read",
+                self.kernel_drops
+            )?;
+            if self.kernel_drops_significant() {
+                writeln!(
+                    f,
+                    "warning: loss figures may be inflated by local drops; try a larger --rcvbuf"
+                )?;
+            }
+        }
+        for outage in &self.outages {
+            writeln!(f, "{outage}")?;
+        }
+        if let Some(delay) = self.limiter_delay {
+            writeln!(
+                f,
+                "rate limiter delayed sends by {}ms in total",
+                delay.as_millis()
+            )?;
+        }
+        // only worth showing when there are several workers whose load may be skewed
+        if self.worker_packets.len() > 1 {
+            let packets: Vec<String> = self.worker_packets.iter().map(u64::to_string).collect();
+            writeln!(f, "packets received per worker: {}", packets.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+fn as_ms(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+/// Draw each RTT as a bar scaled between the smallest and largest of them, and each lost probe as
+/// `!`.
+fn sparkline(rtts: &VecDeque<Option<Duration>>) -> String {
+    let min = rtts.iter().flatten().min();
+    let max = rtts.iter().flatten().max();
+    let (min, max) = match (min, max) {
+        (Some(min), Some(max)) => (min.as_secs_f64(), max.as_secs_f64()),
+        _ => return "!".repeat(rtts.len()),
+    };
+    let top = (SPARKS.len() - 1) as f64;
+    rtts.iter()
+        .map(|rtt| match rtt {
+            Some(rtt) if max > min => {
+                SPARKS[((rtt.as_secs_f64() - min) / (max - min) * top).round() as usize]
+            }
+            Some(_) => SPARKS[0],
+            None => '!',
+        })
+        .collect()
+}
+
+fn format_rtt(rtt: Option<Duration>) -> String {
+    match rtt {
+        Some(rtt) => format!("{:.3}ms", rtt.as_secs_f64() * 1000.0),
+        None => "-".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::error::Result;
+    use crate::ethernet::EthernetConf;
+    use crate::prober::{RequestTag, Response, SendOptions, TargetParams};
+    use crate::socket::ProbeSocket;
+
+    const TARGET: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+
+    /// A probe whose outcomes are made up by the tests rather than received.
+    #[derive(Debug)]
+    struct Fabricated;
+
+    /// A reply from the given address.
+    #[derive(Clone, Debug, Serialize)]
+    struct Reply(Ipv4Addr);
+
+    impl std::fmt::Display for Reply {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "reply from {}", self.0)
+        }
+    }
+
+    impl ProbeOutput for Reply {
+        fn source(&self) -> Ipv4Addr {
+            self.0
+        }
+    }
+
+    #[async_trait]
+    impl Probe for Fabricated {
+        type Output = Reply;
+
+        async fn send(
+            &mut self,
+            _socket: &dyn ProbeSocket,
+            _params: &TargetParams,
+            _options: &SendOptions,
+        ) -> Result<()> {
+            unreachable!("fabricated probes are never sent")
+        }
+
+        fn reconfigure(&mut self, _ec: &EthernetConf) -> Result<()> {
+            Ok(())
+        }
+
+        fn validate_response(
+            _buf: &[u8],
+            _ec: &EthernetConf,
+            _tag: &RequestTag,
+        ) -> Option<Response<Reply>> {
+            None
+        }
+    }
+
+    /// A report of the `index`th probe of the row with `instance`, answered after `rtt_ms`.
+    fn replied(instance: Option<u16>, index: u64, rtt_ms: u64) -> ProbeReport<Fabricated> {
+        ProbeReport {
+            tparams: TargetParams {
+                addr: TARGET,
+                seq: index as u16,
+                probe: ProbeType::Echo,
+            },
+            index,
+            outcome: ProbeOutcome::ReceivedOutput(Reply(TARGET), Duration::from_millis(rtt_ms)),
+            interval: None,
+            warmup: false,
+            instance,
+            broadcast: false,
+            dscp: 0,
+            send_delay: None,
+            sent_at: None,
+            overran: false,
+            send_retries: 0,
+            responder_name: None,
+            label: None,
+            rtt_warn: None,
+        }
+    }
+
+    #[test]
+    fn rows_probing_the_same_address_are_summarized_separately() {
+        let (fast, slow) = (
+            (TARGET, ProbeType::Echo, Some(0)),
+            (TARGET, ProbeType::Echo, Some(1)),
+        );
+        let mut summary = Summary::default();
+        summary.add(fast);
+        summary.add(slow);
+        // the rows' probes complete interleaved, each row with a steady RTT of its own
+        for index in 0..10 {
+            summary.record(&replied(Some(0), index, 10));
+            summary.record(&replied(Some(1), index, 50));
+        }
+
+        let stats = summary.stats();
+        let instances: Vec<_> = stats.targets.iter().map(|t| t.instance).collect();
+        assert_eq!(instances, vec![Some(0), Some(1)]);
+        for (t, rtt) in stats.targets.iter().zip([10.0, 50.0]) {
+            assert_eq!(t.sent, Some(10));
+            assert_eq!(t.rtt_avg_ms, Some(rtt));
+            // merged, the alternating RTTs would differ by 40ms every probe
+            assert_eq!(t.jitter_ms, Some(0.0));
+            assert_eq!(t.rtt_delta_max_ms, Some(0.0));
+        }
+        assert_eq!(summary.heading(&fast), "192.0.2.1 #0");
+        assert_eq!(summary.heading(&slow), "192.0.2.1 #1");
+    }
+
+    #[test]
+    fn short_replies_count_against_their_row_or_else_the_first() {
+        let mut summary = Summary::default();
+        summary.add((TARGET, ProbeType::Echo, Some(0)));
+        summary.add((TARGET, ProbeType::Echo, Some(1)));
+        summary.add_short_replies((TARGET, ProbeType::Echo, Some(1)), 2);
+        // too short to tell which row's probe they answer
+        summary.add_short_replies((TARGET, ProbeType::Echo, None), 3);
+        let short: Vec<_> = summary
+            .stats()
+            .targets
+            .iter()
+            .map(|t| t.short_replies)
+            .collect();
+        assert_eq!(short, vec![3, 2]);
+
+        // an address nothing was listed for still gets counted
+        let mut summary = Summary::default();
+        summary.add_short_replies((TARGET, ProbeType::Echo, None), 1);
+        assert_eq!(summary.stats().targets[0].instance, None);
+    }
+
+    #[test]
+    fn broadcast_rtt_average_is_taken_over_every_response() {
+        let broadcast = Ipv4Addr::new(192, 0, 2, 255);
+        let key = (broadcast, ProbeType::Echo, None);
+        let mut summary = Summary::default();
+        summary.add(key);
+        summary.set_broadcast(key);
+        // three hosts answer each of four probes
+        for index in 0..4 {
+            for (host, rtt_ms) in [(1, 10), (2, 20), (3, 60)] {
+                let mut report = replied(None, index, rtt_ms);
+                report.tparams.addr = broadcast;
+                report.outcome = ProbeOutcome::ReceivedOutput(
+                    Reply(Ipv4Addr::new(192, 0, 2, host)),
+                    Duration::from_millis(rtt_ms),
+                );
+                summary.record(&report);
+            }
+        }
+
+        let stats = summary.stats();
+        let t = &stats.targets[0];
+        assert_eq!(t.responders, Some(3));
+        assert_eq!(t.rtt_min_ms, Some(10.0));
+        assert_eq!(t.rtt_avg_ms, Some(30.0));
+        assert_eq!(t.rtt_max_ms, Some(60.0));
+    }
+}
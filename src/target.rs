@@ -0,0 +1,345 @@
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use serde::de::IntoDeserializer;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::error::{Error, Result};
+
+/// Largest DSCP value, which occupies the upper six bits of the IPv4 ToS byte.
+pub const MAX_DSCP: u8 = 63;
+
+/// Smallest echo request payload, which has room for the nonce replies are checked against.
+pub const MIN_PAYLOAD_SIZE: u16 = 4;
+
+/// Bounds that each `Target` must satisfy before it is probed.
+#[derive(Clone, Debug)]
+pub struct Limits {
+    /// Minimum interval between probes. Intervals must be non-zero regardless.
+    pub min_interval: Duration,
+
+    /// Maximum interval between probes.
+    pub max_interval: Option<Duration>,
+
+    /// Maximum number of probes to send to a single target.
+    pub max_count: Option<u64>,
+}
+
+/// Count and interval of targets whose rows leave them empty.
+#[derive(Clone, Debug)]
+pub struct TargetDefaults {
+    /// Number of probes, or `None` to keep probing until stopped, eg by `--duration`, or until
+    /// the limit on counts is reached.
+    pub count: Option<u64>,
+    pub interval: Duration,
+}
+
+/// Prepare a target list for parsing. Rows may be separated by semicolons or newlines; blank lines
+/// and lines beginning with `#` are dropped so that target files can be annotated.
+pub fn strip_comments(input: &str) -> String {
+    input
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// The kind of probe sent to a target.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbeType {
+    /// ICMP Echo requests.
+    Echo,
+    /// ICMP Timestamp requests.
+    Timestamp,
+}
+
+impl std::fmt::Display for ProbeType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Echo => write!(f, "echo"),
+            Self::Timestamp => write!(f, "timestamp"),
+        }
+    }
+}
+
+/// Deserialize a `+`-separated list of probe types, eg `echo+timestamp`, dropping duplicates. An
+/// empty field yields an empty list.
+fn deserialize_probe_types<'de, D>(deserializer: D) -> std::result::Result<Vec<ProbeType>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    let mut probe_types = Vec::new();
+    for part in s.split('+').map(str::trim).filter(|part| !part.is_empty()) {
+        let probe_type = ProbeType::deserialize(part.into_deserializer())
+            .map_err(|e: serde::de::value::Error| serde::de::Error::custom(e))?;
+        if !probe_types.contains(&probe_type) {
+            probe_types.push(probe_type);
+        }
+    }
+    Ok(probe_types)
+}
+
+/// A single row of the target list.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Target {
+    pub addr: Ipv4Addr,
+
+    /// Number of probes to send. Defaults to the count selected on the command line.
+    #[serde(default)]
+    pub count: Option<u64>,
+
+    /// Interval between probes, eg `250us`, `10ms` or `1.5s`. Bare numbers are milliseconds.
+    /// Defaults to the interval selected on the command line.
+    #[serde(default, deserialize_with = "crate::duration::deserialize_option")]
+    pub interval: Option<Duration>,
+
+    /// Interface to send probes from, by name, index or one of its IPv4 addresses. Defaults to the
+    /// interface selected on the command line.
+    #[serde(default)]
+    pub interface: Option<String>,
+
+    /// Kinds of probe to send, each probing the target independently. Defaults to the kind
+    /// selected on the command line.
+    #[serde(default, deserialize_with = "deserialize_probe_types")]
+    pub probes: Vec<ProbeType>,
+
+    /// Free-form label, eg `site=fra1`, attached to every record and summary of the target.
+    #[serde(default)]
+    pub label: Option<String>,
+
+    /// DSCP value (0-63) marked on probes. Defaults to the value selected on the command line.
+    #[serde(default)]
+    pub dscp: Option<u8>,
+
+    /// Name of the group, eg a site, whose statistics are aggregated across its members.
+    #[serde(default)]
+    pub group: Option<String>,
+
+    /// RTT beyond which probes are marked as exceeding it. Defaults to the threshold selected on
+    /// the command line, if any.
+    #[serde(default, deserialize_with = "crate::duration::deserialize_option")]
+    pub rtt_warn: Option<Duration>,
+
+    /// Bytes of data carried by echo requests. Defaults to the size selected on the command line.
+    #[serde(default)]
+    pub payload_size: Option<u16>,
+
+    /// Whether requests set the Don't Fragment flag, eg `true` for a path through a tunnel with
+    /// a smaller MTU, where fragmented requests would otherwise be lost silently. Defaults to the
+    /// choice made on the command line.
+    #[serde(default)]
+    pub dont_fragment: Option<bool>,
+}
+
+impl Target {
+    /// Fill in the count and interval from `defaults` where the row leaves them empty, then check
+    /// that the target's parameters fall within the given `Limits`. Errors about the count or
+    /// interval say whether it came from the row or the default.
+    pub fn resolve(&mut self, defaults: &TargetDefaults, limits: &Limits) -> Result<()> {
+        let addr = self.addr;
+        let (interval, interval_source) = match self.interval {
+            Some(interval) => (interval, "the target row"),
+            None => (defaults.interval, "--default-interval"),
+        };
+        let (count, count_source) = match (self.count, defaults.count) {
+            (Some(count), _) => (count, "the target row"),
+            (None, Some(count)) => (count, "--default-count"),
+            (None, None) => (limits.max_count.unwrap_or(u64::MAX), "--max-count"),
+        };
+
+        let min_interval = limits.min_interval.max(Duration::from_nanos(1));
+        if interval < min_interval {
+            return Err(Error::GenericStringError(format!(
+                "error in target {addr}: interval {interval:?} from {interval_source} is below the minimum of {min_interval:?}",
+            )));
+        }
+        if let Some(max_interval) = limits.max_interval {
+            if interval > max_interval {
+                return Err(Error::GenericStringError(format!(
+                    "error in target {addr}: interval {interval:?} from {interval_source} exceeds the maximum of {max_interval:?}",
+                )));
+            }
+        }
+        if let Some(dscp) = self.dscp {
+            if dscp > MAX_DSCP {
+                return Err(Error::GenericStringError(format!(
+                    "error in target {addr}: dscp {dscp} exceeds the maximum of {MAX_DSCP}",
+                )));
+            }
+        }
+        if let Some(payload_size) = self.payload_size {
+            if payload_size < MIN_PAYLOAD_SIZE {
+                return Err(Error::GenericStringError(format!(
+                    "error in target {addr}: payload size {payload_size} is below the minimum of {MIN_PAYLOAD_SIZE}",
+                )));
+            }
+        }
+        if count < 1 {
+            return Err(Error::GenericStringError(format!(
+                "error in target {addr}: count {count} from {count_source} is below the minimum of 1",
+            )));
+        }
+        if let Some(max_count) = limits.max_count {
+            if count > max_count {
+                return Err(Error::GenericStringError(format!(
+                    "error in target {addr}: count {count} from {count_source} exceeds the maximum of {max_count}",
+                )));
+            }
+        }
+        self.interval = Some(interval);
+        self.count = Some(count);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(count: Option<u64>, interval: Option<Duration>) -> Target {
+        Target {
+            addr: Ipv4Addr::new(192, 0, 2, 1),
+            count,
+            interval,
+            interface: None,
+            probes: Vec::new(),
+            label: None,
+            dscp: None,
+            group: None,
+            rtt_warn: None,
+            payload_size: None,
+            dont_fragment: None,
+        }
+    }
+
+    fn limits() -> Limits {
+        Limits {
+            min_interval: Duration::from_millis(10),
+            max_interval: Some(Duration::from_secs(60)),
+            max_count: Some(1000),
+        }
+    }
+
+    fn defaults() -> TargetDefaults {
+        TargetDefaults {
+            count: Some(10),
+            interval: Duration::from_secs(1),
+        }
+    }
+
+    fn resolve(mut target: Target, limits: &Limits) -> Result<Target> {
+        target.resolve(&defaults(), limits)?;
+        Ok(target)
+    }
+
+    fn error(target: Target, limits: &Limits) -> String {
+        resolve(target, limits).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn defaults_fill_empty_fields() {
+        let resolved = resolve(target(None, None), &limits()).unwrap();
+        assert_eq!(resolved.count, Some(10));
+        assert_eq!(resolved.interval, Some(Duration::from_secs(1)));
+
+        let resolved =
+            resolve(target(Some(3), Some(Duration::from_millis(50))), &limits()).unwrap();
+        assert_eq!(resolved.count, Some(3));
+        assert_eq!(resolved.interval, Some(Duration::from_millis(50)));
+    }
+
+    #[test]
+    fn interval_bounds_are_inclusive() {
+        let limits = limits();
+        assert!(resolve(target(None, Some(limits.min_interval)), &limits).is_ok());
+        assert!(resolve(target(None, Some(Duration::from_secs(60))), &limits).is_ok());
+
+        let below = limits.min_interval - Duration::from_nanos(1);
+        let message = error(target(None, Some(below)), &limits);
+        assert!(
+            message.contains("9.999999ms from the target row"),
+            "{message}"
+        );
+        assert!(message.contains("minimum of 10ms"), "{message}");
+
+        let above = Duration::from_secs(60) + Duration::from_nanos(1);
+        let message = error(target(None, Some(above)), &limits);
+        assert!(message.contains("exceeds the maximum of 60s"), "{message}");
+    }
+
+    #[test]
+    fn zero_interval_is_rejected_without_a_minimum() {
+        let limits = Limits {
+            min_interval: Duration::ZERO,
+            max_interval: None,
+            max_count: None,
+        };
+        assert!(resolve(target(None, Some(Duration::from_nanos(1))), &limits).is_ok());
+        let message = error(target(None, Some(Duration::ZERO)), &limits);
+        assert!(message.contains("below the minimum of 1ns"), "{message}");
+    }
+
+    #[test]
+    fn count_bounds_are_inclusive() {
+        let limits = limits();
+        assert!(resolve(target(Some(1), None), &limits).is_ok());
+        assert!(resolve(target(Some(1000), None), &limits).is_ok());
+
+        let message = error(target(Some(0), None), &limits);
+        assert!(message.contains("count 0 from the target row"), "{message}");
+        let message = error(target(Some(1001), None), &limits);
+        assert!(message.contains("count 1001 from the target row exceeds the maximum of 1000"));
+    }
+
+    #[test]
+    fn errors_name_the_default_they_came_from() {
+        let limits = Limits {
+            max_count: Some(5),
+            ..limits()
+        };
+        let message = error(target(None, None), &limits);
+        assert!(
+            message.contains("count 10 from --default-count"),
+            "{message}"
+        );
+
+        let mut unbounded = target(None, None);
+        let defaults = TargetDefaults {
+            count: None,
+            interval: Duration::from_millis(1),
+        };
+        let message = unbounded
+            .resolve(&defaults, &limits)
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("from --default-interval"), "{message}");
+
+        // with neither a row nor a default count, the target probes up to the maximum
+        let defaults = TargetDefaults {
+            count: None,
+            interval: Duration::from_secs(1),
+        };
+        unbounded.resolve(&defaults, &limits).unwrap();
+        assert_eq!(unbounded.count, Some(5));
+    }
+
+    #[test]
+    fn dscp_and_payload_size_bounds() {
+        let limits = limits();
+        let mut marked = target(None, None);
+        marked.dscp = Some(MAX_DSCP);
+        assert!(resolve(marked.clone(), &limits).is_ok());
+        marked.dscp = Some(MAX_DSCP + 1);
+        assert!(error(marked, &limits).contains("dscp 64 exceeds the maximum of 63"));
+
+        let mut padded = target(None, None);
+        padded.payload_size = Some(MIN_PAYLOAD_SIZE);
+        assert!(resolve(padded.clone(), &limits).is_ok());
+        padded.payload_size = Some(MIN_PAYLOAD_SIZE - 1);
+        assert!(error(padded, &limits).contains("payload size 3 is below the minimum of 4"));
+    }
+}
@@ -0,0 +1,411 @@
+use std::collections::HashMap;
+use std::mem::MaybeUninit;
+use std::os::fd::AsRawFd;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use async_trait::async_trait;
+use io_uring::{cqueue, opcode, squeue, types, IoUring};
+use socket2::SockAddr;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::{oneshot, Mutex};
+
+use crate::ethernet::LinkType;
+use crate::socket::{ipv4_destination, AsyncSocket, ProbeSocket};
+
+/// Number of submission queue entries in each ring.
+const RING_ENTRIES: u32 = 256;
+
+/// Number and size of the buffers the kernel receives packets into.
+const RECV_BUFFERS: u16 = 256;
+const RECV_BUFFER_SIZE: usize = 4096;
+const RECV_BUFFER_GROUP: u16 = 0;
+
+/// Received packets queued for `recv` before further packets are dropped.
+const RECV_QUEUE_CAPACITY: usize = 4096;
+
+/// Multishot receives were added in Linux 6.0.
+const MIN_KERNEL_VERSION: (u32, u32) = (6, 0);
+
+/// `user_data` tags of the ring's own operations; sends are numbered from `FIRST_SEND`.
+const RECV: u64 = 1;
+const PROVIDE_BUFFERS: u64 = 2;
+const CANCEL: u64 = 3;
+const SHUTDOWN: u64 = 4;
+const FIRST_SEND: u64 = 16;
+
+/// A `ProbeSocket` that sends and receives through an io_uring rather than a syscall per packet
+/// on an epoll-driven socket. Sends are submitted to the ring by the caller, while a dedicated
+/// thread reaps completions and keeps a multishot receive armed once the first `recv` is made.
+#[derive(Debug)]
+pub struct UringSocket {
+    /// Owns the socket, and captures sent buffers.
+    socket: AsyncSocket,
+    shared: Arc<Shared>,
+    packets: Mutex<Receiver<std::io::Result<Vec<u8>>>>,
+    reaper: Option<JoinHandle<()>>,
+}
+
+/// State shared between the `UringSocket` and its completion thread.
+struct Shared {
+    ring: IoUring,
+
+    /// Serializes access to the submission queue, which is filled from any thread.
+    submission: std::sync::Mutex<()>,
+    sends: std::sync::Mutex<HashMap<u64, PendingSend>>,
+    next_send: AtomicU64,
+    recv_armed: AtomicBool,
+    fd: types::Fd,
+
+    /// Memory the kernel receives packets into, `RECV_BUFFERS` buffers of `RECV_BUFFER_SIZE`
+    /// bytes each. Allocated along with the ring but only provided to the kernel once receiving.
+    recv_buffers: RecvBuffers,
+}
+
+impl std::fmt::Debug for Shared {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Shared")
+            .field("fd", &self.fd)
+            .field("recv_armed", &self.recv_armed)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A send in flight, whose buffers must outlive the operation even if its caller goes away.
+struct PendingSend {
+    _data: Vec<u8>,
+    _destination: Option<Box<SockAddr>>,
+    done: oneshot::Sender<std::io::Result<usize>>,
+}
+
+struct RecvBuffers(*mut u8);
+
+// the buffers are only written by the kernel and read by the completion thread after the kernel
+// hands one over, and a buffer isn't provided to the kernel again until it has been read
+unsafe impl Send for RecvBuffers {}
+unsafe impl Sync for RecvBuffers {}
+
+impl RecvBuffers {
+    fn new() -> Self {
+        let buffers = vec![0u8; usize::from(RECV_BUFFERS) * RECV_BUFFER_SIZE].into_boxed_slice();
+        Self(Box::into_raw(buffers).cast())
+    }
+
+    /// # Safety
+    ///
+    /// The kernel must have handed buffer `id` over and not been given it back since.
+    unsafe fn get(&self, id: u16, len: usize) -> &[u8] {
+        let start = self.0.add(usize::from(id) * RECV_BUFFER_SIZE);
+        std::slice::from_raw_parts(start, len.min(RECV_BUFFER_SIZE))
+    }
+
+    fn buffer(&self, id: u16) -> *mut u8 {
+        // in bounds for any id below RECV_BUFFERS
+        unsafe { self.0.add(usize::from(id) * RECV_BUFFER_SIZE) }
+    }
+}
+
+impl Drop for RecvBuffers {
+    fn drop(&mut self) {
+        let len = usize::from(RECV_BUFFERS) * RECV_BUFFER_SIZE;
+        // reconstitutes the box leaked in `new`
+        unsafe {
+            drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+                self.0, len,
+            )))
+        }
+    }
+}
+
+impl UringSocket {
+    /// Drive `socket` through a new io_uring, or explain why the kernel can't.
+    pub fn new(socket: AsyncSocket) -> std::io::Result<Self> {
+        check_kernel_version()?;
+        let ring = IoUring::new(RING_ENTRIES)?;
+        let mut probe = io_uring::Probe::new();
+        ring.submitter().register_probe(&mut probe)?;
+        for (name, code) in [
+            ("send", opcode::Send::CODE),
+            ("recv", opcode::RecvMulti::CODE),
+            ("provide buffers", opcode::ProvideBuffers::CODE),
+            ("async cancel", opcode::AsyncCancel::CODE),
+        ] {
+            if !probe.is_supported(code) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    format!("kernel doesn't support io_uring {name} operations"),
+                ));
+            }
+        }
+        let shared = Arc::new(Shared {
+            ring,
+            submission: std::sync::Mutex::new(()),
+            sends: std::sync::Mutex::new(HashMap::new()),
+            next_send: AtomicU64::new(FIRST_SEND),
+            recv_armed: AtomicBool::new(false),
+            fd: types::Fd(socket.socket().as_raw_fd()),
+            recv_buffers: RecvBuffers::new(),
+        });
+        let (packet_sender, packets) = channel(RECV_QUEUE_CAPACITY);
+        let reaper_shared = shared.clone();
+        let reaper = std::thread::Builder::new()
+            .name("uring-reaper".to_string())
+            .spawn(move || reap(&reaper_shared, &packet_sender))?;
+        let uring_socket = Self {
+            socket,
+            shared,
+            packets: Mutex::new(packets),
+            reaper: Some(reaper),
+        };
+        // the ring waits for readiness itself, so operations needn't fail with EAGAIN
+        uring_socket.socket.socket().set_nonblocking(false)?;
+        Ok(uring_socket)
+    }
+}
+
+impl Drop for UringSocket {
+    fn drop(&mut self) {
+        let entry = opcode::Nop::new().build().user_data(SHUTDOWN);
+        if let Err(e) = self.shared.submit(&[entry]) {
+            tracing::warn!("failed to stop io_uring completion thread: {e}");
+            return;
+        }
+        if let Some(reaper) = self.reaper.take() {
+            let _ = reaper.join();
+        }
+    }
+}
+
+#[async_trait]
+impl ProbeSocket for UringSocket {
+    async fn recv(&self, buf: &mut [MaybeUninit<u8>]) -> std::io::Result<usize> {
+        if !self.shared.recv_armed.swap(true, Ordering::AcqRel) {
+            if let Err(e) = self.shared.arm_recv(true) {
+                self.shared.recv_armed.store(false, Ordering::Release);
+                return Err(e);
+            }
+        }
+        let packet = self
+            .packets
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| std::io::Error::other("io_uring completion thread exited"))??;
+        let len = packet.len().min(buf.len());
+        for (dst, src) in buf.iter_mut().zip(&packet[..len]) {
+            dst.write(*src);
+        }
+        Ok(len)
+    }
+
+    async fn send(&self, buf: &[u8]) -> std::io::Result<usize> {
+        let data = buf.to_vec();
+        // raw IPv4 sockets aren't connected, so send each packet to the destination in its header
+        let destination = match self.socket.link_type() {
+            LinkType::Loopback => Some(Box::new(ipv4_destination(buf)?)),
+            _ => None,
+        };
+        let mut entry = opcode::Send::new(self.shared.fd, data.as_ptr(), data.len() as u32);
+        if let Some(destination) = &destination {
+            entry = entry
+                .dest_addr(destination.as_ptr())
+                .dest_addr_len(destination.len());
+        }
+        let id = self.shared.next_send.fetch_add(1, Ordering::Relaxed);
+        let entry = entry.build().user_data(id);
+
+        let (done, result) = oneshot::channel();
+        self.shared.lock_sends().insert(
+            id,
+            PendingSend {
+                _data: data,
+                _destination: destination,
+                done,
+            },
+        );
+        if let Err(e) = self.shared.submit(&[entry]) {
+            self.shared.lock_sends().remove(&id);
+            return Err(e);
+        }
+        let length = result
+            .await
+            .map_err(|_| std::io::Error::other("io_uring completion thread exited"))??;
+        self.socket.record_sent(&buf[..length]);
+        Ok(length)
+    }
+}
+
+impl Shared {
+    /// Push `entries` onto the submission queue and submit them.
+    fn submit(&self, entries: &[squeue::Entry]) -> std::io::Result<()> {
+        let _guard = self
+            .submission
+            .lock()
+            .map_err(|_| std::io::Error::other("io_uring submission queue poisoned"))?;
+        // safe because the submission lock gives us exclusive use of the queue, and every entry's
+        // buffers outlive its operation
+        let mut queue = unsafe { self.ring.submission_shared() };
+        for entry in entries {
+            while unsafe { queue.push(entry) }.is_err() {
+                queue.sync();
+                self.ring.submit()?;
+                queue.sync();
+            }
+        }
+        queue.sync();
+        drop(queue);
+        self.ring.submit()?;
+        Ok(())
+    }
+
+    /// Start a multishot receive, first handing the kernel every receive buffer if `provide`.
+    fn arm_recv(&self, provide: bool) -> std::io::Result<()> {
+        let recv = opcode::RecvMulti::new(self.fd, RECV_BUFFER_GROUP)
+            .build()
+            .user_data(RECV);
+        if provide {
+            let buffers = opcode::ProvideBuffers::new(
+                self.recv_buffers.buffer(0),
+                RECV_BUFFER_SIZE as i32,
+                RECV_BUFFERS,
+                RECV_BUFFER_GROUP,
+                0,
+            )
+            .build()
+            .user_data(PROVIDE_BUFFERS)
+            .flags(squeue::Flags::IO_LINK);
+            self.submit(&[buffers, recv])
+        } else {
+            self.submit(&[recv])
+        }
+    }
+
+    /// Hand a single receive buffer back to the kernel.
+    fn provide_buffer(&self, id: u16) -> std::io::Result<()> {
+        let entry = opcode::ProvideBuffers::new(
+            self.recv_buffers.buffer(id),
+            RECV_BUFFER_SIZE as i32,
+            1,
+            RECV_BUFFER_GROUP,
+            id,
+        )
+        .build()
+        .user_data(PROVIDE_BUFFERS);
+        self.submit(&[entry])
+    }
+
+    fn lock_sends(&self) -> std::sync::MutexGuard<'_, HashMap<u64, PendingSend>> {
+        // a panic while holding the lock can't leave the map inconsistent
+        self.sends.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Reap completions until the socket is dropped, completing sends and queueing received packets.
+fn reap(shared: &Shared, packets: &Sender<std::io::Result<Vec<u8>>>) {
+    let mut shutting_down = false;
+    loop {
+        match shared.ring.submit_and_wait(1) {
+            Ok(_) => (),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                tracing::error!("io_uring wait failed: {e}");
+                return;
+            }
+        }
+        // safe because this is the only thread that consumes completions
+        let completions: Vec<cqueue::Entry> = unsafe { shared.ring.completion_shared() }.collect();
+        for cqe in completions {
+            let result = cqe.result();
+            match cqe.user_data() {
+                RECV => {
+                    if let Some(id) = cqueue::buffer_select(cqe.flags()) {
+                        if result > 0 {
+                            // safe because the kernel just handed the buffer over
+                            let packet = unsafe { shared.recv_buffers.get(id, result as usize) };
+                            if packets.try_send(Ok(packet.to_vec())).is_err() {
+                                tracing::debug!("dropping received packet, receive queue is full");
+                            }
+                        }
+                        if let Err(e) = shared.provide_buffer(id) {
+                            tracing::warn!("failed to return io_uring receive buffer: {e}");
+                        }
+                    }
+                    if cqueue::more(cqe.flags()) {
+                        continue;
+                    }
+                    // the multishot receive has ended; this is expected once cancelled, and when
+                    // the kernel ran out of buffers it can simply be restarted
+                    if shutting_down {
+                        return;
+                    }
+                    if result < 0 && -result != libc::ENOBUFS {
+                        let e = std::io::Error::from_raw_os_error(-result);
+                        let _ = packets.try_send(Err(e));
+                    } else if let Err(e) = shared.arm_recv(false) {
+                        let _ = packets.try_send(Err(e));
+                    }
+                }
+                PROVIDE_BUFFERS | CANCEL => {
+                    if result < 0 && -result != libc::ENOENT {
+                        let e = std::io::Error::from_raw_os_error(-result);
+                        tracing::warn!("io_uring operation {} failed: {e}", cqe.user_data());
+                    }
+                }
+                SHUTDOWN => {
+                    if !shared.recv_armed.load(Ordering::Acquire) {
+                        return;
+                    }
+                    // wait for the receive to end so that the kernel is done with its buffers
+                    // before they are freed
+                    shutting_down = true;
+                    let cancel = opcode::AsyncCancel::new(RECV).build().user_data(CANCEL);
+                    if let Err(e) = shared.submit(&[cancel]) {
+                        tracing::warn!("failed to cancel io_uring receive: {e}");
+                        return;
+                    }
+                }
+                id => {
+                    if let Some(send) = shared.lock_sends().remove(&id) {
+                        let outcome = if result < 0 {
+                            Err(std::io::Error::from_raw_os_error(-result))
+                        } else {
+                            Ok(result as usize)
+                        };
+                        let _ = send.done.send(outcome);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Fail unless the running kernel supports multishot receives.
+fn check_kernel_version() -> std::io::Result<()> {
+    // safe because zeroes are valid for a utsname, which uname fills in
+    let mut uts: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut uts) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let release: String = uts
+        .release
+        .iter()
+        .take_while(|c| **c != 0)
+        .map(|c| *c as u8 as char)
+        .collect();
+    let mut parts = release
+        .split(|c: char| !c.is_ascii_digit())
+        .map(|part| part.parse::<u32>().unwrap_or(0));
+    let version = (parts.next().unwrap_or(0), parts.next().unwrap_or(0));
+    if version < MIN_KERNEL_VERSION {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!(
+                "kernel {release} predates multishot receives (Linux {}.{})",
+                MIN_KERNEL_VERSION.0, MIN_KERNEL_VERSION.1
+            ),
+        ));
+    }
+    Ok(())
+}